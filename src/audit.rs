@@ -0,0 +1,261 @@
+use crate::{
+    concurrency::map_bounded_concurrent,
+    dynamodb::{list_registered_feeds, FeedRecord},
+    error::BotError,
+    feed::{extract_feed_entries, get_feed, FeedFetchOptions, ParseStatus},
+    http_client, OpaqueError,
+};
+
+const AUDIT_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditErrorCategory {
+    RedirectLoop,
+    Blocked,
+    RateLimited,
+    Unauthorized,
+    HttpStatus,
+    Network,
+    Parse,
+    Other,
+}
+
+impl AuditErrorCategory {
+    fn label(&self) -> &'static str {
+        match self {
+            AuditErrorCategory::RedirectLoop => "redirect loop",
+            AuditErrorCategory::Blocked => "blocked",
+            AuditErrorCategory::RateLimited => "rate limited",
+            AuditErrorCategory::Unauthorized => "unauthorized",
+            AuditErrorCategory::HttpStatus => "http error",
+            AuditErrorCategory::Network => "network",
+            AuditErrorCategory::Parse => "parse",
+            AuditErrorCategory::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuditStatus {
+    Ok {
+        entry_count: usize,
+        // 壊れたフィードの途中までしか読み取れなかった
+        partial: bool,
+    },
+    Failed {
+        category: AuditErrorCategory,
+        message: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FeedAuditResult {
+    pub url: String,
+    pub status: AuditStatus,
+}
+
+fn classify_audit_error(err: &OpaqueError) -> AuditErrorCategory {
+    if let Some(bot_error) = err.downcast_ref::<BotError>() {
+        return match bot_error {
+            BotError::RedirectLoop { .. } => AuditErrorCategory::RedirectLoop,
+            BotError::Blocked { .. } => AuditErrorCategory::Blocked,
+            BotError::FeedRateLimited { .. } => AuditErrorCategory::RateLimited,
+            BotError::FeedUnauthorized { .. } => AuditErrorCategory::Unauthorized,
+            BotError::FeedHttpStatus { .. } => AuditErrorCategory::HttpStatus,
+            _ => AuditErrorCategory::Other,
+        };
+    }
+    if err.downcast_ref::<reqwest::Error>().is_some() {
+        return AuditErrorCategory::Network;
+    }
+    if err
+        .downcast_ref::<feed_rs::parser::ParseFeedError>()
+        .is_some()
+    {
+        return AuditErrorCategory::Parse;
+    }
+    AuditErrorCategory::Other
+}
+
+// 投稿はせず、登録されているフィードを取得して解析できるかだけを確認する
+pub async fn audit_feeds(
+    reqwest_client: &reqwest::Client,
+    feed_records: &[FeedRecord],
+) -> Result<Vec<FeedAuditResult>, OpaqueError> {
+    let feeds = feed_records
+        .iter()
+        .map(|feed_record| {
+            let options = FeedFetchOptions {
+                use_cookies: feed_record.use_cookies,
+                ..Default::default()
+            };
+            (feed_record.url.clone(), options)
+        })
+        .collect::<Vec<_>>();
+    let urls = feeds.iter().map(|(url, _)| url.clone()).collect::<Vec<_>>();
+    let results = map_bounded_concurrent(feeds, AUDIT_CONCURRENCY, |(url, options)| {
+        let reqwest_client = reqwest_client.clone();
+        async move {
+            let status = match get_feed(&reqwest_client, &url, &options).await {
+                Ok(fetched_feed) => AuditStatus::Ok {
+                    entry_count: extract_feed_entries(&fetched_feed.feed).len(),
+                    partial: matches!(fetched_feed.parse_status, ParseStatus::Partial { .. }),
+                },
+                Err(err) => AuditStatus::Failed {
+                    category: classify_audit_error(&err),
+                    message: err.to_string(),
+                },
+            };
+            FeedAuditResult { url, status }
+        }
+    })
+    .await?;
+    Ok(urls
+        .into_iter()
+        .zip(results)
+        .map(|(url, result)| {
+            result.unwrap_or_else(|err| FeedAuditResult {
+                url,
+                status: AuditStatus::Failed {
+                    category: AuditErrorCategory::Other,
+                    message: err.to_string(),
+                },
+            })
+        })
+        .collect())
+}
+
+pub fn format_audit_report(results: &[FeedAuditResult]) -> String {
+    let mut report = String::new();
+    let mut failed_count = 0;
+    for result in results {
+        let line = match &result.status {
+            AuditStatus::Ok {
+                entry_count,
+                partial: false,
+            } => format!("OK      {} ({} entries)", result.url, entry_count),
+            AuditStatus::Ok {
+                entry_count,
+                partial: true,
+            } => format!("PARTIAL {} ({} entries recovered)", result.url, entry_count),
+            AuditStatus::Failed { category, message } => {
+                failed_count += 1;
+                format!("FAILED  {} [{}] {}", result.url, category.label(), message)
+            }
+        };
+        report.push_str(&line);
+        report.push('\n');
+    }
+    report.push_str(&format!(
+        "{} feeds audited, {} failed\n",
+        results.len(),
+        failed_count
+    ));
+    report
+}
+
+pub async fn run_audit(dynamodb_client: &aws_sdk_dynamodb::Client) -> Result<(), OpaqueError> {
+    let feed_records = list_registered_feeds(dynamodb_client).await?;
+    let results = audit_feeds(&http_client::build_client()?, &feed_records).await?;
+    print!("{}", format_audit_report(&results));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn test_audit_feeds() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/good.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Good</title>
+<item><title>Entry 1</title><link>https://example.com/1</link></item>
+<item><title>Entry 2</title><link>https://example.com/2</link></item>
+</channel></rss>"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/broken.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not a feed"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/gone.xml"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("<html>Not Found</html>"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/private.xml"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/busy.xml"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "120"))
+            .mount(&server)
+            .await;
+        let feed_records = ["good", "broken", "gone", "private", "busy"]
+            .iter()
+            .map(|name| FeedRecord {
+                url: format!("{}/{}.xml", server.uri(), name),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let results = audit_feeds(&http_client::build_client().unwrap(), &feed_records)
+            .await
+            .unwrap();
+        // 結果は登録されている順に並ぶ
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.url.as_str())
+                .collect::<Vec<_>>(),
+            feed_records
+                .iter()
+                .map(|feed_record| feed_record.url.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            results[0].status,
+            AuditStatus::Ok {
+                entry_count: 2,
+                partial: false
+            }
+        );
+        let categories = results[1..]
+            .iter()
+            .map(|result| match &result.status {
+                AuditStatus::Failed { category, .. } => Some(*category),
+                AuditStatus::Ok { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            categories,
+            vec![
+                Some(AuditErrorCategory::Parse),
+                Some(AuditErrorCategory::HttpStatus),
+                Some(AuditErrorCategory::Unauthorized),
+                Some(AuditErrorCategory::RateLimited),
+            ]
+        );
+
+        let report = format_audit_report(&results);
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            format!("OK      {}/good.xml (2 entries)", server.uri())
+        );
+        assert!(lines[2].starts_with(&format!("FAILED  {}/gone.xml [http error]", server.uri())));
+        assert_eq!(lines[5], "5 feeds audited, 4 failed");
+    }
+}
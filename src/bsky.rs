@@ -1,13 +1,31 @@
 use std::{env, io::Cursor};
 
 use bytes::Bytes;
-use chrono::{SecondsFormat, Utc};
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
 use feed_rs::model::Feed;
 use reqwest::header::{self, HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
 use crate::{
+    config::{env_flag, env_parse},
+    dynamodb::FeedRecord,
+    error::BotError,
     feed::{FeedEntry, OGPInfo},
+    http_client,
+    lang::{
+        detect_langs, langs_from_categories, parse_langs, LangDetectionThresholds,
+        DEFAULT_MIN_DETECTION_CONFIDENCE, DEFAULT_MIN_DETECTION_GRAPHEMES,
+    },
+    richtext::{
+        append_footer, append_suffix, detect_facets, truncate_preserving_trailing_link, ByteSlice,
+        Facet, FacetFeature, MAX_TAG_BYTES, MAX_TAG_GRAPHEMES,
+    },
+    session_cache::{decide_session_action, CachedSession, SessionAction, SessionStore},
+    text::{
+        apply_text_case, decode_html_entities, grapheme_len, strip_emoji, truncate_graphemes,
+        MAX_POST_GRAPHEMES,
+    },
     OpaqueError,
 };
 
@@ -25,6 +43,22 @@ struct Session {
     refresh_jwt: String,
     handle: String,
     did: String,
+    #[serde(default)]
+    did_doc: Option<DidDocument>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DidDocument {
+    #[serde(default)]
+    service: Vec<DidService>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DidService {
+    id: String,
+    // 文字列以外の形式で書かれていることもある
+    service_endpoint: serde_json::Value,
 }
 
 #[derive(Serialize, Debug)]
@@ -35,17 +69,46 @@ pub struct CreateRecordRequest {
     record: Record,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DeleteRecordRequest {
+    repo: String,
+    collection: String,
+    rkey: String,
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Record {
     #[serde(rename = "$type")]
     r#type: String,
     text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    facets: Vec<Facet>,
     #[serde(skip_serializing_if = "Option::is_none")]
     embed: Option<Embed>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    langs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply: Option<ReplyRef>,
+    // 本文には表示されないタグ。ボットの投稿を見分ける目印に使う
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
     created_at: String,
 }
 
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct StrongRef {
+    uri: String,
+    cid: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct ReplyRef {
+    root: StrongRef,
+    parent: StrongRef,
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct Embed {
@@ -92,46 +155,297 @@ pub struct CreateRecordResponse {
 #[serde(rename_all = "camelCase")]
 pub struct UploadBlobResponse {
     blob: Blob,
+    // 送った画像の大きさと形式。応答と照らし合わせて検証するために使う
+    #[serde(skip)]
+    uploaded_size: u64,
+    #[serde(skip)]
+    uploaded_mime_type: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListRecordsResponse {
+    records: Vec<ListedRecord>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListedRecord {
+    value: ListedPost,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListedPost {
+    embed: Option<ListedEmbed>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListedEmbed {
+    external: Option<ListedEmbedExternal>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListedEmbedExternal {
+    uri: String,
+}
+
+const BSKY_SERVICE_URL: &str = "https://bsky.social";
+
+const PLC_DIRECTORY_URL: &str = "https://plc.directory";
+
+// コメントページへのリンクを付ける文言
+const DISCUSSION_LINK_LABEL: &str = "💬 Discussion";
+
+const DEFAULT_MAX_EMBED_DESCRIPTION_GRAPHEMES: usize = 300;
+
+// 長すぎる埋め込みカードはcreateRecordで拒否されるため、設定に関わらずこの長さに収める
+const MAX_EMBED_TITLE_GRAPHEMES: usize = 300;
+const MAX_EMBED_DESCRIPTION_GRAPHEMES: usize = 1000;
+
+const DEFAULT_TITLE_SEPARATOR: &str = " | ";
+
+// テストモードで投稿しなかった場合に返す投稿のrkey
+const TEST_MODE_RKEY: &str = "test-mode";
+
+// 外部埋め込みのサムネイルの上限
+pub const DEFAULT_MAX_THUMB_BYTES: u64 = 1000000;
+
+// 上限に収まるまで順に品質を下げて圧縮し直す
+const THUMB_JPEG_QUALITIES: [u8; 4] = [100, 85, 70, 50];
+// どの品質でも収まらない場合は、この大きさまで縦横を3/4ずつ縮める
+const THUMB_MAX_DIMENSION: u32 = 1000;
+const THUMB_MIN_DIMENSION: u32 = 200;
+
+// 埋め込みカードを作れなかった場合の投稿の仕方
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum EmbedFallback {
+    #[default]
+    TextOnly,
+    // URLを本文に含めてリンクにしておく
+    AppendUrl,
+}
+
+// 設定しない場合、OGPがなければ本文だけを投稿する
+fn parse_embed_fallback(value: &str) -> Result<EmbedFallback, OpaqueError> {
+    match value.trim() {
+        "" | "text_only" => Ok(EmbedFallback::TextOnly),
+        "append_url" => Ok(EmbedFallback::AppendUrl),
+        _ => Err(format!("invalid EMBED_FALLBACK: {}", value).into()),
+    }
 }
 
 pub struct BskyClient {
     reqwest_client: reqwest::Client,
+    // ログインに使った識別子。保存したセッションが同じアカウントのものか確かめるために使う
+    identifier: String,
+    // アカウントのDIDドキュメントに書かれているPDS
+    service_url: String,
     session: Session,
+    // 保存したものから作り直すか更新したセッション
+    session_updated: bool,
+    created_at_offset: Option<FixedOffset>,
+    prefer_entry_title_over_site_title: bool,
+    // エントリーのタイトルとフィードのタイトルの区切り
+    title_separator: String,
+    // エントリーのタイトルがこれより長い場合はOGPの説明文か切り詰めたタイトルを使う(0の場合は制限しない)
+    max_title_graphemes: usize,
+    post_footer: Option<String>,
+    // 本文中のURLをリンクにする最大数(0の場合はリンクにしない)
+    max_link_facets: usize,
+    // 埋め込みカードの説明文の最大長(grapheme数)
+    max_embed_description_graphemes: usize,
+    // uploadBlobの応答が送った画像の大きさと形式に一致するか確認してから投稿する
+    verify_uploaded_blob: bool,
+    embed_fallback: EmbedFallback,
+    // 言語判定の結果を使う最小の文字数と信頼度
+    lang_detection_thresholds: LangDetectionThresholds,
+    // フィードにもフィードごとの設定にも言語がない場合に使う言語
+    default_langs: Vec<String>,
+    // すべての投稿のtagsに付け、過去の投稿からボットの投稿だけを取り出すために使う
+    bot_marker_tag: Option<String>,
+    // ビルドの設定に関わらず明示的に有効にした場合だけ、本文に"[test]"を付け、createRecordを呼ばずにログに出す
+    test_mode: bool,
 }
 
 impl BskyClient {
-    pub async fn new() -> Result<Self, OpaqueError> {
-        let reqwest_client = reqwest::Client::new();
-        let request = CreateSessionRequest {
-            identifier: env::var("BSKY_IDENTIFIER")?,
-            password: env::var("BSKY_PASSWORD")?,
+    // 保存したセッションが使える間はcreateSessionを呼ばずに使い回す
+    // フィードの取得と同じ設定のクライアントを受け取り、接続も使い回す
+    pub async fn new(
+        session_store: &impl SessionStore,
+        reqwest_client: reqwest::Client,
+    ) -> Result<Self, OpaqueError> {
+        let identifier = env::var("BSKY_IDENTIFIER")?;
+        // 保存先を読めなくてもログインし直せば投稿できる
+        let cached_session = match session_store.get_cached_session().await {
+            Ok(cached_session) => {
+                cached_session.filter(|cached_session| cached_session.identifier == identifier)
+            }
+            Err(err) => {
+                warn!(error = ?err, "Failed to load cached session");
+                None
+            }
+        };
+        let mut client = match (
+            decide_session_action(cached_session.as_ref(), Utc::now()),
+            cached_session,
+        ) {
+            (SessionAction::Reuse, Some(cached_session)) => {
+                Self::from_cached_session(cached_session, reqwest_client)?
+            }
+            (SessionAction::Refresh, Some(cached_session)) => {
+                let mut client = Self::from_cached_session(cached_session, reqwest_client.clone())?;
+                match client.refresh_session().await {
+                    Ok(()) => client,
+                    // 異常終了した実行が更新したトークンを保存できなかった場合などはログインし直す
+                    Err(err) => {
+                        warn!(error = ?err, "Failed to refresh cached session, creating a new one");
+                        Self::login(identifier, reqwest_client).await?
+                    }
+                }
+            }
+            _ => Self::login(identifier, reqwest_client).await?,
+        };
+        client.save_session_if_updated(session_store).await;
+        Ok(client)
+    }
+
+    async fn login(
+        identifier: String,
+        reqwest_client: reqwest::Client,
+    ) -> Result<Self, OpaqueError> {
+        let pds_host = pds_host_from_env();
+        let session = create_session(
+            &reqwest_client,
+            &pds_host,
+            &identifier,
+            &env::var("BSKY_PASSWORD")?,
+        )
+        .await?;
+        let service_url =
+            resolve_pds_url(&reqwest_client, &session, PLC_DIRECTORY_URL, &pds_host).await;
+        let mut client = Self::with_session(reqwest_client, identifier, service_url, session)?;
+        client.session_updated = true;
+        Ok(client)
+    }
+
+    pub fn from_cached_session(
+        cached_session: CachedSession,
+        reqwest_client: reqwest::Client,
+    ) -> Result<Self, OpaqueError> {
+        let session = Session {
+            access_jwt: cached_session.access_jwt,
+            refresh_jwt: cached_session.refresh_jwt,
+            handle: cached_session.handle,
+            did: cached_session.did,
+            did_doc: None,
+        };
+        Self::with_session(
+            reqwest_client,
+            cached_session.identifier,
+            cached_session.service_url,
+            session,
+        )
+    }
+
+    fn with_session(
+        reqwest_client: reqwest::Client,
+        identifier: String,
+        service_url: String,
+        session: Session,
+    ) -> Result<Self, OpaqueError> {
+        // 認証情報の取り違えで別のアカウントに投稿しないよう確認する
+        check_expected_account(
+            &session,
+            env::var("BSKY_EXPECTED_HANDLE")
+                .ok()
+                .filter(|expected| !expected.is_empty())
+                .as_deref(),
+        )?;
+        let created_at_offset = match env::var("BSKY_CREATED_AT_OFFSET") {
+            Ok(offset) => parse_created_at_offset(&offset)?,
+            Err(_) => None,
         };
-        let mut headers = HeaderMap::new();
-        headers.append(
-            header::CONTENT_TYPE,
-            HeaderValue::from_static("application/json"),
-        );
-        headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
-        let response = reqwest_client
-            .post("https://bsky.social/xrpc/com.atproto.server.createSession")
-            .headers(headers)
-            .body(serde_json::to_string(&request)?)
-            .send()
-            .await?
-            .error_for_status()?;
-        let session: Session = response.json().await?;
         Ok(Self {
             reqwest_client,
+            identifier,
+            service_url,
             session,
+            session_updated: false,
+            created_at_offset,
+            prefer_entry_title_over_site_title: env_flag(
+                "PREFER_ENTRY_TITLE_OVER_SITE_TITLE",
+                true,
+            ),
+            title_separator: env::var("TITLE_SEPARATOR")
+                .map(|separator| parse_title_separator(&separator))
+                .unwrap_or_else(|_| DEFAULT_TITLE_SEPARATOR.to_string()),
+            max_title_graphemes: env_parse("MAX_TITLE_GRAPHEMES", 0)?,
+            post_footer: env::var("POST_FOOTER")
+                .ok()
+                .filter(|footer| !footer.is_empty()),
+            max_link_facets: env_parse("MAX_LINK_FACETS", 0)?,
+            max_embed_description_graphemes: env_parse(
+                "MAX_EMBED_DESCRIPTION_GRAPHEMES",
+                DEFAULT_MAX_EMBED_DESCRIPTION_GRAPHEMES,
+            )?,
+            verify_uploaded_blob: env_flag("VERIFY_UPLOADED_BLOB", false),
+            embed_fallback: parse_embed_fallback(&env::var("EMBED_FALLBACK").unwrap_or_default())?,
+            lang_detection_thresholds: LangDetectionThresholds {
+                min_graphemes: env_parse(
+                    "LANG_DETECTION_MIN_GRAPHEMES",
+                    DEFAULT_MIN_DETECTION_GRAPHEMES,
+                )?,
+                min_confidence: env_parse(
+                    "LANG_DETECTION_MIN_CONFIDENCE",
+                    DEFAULT_MIN_DETECTION_CONFIDENCE,
+                )?,
+            },
+            default_langs: parse_langs(&env::var("DEFAULT_LANGS").unwrap_or_default()),
+            bot_marker_tag: parse_bot_marker_tag(&env::var("BOT_MARKER_TAG").unwrap_or_default())?,
+            test_mode: env_flag("BSKY_TEST_MODE", false),
         })
     }
 
+    pub fn cached_session(&self) -> CachedSession {
+        CachedSession {
+            identifier: self.identifier.clone(),
+            access_jwt: self.session.access_jwt.clone(),
+            refresh_jwt: self.session.refresh_jwt.clone(),
+            handle: self.session.handle.clone(),
+            did: self.session.did.clone(),
+            service_url: self.service_url.clone(),
+        }
+    }
+
+    // リフレッシュトークンは使い捨てのため、更新した場合は次の実行で使えるよう保存する
+    pub async fn save_session_if_updated(&mut self, session_store: &impl SessionStore) {
+        if !self.session_updated {
+            return;
+        }
+        match session_store
+            .put_cached_session(&self.cached_session())
+            .await
+        {
+            Ok(()) => self.session_updated = false,
+            Err(err) => warn!(error = ?err, "Failed to save session"),
+        }
+    }
+
+    fn xrpc_url(&self, method: &str) -> String {
+        format!("{}/xrpc/{}", self.service_url, method)
+    }
+
     pub async fn refresh_session(&mut self) -> Result<(), OpaqueError> {
         let mut headers = HeaderMap::new();
         headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
         let response = self
             .reqwest_client
-            .post("https://bsky.social/xrpc/com.atproto.server.refreshSession")
+            .post(self.xrpc_url("com.atproto.server.refreshSession"))
             .bearer_auth(&self.session.refresh_jwt)
             .headers(headers)
             .send()
@@ -139,6 +453,7 @@ impl BskyClient {
             .error_for_status()?;
         let session: Session = response.json().await?;
         self.session = session;
+        self.session_updated = true;
         Ok(())
     }
 
@@ -152,48 +467,143 @@ impl BskyClient {
             .await?;
         if response.status() == 401 {
             self.refresh_session().await?;
-            response = self
-                .reqwest_client
-                .execute(request)
-                .await?
-                .error_for_status()?;
-        } else {
-            response = response.error_for_status()?;
+            response = self.reqwest_client.execute(request).await?;
         }
-        Ok(response)
+        check_xrpc_response(response).await
     }
 
-    async fn upload_blob(&mut self, body: Bytes) -> Result<UploadBlobResponse, OpaqueError> {
+    async fn upload_blob(
+        &mut self,
+        body: Bytes,
+        content_type: &str,
+    ) -> Result<UploadBlobResponse, OpaqueError> {
         let mut headers = HeaderMap::new();
-        headers.append(header::CONTENT_TYPE, HeaderValue::from_static("*/*"));
+        headers.append(header::CONTENT_TYPE, HeaderValue::from_str(content_type)?);
         headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let uploaded_size = body.len() as u64;
         let request = self
             .reqwest_client
-            .post("https://bsky.social/xrpc/com.atproto.repo.uploadBlob")
+            .post(self.xrpc_url("com.atproto.repo.uploadBlob"))
             .bearer_auth(&self.session.access_jwt)
             .headers(headers)
             .body(body)
             .build()?;
         let response = self.execute_request_with_refresh_session(request).await?;
         let response_body: UploadBlobResponse = response.json().await?;
-        Ok(response_body)
+        Ok(UploadBlobResponse {
+            uploaded_size,
+            uploaded_mime_type: content_type.to_string(),
+            ..response_body
+        })
+    }
+
+    pub async fn upload_thumbnail(
+        &mut self,
+        image_bytes: Bytes,
+        content_type: &str,
+    ) -> Result<UploadBlobResponse, OpaqueError> {
+        let content_type = detect_image_content_type(&image_bytes, content_type);
+        self.upload_blob(image_bytes, &content_type).await
+    }
+
+    // PDSに受け付けられなかった画像をJPEGに変換し直してアップロードする
+    pub async fn upload_thumbnail_as_jpeg(
+        &mut self,
+        image_bytes: Bytes,
+        max_thumb_bytes: u64,
+    ) -> Result<UploadBlobResponse, OpaqueError> {
+        let jpeg_image_bytes = resize_thumbnail(&image_bytes, max_thumb_bytes)?;
+        self.upload_blob(jpeg_image_bytes, "image/jpeg").await
     }
 
     pub async fn upload_thumbnail_with_resizing(
         &mut self,
         image_bytes: Bytes,
+        content_type: &str,
+        max_thumb_bytes: u64,
     ) -> Result<UploadBlobResponse, OpaqueError> {
-        let resized_image_bytes = match resize_thumbnail(&image_bytes) {
-            Ok(resized_image_bytes) => resized_image_bytes,
-            Err(_) => image_bytes,
+        let err = match resize_thumbnail(&image_bytes, max_thumb_bytes) {
+            Ok(resized_image_bytes) => {
+                return self.upload_blob(resized_image_bytes, "image/jpeg").await
+            }
+            Err(err) => err,
         };
-        self.upload_blob(resized_image_bytes).await
+        // 変換できなかった場合、デコードできないWebPやAVIFはPDSに拒否されることがあるため、JPEGとPNGだけ元の形式のままアップロードする
+        let content_type = detect_image_content_type(&image_bytes, content_type);
+        if !UNTRANSCODED_THUMB_CONTENT_TYPES.contains(&content_type.as_str())
+            || image_bytes.len() as u64 > max_thumb_bytes
+        {
+            warn!(content_type, error = ?err, "Failed to transcode thumbnail to JPEG");
+            return Err(err);
+        }
+        self.upload_blob(image_bytes, &content_type).await
+    }
+
+    // 検証に失敗した場合はサムネイルなしで投稿する
+    // 投稿から参照されるまでのblobはgetBlobで取得できないため、uploadBlobの応答だけで確かめる
+    pub fn verify_uploaded_blob(&self, upload_blob_response: &UploadBlobResponse) -> bool {
+        let blob = &upload_blob_response.blob;
+        if !is_valid_blob(blob) {
+            warn!(blob = ?blob, "Uploaded blob has an invalid shape");
+            return false;
+        }
+        if !self.verify_uploaded_blob {
+            return true;
+        }
+        if blob.size != upload_blob_response.uploaded_size
+            || blob.mime_type != upload_blob_response.uploaded_mime_type
+        {
+            warn!(
+                blob = ?blob,
+                uploaded_size = upload_blob_response.uploaded_size,
+                uploaded_mime_type = %upload_blob_response.uploaded_mime_type,
+                "Uploaded blob does not match the uploaded image"
+            );
+            return false;
+        }
+        true
+    }
+
+    // アカウントの直近の投稿から外部埋め込みのURLを新しい順に取得する
+    pub async fn list_posted_embed_urls(&mut self, limit: u32) -> Result<Vec<String>, OpaqueError> {
+        let mut headers = HeaderMap::new();
+        headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let request = self
+            .reqwest_client
+            .get(self.xrpc_url("com.atproto.repo.listRecords"))
+            .bearer_auth(&self.session.access_jwt)
+            .headers(headers)
+            .query(&[
+                ("repo", self.session.did.as_str()),
+                ("collection", "app.bsky.feed.post"),
+                ("limit", &limit.to_string()),
+            ])
+            .build()?;
+        let response = self.execute_request_with_refresh_session(request).await?;
+        let response_body: ListRecordsResponse = response.json().await?;
+        Ok(extract_embed_urls(
+            response_body,
+            self.bot_marker_tag.as_deref(),
+        ))
     }
 
     pub async fn create_record(
         &mut self,
         request: CreateRecordRequest,
     ) -> Result<CreateRecordResponse, OpaqueError> {
+        if self.test_mode {
+            info!(
+                request = %serde_json::to_string(&request)?,
+                "Test mode, skipped createRecord"
+            );
+            return Ok(CreateRecordResponse {
+                uri: format!(
+                    "at://{}/app.bsky.feed.post/{}",
+                    self.session.did, TEST_MODE_RKEY
+                ),
+                cid: String::new(),
+            });
+        }
         let mut headers = HeaderMap::new();
         headers.append(
             header::CONTENT_TYPE,
@@ -202,36 +612,193 @@ impl BskyClient {
         headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
         let request = self
             .reqwest_client
-            .post("https://bsky.social/xrpc/com.atproto.repo.createRecord")
+            .post(self.xrpc_url("com.atproto.repo.createRecord"))
             .bearer_auth(&self.session.access_jwt)
             .headers(headers)
             .body(serde_json::to_string(&request)?)
             .build()?;
         let response = self.execute_request_with_refresh_session(request).await?;
         let response_body: CreateRecordResponse = response.json().await?;
+        debug!(uri = %response_body.uri, cid = %response_body.cid, "Created record");
         Ok(response_body)
     }
 
+    // 投稿のat://URIを指定して削除する
+    pub async fn delete_record(&mut self, uri: &str) -> Result<(), OpaqueError> {
+        let (repo, collection, rkey) =
+            parse_at_uri(uri).ok_or(format!("invalid record uri: {}", uri))?;
+        let request = DeleteRecordRequest {
+            repo: repo.to_string(),
+            collection: collection.to_string(),
+            rkey: rkey.to_string(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let request = self
+            .reqwest_client
+            .post(self.xrpc_url("com.atproto.repo.deleteRecord"))
+            .bearer_auth(&self.session.access_jwt)
+            .headers(headers)
+            .body(serde_json::to_string(&request)?)
+            .build()?;
+        self.execute_request_with_refresh_session(request).await?;
+        Ok(())
+    }
+
     pub async fn format_create_record_request_from_feed_entry(
         &self,
+        feed_record: &FeedRecord,
         feed: &Feed,
         feed_entry: FeedEntry,
         ogp_info: Option<OGPInfo>,
         upload_blob_response: Option<UploadBlobResponse>,
     ) -> CreateRecordRequest {
-        let mut title = match &feed_entry.title {
-            Some(entry_title) => match &feed.title {
-                Some(feed_title) => format!("{} | {}", entry_title, feed_title.content),
+        let feed_title = feed
+            .title
+            .as_ref()
+            .map(|title| decode_html_entities(&title.content))
+            .or(feed_record.display_name.clone())
+            .map(|feed_title| strip_emoji_from_title(&feed_title, feed_record.strip_emoji));
+        // フィードで宣言された言語が誤っている場合に備え、フィードごとに設定された既定の言語を優先する
+        let feed_lang = feed_record.default_lang.as_deref().or(feed
+            .language
+            .as_deref()
+            .map(str::trim)
+            .filter(|lang| !lang.is_empty()));
+        // 絵文字と大文字・小文字の変換はファセットのオフセットを計算する前に行う
+        // サイト名は固有名詞のため、変換はエントリーのタイトルにだけ行う
+        let entry_title = feed_entry.title.as_deref().map(|entry_title| {
+            shorten_entry_title(
+                &apply_text_case(
+                    &strip_emoji_from_title(entry_title, feed_record.strip_emoji),
+                    feed_record.text_case,
+                    feed_lang,
+                ),
+                ogp_info
+                    .as_ref()
+                    .and_then(|ogp_info| ogp_info.description.as_deref()),
+                self.max_title_graphemes,
+            )
+        });
+        let comments_url = feed_entry
+            .comments_url
+            .as_deref()
+            .filter(|_| feed_record.post_comments_link);
+        let fallback_url = Some(feed_entry.url.as_str())
+            .filter(|_| ogp_info.is_none() && self.embed_fallback == EmbedFallback::AppendUrl);
+        // フッターの各行と、その行に付けるリンク
+        let footer_lines = [
+            fallback_url.map(|url| (url, Some(url))),
+            comments_url.map(|comments_url| (DISCUSSION_LINK_LABEL, Some(comments_url))),
+            self.post_footer
+                .as_deref()
+                .map(|post_footer| (post_footer, None)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        let footer = footer_lines
+            .iter()
+            .map(|(line, _)| *line)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let test_prefix = if self.test_mode { "[test]\n" } else { "" };
+        // 長すぎる場合はサイト名とフッターを残し、エントリーのタイトルを切り詰める
+        let footer_graphemes = if footer.is_empty() {
+            0
+        } else {
+            grapheme_len(&footer) + grapheme_len("\n")
+        };
+        let title_max_graphemes =
+            MAX_POST_GRAPHEMES.saturating_sub(grapheme_len(test_prefix) + footer_graphemes);
+        let published = feed_entry
+            .published
+            .map(|published| match self.created_at_offset {
+                Some(offset) => published
+                    .with_timezone(&offset)
+                    .format("%Y-%m-%d")
+                    .to_string(),
+                None => published.format("%Y-%m-%d").to_string(),
+            });
+        // 要約に含まれるURLも、本文の他のURLと同じく後でリンクのファセットにする
+        let summary = feed_entry
+            .summary
+            .as_deref()
+            .map(|summary| summary.split_whitespace().collect::<Vec<_>>().join(" "));
+        let mut title = match (&feed_record.post_template, &entry_title) {
+            (Some(post_template), _) => render_post_template(
+                post_template,
+                TemplateValues {
+                    title: entry_title.as_deref().unwrap_or_default(),
+                    feed_title: feed_title.as_deref(),
+                    url: &feed_entry.url,
+                    published: published.as_deref(),
+                    summary: summary.as_deref(),
+                },
+                title_max_graphemes,
+            ),
+            (None, Some(entry_title)) => match &feed_title {
+                Some(feed_title) => append_suffix(
+                    entry_title,
+                    &format!("{}{}", self.title_separator, feed_title),
+                    title_max_graphemes,
+                ),
                 None => entry_title.clone(),
             },
-            None => "".to_string(),
+            (None, None) => "".to_string(),
         };
-        if cfg!(debug_assertions) {
-            title = format!("[test]\n{}", title);
+        title = format!("{}{}", test_prefix, title);
+        if !footer.is_empty() {
+            title = append_footer(&title, &footer, MAX_POST_GRAPHEMES);
+        } else {
+            title = truncate_preserving_trailing_link(&title, MAX_POST_GRAPHEMES);
+        }
+        let mut facets = detect_facets(&title, self.max_link_facets);
+        // フッターは省略されないため、末尾からの位置で各行にリンクを付ける
+        if !footer.is_empty() && title.ends_with(&footer) {
+            let mut byte_start = title.len() - footer.len();
+            for (line, uri) in &footer_lines {
+                if let Some(uri) = uri.filter(|_| {
+                    !facets
+                        .iter()
+                        .any(|facet| facet.index.byte_start == byte_start)
+                }) {
+                    facets.push(Facet {
+                        index: ByteSlice {
+                            byte_start,
+                            byte_end: byte_start + line.len(),
+                        },
+                        features: vec![FacetFeature::Link {
+                            uri: uri.to_string(),
+                        }],
+                    });
+                }
+                byte_start += line.len() + "\n".len();
+            }
+        }
+        let lang_detection_text = [feed_entry.title.as_deref(), feed_entry.summary.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut langs = langs_from_categories(&feed_entry.categories, &feed_record.category_langs);
+        if langs.is_empty() {
+            langs = detect_langs(
+                &lang_detection_text,
+                feed_lang,
+                &self.lang_detection_thresholds,
+            );
+        }
+        if langs.is_empty() {
+            langs = self.default_langs.clone();
         }
         let thumb = match upload_blob_response {
             Some(upload_blob_response) => {
-                if upload_blob_response.blob.size > 1000000 {
+                if upload_blob_response.blob.size > get_max_thumb_bytes(feed_record) {
                     None
                 } else {
                     Some(upload_blob_response.blob)
@@ -242,32 +809,57 @@ impl BskyClient {
 
         let embed = match ogp_info {
             Some(ogp_info) => {
-                let embed_title = if let Some(ogp_title) = ogp_info.title {
-                    ogp_title
-                } else if let Some(feed_entry_title) = feed_entry.title {
-                    feed_entry_title
-                } else {
-                    "".to_string()
-                };
+                let embed_title = select_embed_title(
+                    ogp_info.title,
+                    feed_entry.title,
+                    feed_title.as_deref(),
+                    self.prefer_entry_title_over_site_title,
+                );
                 Some(Embed {
                     r#type: "app.bsky.embed.external".to_string(),
                     external: EmbedExternal {
                         uri: feed_entry.url,
-                        title: embed_title,
-                        description: ogp_info.description.unwrap_or("".to_string()),
+                        title: truncate_graphemes(&embed_title, MAX_EMBED_TITLE_GRAPHEMES),
+                        description: truncate_graphemes(
+                            &select_embed_description(
+                                ogp_info.description.as_deref(),
+                                feed_entry.summary.as_deref(),
+                                feed_record.default_embed_description.as_deref(),
+                            ),
+                            self.max_embed_description_graphemes
+                                .min(MAX_EMBED_DESCRIPTION_GRAPHEMES),
+                        ),
                         thumb,
                     },
                 })
             }
             None => None,
         };
-        let created_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        // 固定のルート投稿が設定されている場合は、その投稿への返信としてスレッドにまとめる
+        let reply = match (&feed_record.thread_root_uri, &feed_record.thread_root_cid) {
+            (Some(uri), Some(cid)) => {
+                let root = StrongRef {
+                    uri: uri.clone(),
+                    cid: cid.clone(),
+                };
+                Some(ReplyRef {
+                    parent: root.clone(),
+                    root,
+                })
+            }
+            _ => None,
+        };
+        let created_at = format_created_at(Utc::now(), self.created_at_offset);
         CreateRecordRequest {
             repo: self.session.did.clone(),
             collection: "app.bsky.feed.post".to_string(),
             record: Record {
                 r#type: "app.bsky.feed.post".to_string(),
                 text: title,
+                facets,
+                langs,
+                reply,
+                tags: self.bot_marker_tag.iter().cloned().collect(),
                 created_at,
                 embed,
             },
@@ -275,70 +867,2571 @@ impl BskyClient {
     }
 }
 
-fn resize_thumbnail(image_bytes: &Bytes) -> Result<Bytes, OpaqueError> {
-    let image = image::io::Reader::new(Cursor::new(image_bytes))
-        .with_guessed_format()?
-        .decode()?;
-    let resized_image = image.resize(1000, 1000, image::imageops::FilterType::Lanczos3);
-    let mut resized_image_bytes = Vec::new();
-    resized_image.write_to(
-        &mut Cursor::new(&mut resized_image_bytes),
-        image::ImageOutputFormat::Jpeg(100),
-    )?;
-    let resized_image_bytes = Bytes::from(resized_image_bytes);
-    Ok(resized_image_bytes)
+#[derive(Debug, Clone, Copy)]
+struct TemplateValues<'a> {
+    title: &'a str,
+    feed_title: Option<&'a str>,
+    url: &'a str,
+    published: Option<&'a str>,
+    summary: Option<&'a str>,
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::feed::{extract_feed_entries, extract_feed_entry_info, get_feed, get_og_image};
+// {title}、{feed_title}、{url}、{published}、{summary}を置き換える。値のないものは空にし、それ以外の{}はそのまま残す
+// タイトルなどに含まれる{}を置き換えないよう、テンプレートを1回だけ走査する
+fn render_template(template: &str, values: TemplateValues) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let value = match &rest[1..end] {
+            "title" => Some(values.title),
+            "feed_title" => Some(values.feed_title.unwrap_or_default()),
+            "url" => Some(values.url),
+            "published" => Some(values.published.unwrap_or_default()),
+            "summary" => Some(values.summary.unwrap_or_default()),
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
 
-    use super::*;
-    use dotenvy::dotenv;
+// 長すぎる場合はテンプレートの固定部分とURLを残し、エントリーのタイトルを切り詰める
+fn render_post_template(template: &str, values: TemplateValues, max_graphemes: usize) -> String {
+    // 要約は長くなりやすいため、タイトルより先に切り詰める
+    let summary_count = template.matches("{summary}").count();
+    let summary;
+    let values =
+        if summary_count > 0 && grapheme_len(&render_template(template, values)) > max_graphemes {
+            let fixed_graphemes = grapheme_len(&render_template(
+                template,
+                TemplateValues {
+                    summary: None,
+                    ..values
+                },
+            ));
+            summary = truncate_graphemes(
+                values.summary.unwrap_or_default(),
+                max_graphemes.saturating_sub(fixed_graphemes) / summary_count,
+            );
+            TemplateValues {
+                summary: Some(&summary),
+                ..values
+            }
+        } else {
+            values
+        };
+    let rendered = render_template(template, values);
+    let title_count = template.matches("{title}").count();
+    if grapheme_len(&rendered) <= max_graphemes || title_count == 0 {
+        return truncate_preserving_trailing_link(&rendered, max_graphemes);
+    }
+    let fixed_graphemes = grapheme_len(&render_template(
+        template,
+        TemplateValues {
+            title: "",
+            ..values
+        },
+    ));
+    let title = truncate_graphemes(
+        values.title,
+        max_graphemes.saturating_sub(fixed_graphemes) / title_count,
+    );
+    truncate_preserving_trailing_link(
+        &render_template(
+            template,
+            TemplateValues {
+                title: &title,
+                ..values
+            },
+        ),
+        max_graphemes,
+    )
+}
 
-    #[tokio::test]
-    async fn test_create_session() {
-        dotenv().ok();
-        let client = BskyClient::new().await.unwrap();
-        println!("{:?}", client.session);
+// og:titleがサイト名(フィードのタイトル)と同じ場合は、より具体的なエントリーのタイトルを優先する
+fn select_embed_title(
+    ogp_title: Option<String>,
+    feed_entry_title: Option<String>,
+    feed_title: Option<&str>,
+    prefer_entry_title_over_site_title: bool,
+) -> String {
+    match (ogp_title, feed_entry_title) {
+        (Some(ogp_title), Some(feed_entry_title))
+            if prefer_entry_title_over_site_title
+                && feed_title.is_some_and(|feed_title| is_same_title(&ogp_title, feed_title)) =>
+        {
+            feed_entry_title
+        }
+        (Some(ogp_title), _) => ogp_title,
+        (None, Some(feed_entry_title)) => feed_entry_title,
+        (None, None) => "".to_string(),
     }
+}
 
-    #[tokio::test]
-    async fn test_refresh_session() {
-        dotenv().ok();
-        let mut client = BskyClient::new().await.unwrap();
-        client.refresh_session().await.unwrap();
-        println!("{:?}", client.session);
+// og:descriptionがないか空白だけのページでも、カードに説明文が入るようにする
+fn select_embed_description(
+    ogp_description: Option<&str>,
+    feed_entry_summary: Option<&str>,
+    default_description: Option<&str>,
+) -> String {
+    [ogp_description, feed_entry_summary, default_description]
+        .into_iter()
+        .flatten()
+        .map(|description| description.split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|description| !description.is_empty())
+        .unwrap_or_default()
+}
+
+fn is_same_title(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+// "at://did:plc:xxx/app.bsky.feed.post/rkey"をリポジトリ、コレクション、rkeyに分ける
+fn parse_at_uri(uri: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = uri.strip_prefix("at://")?.split('/');
+    let repo = parts.next().filter(|repo| !repo.is_empty())?;
+    let collection = parts.next().filter(|collection| !collection.is_empty())?;
+    let rkey = parts.next().filter(|rkey| !rkey.is_empty())?;
+    if parts.next().is_some() {
+        return None;
     }
+    Some((repo, collection, rkey))
+}
 
-    #[tokio::test]
-    async fn test_upload_thumbnail() {
-        dotenv().ok();
-        let og_image = get_og_image("https://www.rust-lang.org/static/images/rust-social-wide.jpg")
-            .await
-            .unwrap();
-        let mut client = BskyClient::new().await.unwrap();
-        let response = client
-            .upload_thumbnail_with_resizing(og_image.image)
-            .await
-            .unwrap();
-        println!("{:?}", response);
+// 目印のタグが設定されている場合は、そのタグが付いたボットの投稿のみを対象にする
+fn extract_embed_urls(response: ListRecordsResponse, bot_marker_tag: Option<&str>) -> Vec<String> {
+    response
+        .records
+        .into_iter()
+        .filter(|record| is_bot_post(&record.value, bot_marker_tag))
+        .filter_map(|record| record.value.embed)
+        .filter_map(|embed| embed.external)
+        .map(|external| external.uri)
+        .collect()
+}
+
+// "Z"を指定した場合はUTC(Z表記)、"+09:00"などを指定した場合はそのオフセット表記で出力する
+fn parse_created_at_offset(offset: &str) -> Result<Option<FixedOffset>, OpaqueError> {
+    if offset.eq_ignore_ascii_case("z") {
+        return Ok(None);
     }
+    let offset = offset
+        .parse::<FixedOffset>()
+        .map_err(|e| format!("invalid BSKY_CREATED_AT_OFFSET {}, {:?}", offset, e))?;
+    Ok(Some(offset))
+}
 
-    #[tokio::test]
-    async fn test_create_record_request() {
-        dotenv().ok();
-        let feed = get_feed("https://this-week-in-rust.org/atom.xml")
-            .await
-            .unwrap();
+fn format_created_at(now: DateTime<Utc>, offset: Option<FixedOffset>) -> String {
+    match offset {
+        Some(offset) => now
+            .with_timezone(&offset)
+            .to_rfc3339_opts(SecondsFormat::Micros, false),
+        None => now.to_rfc3339_opts(SecondsFormat::Micros, true),
+    }
+}
+
+async fn create_session(
+    reqwest_client: &reqwest::Client,
+    service_url: &str,
+    identifier: &str,
+    password: &str,
+) -> Result<Session, OpaqueError> {
+    let request = CreateSessionRequest {
+        identifier: identifier.to_string(),
+        password: password.to_string(),
+    };
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.append(header::ACCEPT, HeaderValue::from_static("application/json"));
+    let response = reqwest_client
+        .post(format!(
+            "{}/xrpc/com.atproto.server.createSession",
+            service_url
+        ))
+        .headers(headers)
+        .body(serde_json::to_string(&request)?)
+        .send()
+        .await?;
+    let response = check_xrpc_response(response).await?;
+    let session: Session = response.json().await?;
+    Ok(session)
+}
+
+// 独自のPDSを使う場合はBSKY_PDS_HOSTで指定する
+fn pds_host_from_env() -> String {
+    env::var("BSKY_PDS_HOST")
+        .ok()
+        .map(|pds_host| pds_host.trim().trim_end_matches('/').to_string())
+        .filter(|pds_host| !pds_host.is_empty())
+        .unwrap_or_else(|| BSKY_SERVICE_URL.to_string())
+}
+
+fn find_pds_endpoint(did_doc: &DidDocument) -> Option<String> {
+    did_doc
+        .service
+        .iter()
+        .find(|service| service.id.ends_with("#atproto_pds"))
+        .and_then(|service| service.service_endpoint.as_str())
+        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+        .filter(|endpoint| endpoint.starts_with("https://") || endpoint.starts_with("http://"))
+}
+
+async fn fetch_did_document(
+    reqwest_client: &reqwest::Client,
+    did: &str,
+    plc_directory_url: &str,
+) -> Result<DidDocument, OpaqueError> {
+    let url = if did.starts_with("did:plc:") {
+        format!("{}/{}", plc_directory_url, did)
+    } else if let Some(host) = did.strip_prefix("did:web:") {
+        // ポート番号の:は%3Aにエンコードされている
+        format!("https://{}/.well-known/did.json", host.replace("%3A", ":"))
+    } else {
+        return Err(format!("unsupported did method: {}", did).into());
+    };
+    let response = reqwest_client.get(url).send().await?.error_for_status()?;
+    Ok(response.json().await?)
+}
+
+// 他のPDSのアカウントでもそのPDSに直接リクエストできるよう、DIDドキュメントからPDSを解決する
+// 解決できない場合はログインに使ったホストを使う
+async fn resolve_pds_url(
+    reqwest_client: &reqwest::Client,
+    session: &Session,
+    plc_directory_url: &str,
+    fallback: &str,
+) -> String {
+    let pds_endpoint = match &session.did_doc {
+        Some(did_doc) => find_pds_endpoint(did_doc),
+        None => match fetch_did_document(reqwest_client, &session.did, plc_directory_url).await {
+            Ok(did_doc) => find_pds_endpoint(&did_doc),
+            Err(err) => {
+                warn!(error = ?err, "Failed to resolve DID document");
+                None
+            }
+        },
+    };
+    pds_endpoint.unwrap_or_else(|| fallback.to_string())
+}
+
+// handleの代わりにDIDを指定することもできる
+fn check_expected_account(session: &Session, expected: Option<&str>) -> Result<(), OpaqueError> {
+    let Some(expected) = expected.map(|expected| expected.trim().trim_start_matches('@')) else {
+        return Ok(());
+    };
+    let actual = if expected.starts_with("did:") {
+        &session.did
+    } else {
+        &session.handle
+    };
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(BotError::UnexpectedAccount {
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VerifiedAccount {
+    pub handle: String,
+    pub did: String,
+}
+
+// アプリパスワードの確認用。セッションのトークンは保持せずに破棄する
+pub async fn verify_credentials(
+    identifier: &str,
+    password: &str,
+) -> Result<VerifiedAccount, OpaqueError> {
+    verify_credentials_with_service_url(&pds_host_from_env(), identifier, password).await
+}
+
+async fn verify_credentials_with_service_url(
+    service_url: &str,
+    identifier: &str,
+    password: &str,
+) -> Result<VerifiedAccount, OpaqueError> {
+    let session = create_session(
+        &http_client::build_client()?,
+        service_url,
+        identifier,
+        password,
+    )
+    .await?;
+    Ok(VerifiedAccount {
+        handle: session.handle,
+        did: session.did,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct XrpcErrorResponse {
+    error: Option<String>,
+    message: Option<String>,
+}
+
+// エラーレスポンスのbodyを残してBotError::BskyApiにする
+async fn check_xrpc_response(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, OpaqueError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.json::<XrpcErrorResponse>().await.ok();
+    Err(BotError::BskyApi {
+        status: status.as_u16(),
+        error: body.as_ref().and_then(|body| body.error.clone()),
+        message: body.and_then(|body| body.message),
+    }
+    .into())
+}
+
+// CIDv1(base32)のリンクを持つ画像のblobかどうか
+fn is_valid_blob(blob: &Blob) -> bool {
+    let link = &blob.r#ref.link;
+    blob.r#type == "blob"
+        && link.len() > 1
+        && link.starts_with('b')
+        && link.chars().all(|c| c.is_ascii_alphanumeric())
+        && blob.mime_type.starts_with("image/")
+        && blob.size > 0
+}
+
+// 画像の形式を理由に拒否した場合のXRPCのエラーコード
+const UNSUPPORTED_BLOB_ERROR: &str = "InvalidMimeType";
+// エラーコードがInvalidRequestの場合に、画像の形式が理由であることを示すメッセージの先頭
+const UNSUPPORTED_BLOB_MESSAGE_PREFIX: &str = "Wrong type of blob:";
+
+// createRecordが埋め込みの画像の形式を理由に拒否したかどうか
+pub fn is_unsupported_blob_error(err: &OpaqueError) -> bool {
+    match err.downcast_ref::<BotError>() {
+        Some(BotError::BskyApi {
+            status: 400,
+            error: Some(error),
+            message,
+        }) => match error.as_str() {
+            UNSUPPORTED_BLOB_ERROR => true,
+            "InvalidRequest" => message
+                .as_deref()
+                .is_some_and(|message| message.starts_with(UNSUPPORTED_BLOB_MESSAGE_PREFIX)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// 長すぎるタイトルが投稿の大半を占めないよう、収まる説明文があればそちらを使う
+fn shorten_entry_title(
+    entry_title: &str,
+    description: Option<&str>,
+    max_title_graphemes: usize,
+) -> String {
+    if max_title_graphemes == 0 || grapheme_len(entry_title) <= max_title_graphemes {
+        return entry_title.to_string();
+    }
+    match description.map(str::trim).filter(|description| {
+        !description.is_empty() && grapheme_len(description) <= max_title_graphemes
+    }) {
+        Some(description) => description.to_string(),
+        None => truncate_graphemes(entry_title, max_title_graphemes),
+    }
+}
+
+fn strip_emoji_from_title(title: &str, enabled: bool) -> String {
+    if enabled {
+        strip_emoji(title)
+    } else {
+        title.to_string()
+    }
+}
+
+fn is_bot_post(post: &ListedPost, bot_marker_tag: Option<&str>) -> bool {
+    match bot_marker_tag {
+        Some(bot_marker_tag) => post.tags.iter().any(|tag| tag == bot_marker_tag),
+        None => true,
+    }
+}
+
+// 先頭の"#"は付けても付けなくても同じタグとして扱う
+fn parse_bot_marker_tag(tag: &str) -> Result<Option<String>, OpaqueError> {
+    let tag = tag.trim().trim_start_matches('#');
+    if tag.is_empty() {
+        return Ok(None);
+    }
+    if tag.chars().any(char::is_whitespace)
+        || grapheme_len(tag) > MAX_TAG_GRAPHEMES
+        || tag.len() > MAX_TAG_BYTES
+    {
+        return Err(format!("invalid BOT_MARKER_TAG: {}", tag).into());
+    }
+    Ok(Some(tag.to_string()))
+}
+
+// 環境変数では改行を書きにくいため"\n"を改行として扱う
+fn parse_title_separator(separator: &str) -> String {
+    separator.replace("\\n", "\n")
+}
+
+pub fn get_max_thumb_bytes(feed_record: &FeedRecord) -> u64 {
+    feed_record
+        .max_thumb_bytes
+        .unwrap_or(DEFAULT_MAX_THUMB_BYTES)
+}
+
+const UNTRANSCODED_THUMB_CONTENT_TYPES: [&str; 2] = ["image/jpeg", "image/png"];
+
+// 取得先が返すContent-Typeは当てにならないため画像の内容から判定し、判定できない場合だけそれを使う
+fn detect_image_content_type(image_bytes: &[u8], content_type: &str) -> String {
+    match image::guess_format(image_bytes) {
+        Ok(format) => format.to_mime_type().to_string(),
+        Err(_) if content_type.starts_with("image/") => content_type.to_string(),
+        Err(_) => "application/octet-stream".to_string(),
+    }
+}
+
+// アップロードした後で上限を超えて捨てることのないよう、上限に収まるまで品質と大きさを下げる
+fn resize_thumbnail(image_bytes: &Bytes, max_thumb_bytes: u64) -> Result<Bytes, OpaqueError> {
+    let image = image::io::Reader::new(Cursor::new(image_bytes))
+        .with_guessed_format()?
+        .decode()?;
+    // 小さな画像は拡大しない
+    let mut dimension = image.width().max(image.height()).min(THUMB_MAX_DIMENSION);
+    let mut resized_image_bytes = Vec::new();
+    loop {
+        let resized_image =
+            image.resize(dimension, dimension, image::imageops::FilterType::Lanczos3);
+        for quality in THUMB_JPEG_QUALITIES {
+            resized_image_bytes.clear();
+            resized_image.write_to(
+                &mut Cursor::new(&mut resized_image_bytes),
+                image::ImageOutputFormat::Jpeg(quality),
+            )?;
+            if resized_image_bytes.len() as u64 <= max_thumb_bytes {
+                return Ok(Bytes::from(resized_image_bytes));
+            }
+        }
+        if dimension <= THUMB_MIN_DIMENSION {
+            return Err(format!(
+                "thumbnail is still {} bytes at {}px, over the limit of {} bytes",
+                resized_image_bytes.len(),
+                dimension,
+                max_thumb_bytes
+            )
+            .into());
+        }
+        dimension = (dimension * 3 / 4).max(THUMB_MIN_DIMENSION);
+        debug!(dimension, "Thumbnail is over the size limit, shrinking");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        feed::{
+            extract_feed_entries, extract_feed_entry_info, get_feed, get_og_image,
+            EntryInfoOptions, FeedFetchOptions,
+        },
+        session_cache::tests::MockSessionStore,
+        text::TextCase,
+    };
+
+    use super::*;
+    use dotenvy::dotenv;
+
+    fn test_client() -> BskyClient {
+        BskyClient {
+            reqwest_client: reqwest::Client::new(),
+            identifier: "bot.example.com".to_string(),
+            service_url: BSKY_SERVICE_URL.to_string(),
+            session: Session {
+                access_jwt: "access".to_string(),
+                refresh_jwt: "refresh".to_string(),
+                handle: "bot.example.com".to_string(),
+                did: "did:plc:test".to_string(),
+                did_doc: None,
+            },
+            session_updated: false,
+            created_at_offset: None,
+            prefer_entry_title_over_site_title: true,
+            title_separator: DEFAULT_TITLE_SEPARATOR.to_string(),
+            max_title_graphemes: 0,
+            post_footer: None,
+            max_link_facets: 0,
+            max_embed_description_graphemes: DEFAULT_MAX_EMBED_DESCRIPTION_GRAPHEMES,
+            verify_uploaded_blob: false,
+            embed_fallback: EmbedFallback::default(),
+            lang_detection_thresholds: LangDetectionThresholds::default(),
+            default_langs: Vec::new(),
+            bot_marker_tag: None,
+            test_mode: false,
+        }
+    }
+
+    fn test_feed(feed_title: &str) -> Feed {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>{feed_title}</title>
+    <link>https://example.com/</link>
+    <item>
+      <title>Entry title</title>
+      <link>https://example.com/entry</link>
+      <guid>https://example.com/entry</guid>
+    </item>
+  </channel>
+</rss>"#
+        );
+        feed_rs::parser::parse(xml.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let values = TemplateValues {
+            title: "Rust 1.76.0",
+            feed_title: Some("Rust Blog"),
+            url: "https://example.com/entry",
+            published: Some("2024-02-08"),
+            summary: Some("Highlights of the release."),
+        };
+        assert_eq!(
+            render_template("{title} | {feed_title} {url} ({published})", values),
+            "Rust 1.76.0 | Rust Blog https://example.com/entry (2024-02-08)"
+        );
+        // 値のないプレースホルダーは空にし、知らないものや閉じていないものは残す
+        let missing = TemplateValues {
+            feed_title: None,
+            published: None,
+            summary: None,
+            ..values
+        };
+        assert_eq!(
+            render_template(
+                "{title}{feed_title}{published}{summary} {unknown} {title",
+                missing
+            ),
+            "Rust 1.76.0 {unknown} {title"
+        );
+        assert_eq!(render_template("新着記事", values), "新着記事");
+        // 値に含まれる{}は置き換えない
+        let braces = TemplateValues {
+            title: "{url}",
+            ..values
+        };
+        assert_eq!(render_template("{title}", braces), "{url}");
+        // 長すぎる場合はタイトルだけを切り詰める
+        assert_eq!(
+            render_post_template("🦀 {title} {url}", values, 35),
+            "🦀 Rust 1… https://example.com/entry"
+        );
+        // 要約はタイトルより先に切り詰める
+        assert_eq!(
+            render_post_template("{title}\n{summary}", values, 22),
+            "Rust 1.76.0\nHighlight…"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_record_in_test_mode() {
+        let server = wiremock::MockServer::start().await;
+        let mut client = BskyClient {
+            service_url: server.uri(),
+            test_mode: true,
+            ..test_client()
+        };
+        let feed = test_feed("Example Blog");
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                extract_feed_entries(&feed).remove(0),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.text, "[test]\nEntry title | Example Blog");
+        // テストモードではcreateRecordを呼ばない
+        let response = client.create_record(request).await.unwrap();
+        assert_eq!(
+            response.uri,
+            "at://did:plc:test/app.bsky.feed.post/test-mode"
+        );
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_post_template() {
+        let mut client = test_client();
+        client.max_link_facets = 1;
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        // フィード名を含めないテンプレート
+        let feed_record = FeedRecord {
+            post_template: Some("🦀 {title}".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.text, "🦀 Entry title");
+        // URLを含むテンプレートではリンクのファセットを付ける
+        let feed_record = FeedRecord {
+            post_template: Some("{title} {url}".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.text, "Entry title https://example.com/entry");
+        assert_eq!(request.record.facets.len(), 1);
+        // 要約に含まれるURLにもリンクのファセットを付ける
+        let feed_record = FeedRecord {
+            post_template: Some("{title}\n{summary}".to_string()),
+            ..Default::default()
+        };
+        let mut summary_entry = feed_entry.clone();
+        summary_entry.summary = Some(
+            "Changelog: https://example.com/a\n  Migration guide: https://example.com/b"
+                .to_string(),
+        );
+        client.max_link_facets = 2;
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                summary_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(
+            request.record.text,
+            "Entry title\nChangelog: https://example.com/a Migration guide: https://example.com/b"
+        );
+        assert_eq!(
+            request
+                .record
+                .facets
+                .iter()
+                .map(|facet| &request.record.text[facet.index.byte_start..facet.index.byte_end])
+                .collect::<Vec<_>>(),
+            vec!["https://example.com/a", "https://example.com/b"]
+        );
+        client.max_link_facets = 1;
+        // テンプレートがない場合はこれまでどおりフィード名を付ける
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.text, "Entry title | Example Blog");
+    }
+
+    #[test]
+    fn test_select_embed_title() {
+        assert_eq!(
+            select_embed_title(
+                Some("Example Blog".to_string()),
+                Some("Entry title".to_string()),
+                Some("Example Blog"),
+                true
+            ),
+            "Entry title"
+        );
+        assert_eq!(
+            select_embed_title(
+                Some(" example blog ".to_string()),
+                Some("Entry title".to_string()),
+                Some("Example Blog"),
+                true
+            ),
+            "Entry title"
+        );
+        assert_eq!(
+            select_embed_title(
+                Some("Example Blog".to_string()),
+                Some("Entry title".to_string()),
+                Some("Example Blog"),
+                false
+            ),
+            "Example Blog"
+        );
+        assert_eq!(
+            select_embed_title(
+                Some("OGP title".to_string()),
+                Some("Entry title".to_string()),
+                Some("Example Blog"),
+                true
+            ),
+            "OGP title"
+        );
+        assert_eq!(
+            select_embed_title(
+                Some("Example Blog".to_string()),
+                None,
+                Some("Example Blog"),
+                true
+            ),
+            "Example Blog"
+        );
+        assert_eq!(
+            select_embed_title(None, Some("Entry title".to_string()), None, true),
+            "Entry title"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_site_og_title() {
+        let client = test_client();
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: Some("Example Blog".to_string()),
+            image_url: None,
+            twitter_image_url: None,
+            description: Some("description".to_string()),
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        assert_eq!(request.record.embed.unwrap().external.title, "Entry title");
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_footer() {
+        let mut client = test_client();
+        client.post_footer = Some("🤖 auto-posted".to_string());
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(request
+            .record
+            .text
+            .ends_with("Entry title | Example Blog\n🤖 auto-posted"));
+
+        let feed = test_feed(&"Long feed title ".repeat(30));
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(grapheme_len(&request.record.text), MAX_POST_GRAPHEMES);
+        assert!(request.record.text.ends_with("…\n🤖 auto-posted"));
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_link_facets() {
+        let mut client = test_client();
+        let feed = test_feed("Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        feed_entry.title =
+            Some("Release notes https://example.com/a and https://example.com/b".to_string());
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.facets.is_empty());
+        assert!(!serde_json::to_string(&request).unwrap().contains("facets"));
+
+        client.max_link_facets = 1;
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.facets.len(), 1);
+        let byte_start = request.record.text.find("https://example.com/a").unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            json["record"]["facets"][0],
+            serde_json::json!({
+                "index": { "byteStart": byte_start, "byteEnd": byte_start + 21 },
+                "features": [
+                    { "$type": "app.bsky.richtext.facet#link", "uri": "https://example.com/a" }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_default_lang() {
+        let client = test_client();
+        let feed = test_feed("Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        feed_entry.title = Some("Rust 1.76.0 リリース".to_string());
+        let feed_record = FeedRecord {
+            default_lang: Some("ja".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["ja".to_string()]);
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.langs.is_empty());
+        // フィードごとに設定された既定の言語をフィードで宣言された言語より優先する
+        let mut feed = feed;
+        feed.language = Some("en".to_string());
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["ja".to_string()]);
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["en".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_feed_language() {
+        let client = test_client();
+        let mut feed = test_feed("Example Blog");
+        feed.language = Some("ja".to_string());
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        // 短いタイトルは判定しないため、フィードで宣言された言語を使う
+        feed_entry.title = Some("Rust 1.76.0".to_string());
+        feed_entry.summary = None;
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["ja".to_string()]);
+        let record = serde_json::to_value(&request.record).unwrap();
+        assert_eq!(record["langs"], serde_json::json!(["ja"]));
+        // フィードにも設定にも言語がない場合は全体の既定の言語を使う
+        feed.language = None;
+        let client = BskyClient {
+            default_langs: vec!["en".to_string()],
+            ..test_client()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["en".to_string()]);
+        // 既定の言語もなければlangsを省略する
+        let request = test_client()
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        let record = serde_json::to_value(&request.record).unwrap();
+        assert!(record.get("langs").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_category_langs() {
+        let client = test_client();
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Aggregator</title>
+    <link>https://example.com/</link>
+    <item>
+      <title>The Rust team is happy to announce a new version of Rust</title>
+      <link>https://example.com/entry</link>
+      <guid>https://example.com/entry</guid>
+      <category>日本語</category>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        assert_eq!(feed_entry.categories, vec!["日本語".to_string()]);
+        let feed_record = FeedRecord {
+            category_langs: vec![("日本語".to_string(), "ja".to_string())],
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["ja".to_string()]);
+        // 対応がない場合は本文から判定する
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.langs, vec!["en".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_comments_link() {
+        let mut client = test_client();
+        client.post_footer = Some("🤖 auto-posted".to_string());
+        let feed = test_feed("Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        feed_entry.comments_url = Some("https://news.ycombinator.com/item?id=1".to_string());
+        let feed_record = FeedRecord {
+            post_comments_link: true,
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        let text = &request.record.text;
+        assert!(text.ends_with("Entry title | Example Blog\n💬 Discussion\n🤖 auto-posted"));
+        let facet = request.record.facets.last().unwrap();
+        assert_eq!(
+            &text[facet.index.byte_start..facet.index.byte_end],
+            "💬 Discussion"
+        );
+        assert_eq!(
+            facet.features,
+            vec![FacetFeature::Link {
+                uri: "https://news.ycombinator.com/item?id=1".to_string()
+            }]
+        );
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(!request.record.text.contains("💬 Discussion"));
+        assert!(request.record.facets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_embed_fallback() {
+        let mut client = test_client();
+        client.post_footer = Some("🤖 auto-posted".to_string());
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        // 埋め込みを作れない場合、既定では本文だけを投稿する
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.embed.is_none());
+        assert!(request
+            .record
+            .text
+            .ends_with("Entry title | Example Blog\n🤖 auto-posted"));
+        assert!(request.record.facets.is_empty());
+
+        client.embed_fallback = parse_embed_fallback("append_url").unwrap();
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.embed.is_none());
+        let text = &request.record.text;
+        assert!(text.ends_with(&format!(
+            "Entry title | Example Blog\n{}\n🤖 auto-posted",
+            feed_entry.url
+        )));
+        assert_eq!(request.record.facets.len(), 1);
+        let facet = &request.record.facets[0];
+        assert_eq!(
+            &text[facet.index.byte_start..facet.index.byte_end],
+            feed_entry.url
+        );
+        assert_eq!(
+            facet.features,
+            vec![FacetFeature::Link {
+                uri: feed_entry.url.clone()
+            }]
+        );
+        // 本文中のURLを検出する設定でもリンクは重複させない
+        client.max_link_facets = 3;
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.facets.len(), 1);
+        // 埋め込みを作れた場合はURLを追加しない
+        let ogp_info = OGPInfo {
+            title: Some("OGP title".to_string()),
+            image_url: None,
+            twitter_image_url: None,
+            description: None,
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        assert!(request.record.embed.is_some());
+        assert!(!request.record.text.contains(&feed_entry.url));
+        assert!(parse_embed_fallback("unknown").is_err());
+    }
+
+    #[test]
+    fn test_parse_embed_fallback() {
+        assert_eq!(parse_embed_fallback("").unwrap(), EmbedFallback::TextOnly);
+        assert_eq!(
+            parse_embed_fallback("text_only").unwrap(),
+            EmbedFallback::TextOnly
+        );
+        assert_eq!(
+            parse_embed_fallback(" append_url ").unwrap(),
+            EmbedFallback::AppendUrl
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_limits_embed_fields() {
+        let mut client = test_client();
+        client.max_embed_description_graphemes = 10000;
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: Some("🦀".repeat(500)),
+            image_url: None,
+            twitter_image_url: None,
+            description: Some("e\u{301}".repeat(2000)),
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        let external = request.record.embed.unwrap().external;
+        assert_eq!(grapheme_len(&external.title), MAX_EMBED_TITLE_GRAPHEMES);
+        assert!(external.title.ends_with('…'));
+        assert_eq!(
+            grapheme_len(&external.description),
+            MAX_EMBED_DESCRIPTION_GRAPHEMES
+        );
+        assert!(external.description.ends_with('…'));
+        // 上限に収まる場合はそのまま使う
+        let ogp_info = OGPInfo {
+            title: Some("OGP title".to_string()),
+            image_url: None,
+            twitter_image_url: None,
+            description: Some("Short".to_string()),
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        let external = request.record.embed.unwrap().external;
+        assert_eq!(external.title, "OGP title");
+        assert_eq!(external.description, "Short");
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_truncates_description() {
+        let mut client = test_client();
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: None,
+            twitter_image_url: None,
+            description: Some("説明文".repeat(200)),
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info.clone()),
+                None,
+            )
+            .await;
+        let description = request.record.embed.unwrap().external.description;
+        assert_eq!(grapheme_len(&description), 300);
+        assert!(description.ends_with('…'));
+
+        client.max_embed_description_graphemes = 10;
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        assert_eq!(
+            request.record.embed.unwrap().external.description,
+            "説明文説明文説明文…"
+        );
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: None,
+            twitter_image_url: None,
+            description: Some("Short".to_string()),
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        assert_eq!(request.record.embed.unwrap().external.description, "Short");
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_without_og_description() {
+        let client = test_client();
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: Some("Entry title".to_string()),
+            image_url: None,
+            twitter_image_url: None,
+            description: Some(" \n ".to_string()),
+        };
+        let feed_record = FeedRecord {
+            default_embed_description: Some("Articles from Example Blog".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info.clone()),
+                None,
+            )
+            .await;
+        assert_eq!(
+            request.record.embed.unwrap().external.description,
+            "Articles from Example Blog"
+        );
+
+        // エントリーの本文があればフィードの既定の説明文より優先する
+        let feed_entry = FeedEntry {
+            summary: Some("The first  paragraph\nof the entry.".to_string()),
+            ..feed_entry
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        assert_eq!(
+            request.record.embed.unwrap().external.description,
+            "The first paragraph of the entry."
+        );
+    }
+
+    #[test]
+    fn test_select_embed_description() {
+        assert_eq!(
+            select_embed_description(Some(" OGP  description "), Some("Summary"), Some("Default")),
+            "OGP description"
+        );
+        assert_eq!(
+            select_embed_description(None, None, Some("Default")),
+            "Default"
+        );
+        assert_eq!(select_embed_description(Some(""), None, None), "");
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_strip_emoji() {
+        let mut client = test_client();
+        client.max_link_facets = 1;
+        let feed = test_feed("🦀 Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        feed_entry.title = Some("🎉 Release notes 🚀 https://example.com/a ✨".to_string());
+        let feed_record = FeedRecord {
+            strip_emoji: true,
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request
+            .record
+            .text
+            .ends_with("Release notes https://example.com/a | Example Blog"));
+        let byte_start = request.record.text.find("https://example.com/a").unwrap();
+        assert_eq!(request.record.facets[0].index.byte_start, byte_start);
+        assert_eq!(request.record.facets[0].index.byte_end, byte_start + 21);
+        // 無効な場合は絵文字を残す
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(request
+            .record
+            .text
+            .ends_with("🎉 Release notes 🚀 https://example.com/a ✨ | 🦀 Example Blog"));
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_preserves_trailing_link() {
+        let mut client = test_client();
+        client.max_link_facets = 1;
+        let feed = test_feed("Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        let url = "https://example.com/2024/02/10/very-long-article-url-that-must-stay-intact";
+        feed_entry.title = Some(format!("{}{}", "Long entry title ".repeat(20), url));
+        let feed_record = FeedRecord::default();
+        let feed = Feed {
+            title: None,
+            ..feed
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        let text = &request.record.text;
+        assert_eq!(grapheme_len(text), MAX_POST_GRAPHEMES);
+        assert!(text.ends_with(&format!("… {}", url)));
+        let facet = &request.record.facets[0];
+        assert_eq!(&text[facet.index.byte_start..facet.index.byte_end], url);
+        assert_eq!(facet.index.byte_end, text.len());
+        assert_eq!(
+            facet.features,
+            vec![FacetFeature::Link {
+                uri: url.to_string()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_truncates_entry_title_before_site_name() {
+        let mut client = test_client();
+        client.max_link_facets = 1;
+        let feed = test_feed("Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        feed_entry.title = Some("長いエントリーのタイトル".repeat(30));
+        let url = "https://example.com/2024/02/10/very-long-article-url-that-must-stay-intact";
+        let feed_record = FeedRecord {
+            display_name: Some(url.to_string()),
+            ..Default::default()
+        };
+        let feed = Feed {
+            title: None,
+            ..feed
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        let text = &request.record.text;
+        assert_eq!(grapheme_len(text), MAX_POST_GRAPHEMES);
+        // サイト名の部分は省略せず、エントリーのタイトルを切り詰める
+        assert!(text.ends_with(&format!("… | {}", url)));
+        let facet = &request.record.facets[0];
+        assert_eq!(&text[facet.index.byte_start..facet.index.byte_end], url);
+        assert_eq!(facet.index.byte_end, text.len());
+        assert_eq!(
+            facet.features,
+            vec![FacetFeature::Link {
+                uri: url.to_string()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_text_case() {
+        let client = test_client();
+        let feed = test_feed("Example Blog");
+        let mut feed_entry = extract_feed_entries(&feed).remove(0);
+        feed_entry.title = Some("THE STATE OF RUST IN 2024".to_string());
+        let feed_record = FeedRecord {
+            text_case: TextCase::Title,
+            default_lang: Some("en".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        // サイト名はそのまま残す
+        assert!(request
+            .record
+            .text
+            .ends_with("The State of Rust in 2024 | Example Blog"));
+        // 既定では変換しない
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(request
+            .record
+            .text
+            .ends_with("THE STATE OF RUST IN 2024 | Example Blog"));
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_display_name() {
+        let client = test_client();
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <link>https://example.com/</link>
+    <item>
+      <title>Entry title</title>
+      <link>https://example.com/entry</link>
+      <guid>https://example.com/entry</guid>
+    </item>
+  </channel>
+</rss>"#;
+        let untitled_feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let feed_entry = extract_feed_entries(&untitled_feed).remove(0);
+        let feed_record = FeedRecord {
+            display_name: Some("Example Blog".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &untitled_feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with("Entry title | Example Blog"));
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &untitled_feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with("Entry title"));
+        assert!(!request.record.text.contains(" | "));
+        // フィードにタイトルがある場合はそちらを優先する
+        let feed = test_feed("Feed title");
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with("Entry title | Feed title"));
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_thread_root() {
+        let client = test_client();
+        let feed = test_feed("Feed title");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let feed_record = FeedRecord {
+            thread_root_uri: Some("at://did:plc:test/app.bsky.feed.post/3kroot".to_string()),
+            thread_root_cid: Some("bafyreiroot".to_string()),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        let json = serde_json::to_value(&request).unwrap();
+        let root = serde_json::json!({
+            "uri": "at://did:plc:test/app.bsky.feed.post/3kroot",
+            "cid": "bafyreiroot"
+        });
+        assert_eq!(json["record"]["reply"]["root"], root);
+        assert_eq!(json["record"]["reply"]["parent"], root);
+        // ルート投稿が設定されていない場合は返信にしない
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(request.record.reply, None);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json["record"].get("reply").is_none());
+    }
+
+    #[test]
+    fn test_check_expected_account() {
+        let session = test_client().session;
+        assert!(check_expected_account(&session, None).is_ok());
+        assert!(check_expected_account(&session, Some("bot.example.com")).is_ok());
+        assert!(check_expected_account(&session, Some("@Bot.Example.com")).is_ok());
+        assert!(check_expected_account(&session, Some("did:plc:test")).is_ok());
+        let err = check_expected_account(&session, Some("other.example.com")).unwrap_err();
+        match err.downcast_ref::<BotError>() {
+            Some(BotError::UnexpectedAccount { expected, actual }) => {
+                assert_eq!(expected, "other.example.com");
+                assert_eq!(actual, "bot.example.com");
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert!(check_expected_account(&session, Some("did:plc:other")).is_err());
+    }
+
+    #[test]
+    fn test_shorten_entry_title() {
+        let long_title = "A very long entry title that goes on and on";
+        assert_eq!(
+            shorten_entry_title(long_title, Some("Short summary"), 20),
+            "Short summary"
+        );
+        assert_eq!(
+            shorten_entry_title(long_title, Some("A description that is also too long"), 20),
+            "A very long entry t…"
+        );
+        assert_eq!(
+            shorten_entry_title(long_title, None, 20),
+            "A very long entry t…"
+        );
+        assert_eq!(shorten_entry_title("Short title", None, 20), "Short title");
+        assert_eq!(
+            shorten_entry_title(long_title, Some("Short"), 0),
+            long_title
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_max_title_graphemes() {
+        let mut client = test_client();
+        client.max_title_graphemes = 10;
+        let feed = test_feed("Feed title");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: None,
+            twitter_image_url: None,
+            description: Some("Summary".to_string()),
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with("Summary | Feed title"));
+        client.max_title_graphemes = 20;
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with("Entry title | Feed title"));
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_title_separator() {
+        let mut client = test_client();
+        let feed = test_feed("Feed title");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        client.title_separator = " — ".to_string();
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with(" — Feed title"));
+        assert!(!request.record.text.contains(" | "));
+        client.title_separator = parse_title_separator("\\n");
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                None,
+                None,
+            )
+            .await;
+        assert!(request.record.text.ends_with("\nFeed title"));
+        assert!(!request.record.text.contains(" | "));
+    }
+
+    #[test]
+    fn test_format_created_at() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T03:04:05.123456Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(format_created_at(now, None), "2024-02-10T03:04:05.123456Z");
+        let offset = parse_created_at_offset("+09:00").unwrap();
+        assert_eq!(
+            format_created_at(now, offset),
+            "2024-02-10T12:04:05.123456+09:00"
+        );
+        let offset = parse_created_at_offset("+00:00").unwrap();
+        assert_eq!(
+            format_created_at(now, offset),
+            "2024-02-10T03:04:05.123456+00:00"
+        );
+        let offset = parse_created_at_offset("Z").unwrap();
+        assert_eq!(
+            format_created_at(now, offset),
+            "2024-02-10T03:04:05.123456Z"
+        );
+        assert!(parse_created_at_offset("JST").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_xrpc_response_unsupported_blob() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.createRecord"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "InvalidRequest",
+                "message": "Wrong type of blob: expected image/*, got application/octet-stream (invalid mimetype)",
+            })))
+            .mount(&server)
+            .await;
+        let response = reqwest::Client::new()
+            .post(format!(
+                "{}/xrpc/com.atproto.repo.createRecord",
+                server.uri()
+            ))
+            .send()
+            .await
+            .unwrap();
+        let err = check_xrpc_response(response).await.unwrap_err();
+        assert!(is_unsupported_blob_error(&err));
+    }
+
+    #[tokio::test]
+    async fn test_verify_credentials() {
+        use wiremock::matchers::{body_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .and(body_json(serde_json::json!({
+                "identifier": "bot.example.com",
+                "password": "app-password",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "accessJwt": "access",
+                "refreshJwt": "refresh",
+                "handle": "bot.example.com",
+                "did": "did:plc:test",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "error": "AuthenticationRequired",
+                "message": "Invalid identifier or password",
+            })))
+            .mount(&server)
+            .await;
+
+        let account =
+            verify_credentials_with_service_url(&server.uri(), "bot.example.com", "app-password")
+                .await
+                .unwrap();
+        assert_eq!(
+            account,
+            VerifiedAccount {
+                handle: "bot.example.com".to_string(),
+                did: "did:plc:test".to_string(),
+            }
+        );
+        let err = verify_credentials_with_service_url(&server.uri(), "bot.example.com", "wrong")
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<BotError>() {
+            Some(BotError::BskyApi { status, error, .. }) => {
+                assert_eq!(*status, 401);
+                assert_eq!(error.as_deref(), Some("AuthenticationRequired"));
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_refreshed_cached_session() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.refreshSession"))
+            .and(header("authorization", "Bearer cached-refresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "accessJwt": "refreshed-access",
+                "refreshJwt": "refreshed-refresh",
+                "handle": "bot.example.com",
+                "did": "did:plc:test"
+            })))
+            .mount(&server)
+            .await;
+        let cached_session = CachedSession {
+            identifier: "bot.example.com".to_string(),
+            access_jwt: "cached-access".to_string(),
+            refresh_jwt: "cached-refresh".to_string(),
+            handle: "bot.example.com".to_string(),
+            did: "did:plc:test".to_string(),
+            service_url: server.uri(),
+        };
+        let mut client =
+            BskyClient::from_cached_session(cached_session.clone(), reqwest::Client::new())
+                .unwrap();
+        assert_eq!(client.cached_session(), cached_session);
+        // 保存したものをそのまま使う場合は書き込まない
+        let session_store = MockSessionStore::default();
+        client.save_session_if_updated(&session_store).await;
+        assert_eq!(*session_store.cached_session.lock().unwrap(), None);
+
+        // 使い捨てのリフレッシュトークンを更新したら保存する
+        client.refresh_session().await.unwrap();
+        client.save_session_if_updated(&session_store).await;
+        assert_eq!(
+            *session_store.cached_session.lock().unwrap(),
+            Some(CachedSession {
+                access_jwt: "refreshed-access".to_string(),
+                refresh_jwt: "refreshed-refresh".to_string(),
+                ..cached_session
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_pds_url() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/did:plc:test"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "did:plc:test",
+                "service": [
+                    {
+                        "id": "#atproto_labeler",
+                        "type": "AtprotoLabeler",
+                        "serviceEndpoint": "https://labeler.example.com",
+                    },
+                    {
+                        "id": "#atproto_pds",
+                        "type": "AtprotoPersonalDataServer",
+                        "serviceEndpoint": "https://pds.example.com/",
+                    },
+                ],
+            })))
+            .mount(&server)
+            .await;
+        let reqwest_client = reqwest::Client::new();
+        let session = |did: &str, did_doc: Option<serde_json::Value>| -> Session {
+            serde_json::from_value(serde_json::json!({
+                "accessJwt": "access",
+                "refreshJwt": "refresh",
+                "handle": "bot.example.com",
+                "did": did,
+                "didDoc": did_doc,
+            }))
+            .unwrap()
+        };
+        // createSessionのdidDocに含まれている場合はそれを使う
+        let did_doc = serde_json::json!({
+            "id": "did:plc:other",
+            "service": [{
+                "id": "did:plc:other#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://self-hosted.example.org",
+            }],
+        });
+        assert_eq!(
+            resolve_pds_url(
+                &reqwest_client,
+                &session("did:plc:other", Some(did_doc)),
+                &server.uri(),
+                BSKY_SERVICE_URL
+            )
+            .await,
+            "https://self-hosted.example.org"
+        );
+        // 含まれていない場合はPLCディレクトリから取得する
+        assert_eq!(
+            resolve_pds_url(
+                &reqwest_client,
+                &session("did:plc:test", None),
+                &server.uri(),
+                BSKY_SERVICE_URL
+            )
+            .await,
+            "https://pds.example.com"
+        );
+        // 解決できない場合はログインに使ったホストを使う
+        assert_eq!(
+            resolve_pds_url(
+                &reqwest_client,
+                &session("did:plc:unknown", None),
+                &server.uri(),
+                "https://pds.example.net"
+            )
+            .await,
+            "https://pds.example.net"
+        );
+        let did_doc = serde_json::json!({ "id": "did:plc:test" });
+        assert_eq!(
+            resolve_pds_url(
+                &reqwest_client,
+                &session("did:plc:test", Some(did_doc)),
+                &server.uri(),
+                BSKY_SERVICE_URL
+            )
+            .await,
+            BSKY_SERVICE_URL
+        );
+    }
+
+    #[test]
+    fn test_is_unsupported_blob_error() {
+        let err: OpaqueError = BotError::BskyApi {
+            status: 400,
+            error: Some("InvalidRequest".to_string()),
+            message: Some("Record/text must not be longer than 300 graphemes".to_string()),
+        }
+        .into();
+        assert!(!is_unsupported_blob_error(&err));
+        let err: OpaqueError = BotError::BskyApi {
+            status: 500,
+            error: None,
+            message: Some("blob store unavailable".to_string()),
+        }
+        .into();
+        assert!(!is_unsupported_blob_error(&err));
+        let err: OpaqueError = BotError::BskyApi {
+            status: 400,
+            error: Some("InvalidMimeType".to_string()),
+            message: None,
+        }
+        .into();
+        assert!(is_unsupported_blob_error(&err));
+        // メッセージに"blob"や"mime"を含むだけの他のエラーは画像の形式が理由とみなさない
+        for message in [
+            "Could not find blob: bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy",
+            "Record/embed/external/thumb should be a blob ref",
+            "Invalid mime boundary",
+        ] {
+            let err: OpaqueError = BotError::BskyApi {
+                status: 400,
+                error: Some("InvalidRequest".to_string()),
+                message: Some(message.to_string()),
+            }
+            .into();
+            assert!(!is_unsupported_blob_error(&err));
+        }
+        let err: OpaqueError = BotError::BskyApi {
+            status: 400,
+            error: Some("BlobNotFound".to_string()),
+            message: Some("Wrong type of blob: expected image/*".to_string()),
+        }
+        .into();
+        assert!(!is_unsupported_blob_error(&err));
+        let err: OpaqueError = "Failed to clone request".into();
+        assert!(!is_unsupported_blob_error(&err));
+    }
+
+    #[test]
+    fn test_resize_thumbnail_reencodes_as_jpeg() {
+        let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 128]));
+        let mut png_bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(image)
+            .write_to(
+                &mut Cursor::new(&mut png_bytes),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let jpeg_bytes =
+            resize_thumbnail(&Bytes::from(png_bytes), DEFAULT_MAX_THUMB_BYTES).unwrap();
+        assert_eq!(&jpeg_bytes[..2], &[0xff, 0xd8]);
+    }
+
+    fn png_bytes() -> Bytes {
+        let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 128]));
+        let mut png_bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(image)
+            .write_to(
+                &mut Cursor::new(&mut png_bytes),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        Bytes::from(png_bytes)
+    }
+
+    // 1x1のWebP(VP8)
+    const WEBP_BASE64: &str = "UklGRiIAAABXRUJQVlA4IBYAAAAwAQCdASoBAAEADsD+JaQAA3AAAAAA";
+
+    #[test]
+    fn test_resize_thumbnail_transcodes_webp() {
+        use base64::Engine;
+
+        let webp_bytes = base64::engine::general_purpose::STANDARD
+            .decode(WEBP_BASE64)
+            .unwrap();
+        assert_eq!(
+            detect_image_content_type(&webp_bytes, "application/octet-stream"),
+            "image/webp"
+        );
+        let jpeg_bytes =
+            resize_thumbnail(&Bytes::from(webp_bytes), DEFAULT_MAX_THUMB_BYTES).unwrap();
+        assert_eq!(&jpeg_bytes[..2], &[0xff, 0xd8]);
+    }
+
+    #[tokio::test]
+    async fn test_upload_thumbnail_with_resizing_skips_undecodable_image() {
+        let server = wiremock::MockServer::start().await;
+        let mut client = BskyClient {
+            service_url: server.uri(),
+            ..test_client()
+        };
+        // デコードできないAVIFはそのままアップロードしない
+        assert!(client
+            .upload_thumbnail_with_resizing(
+                Bytes::from_static(b"not decodable"),
+                "image/avif",
+                DEFAULT_MAX_THUMB_BYTES
+            )
+            .await
+            .is_err());
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_detect_image_content_type() {
+        let png_bytes = png_bytes();
+        assert_eq!(detect_image_content_type(&png_bytes, "*/*"), "image/png");
+        // 宣言された形式より画像の内容を優先する
+        assert_eq!(
+            detect_image_content_type(&png_bytes, "image/jpeg"),
+            "image/png"
+        );
+        assert_eq!(
+            detect_image_content_type(b"unknown", "image/avif"),
+            "image/avif"
+        );
+        assert_eq!(
+            detect_image_content_type(b"unknown", "text/html"),
+            "application/octet-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_thumbnail_content_type() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let png_bytes = png_bytes();
+        for content_type in ["image/png", "image/jpeg"] {
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.repo.uploadBlob"))
+                .and(header("content-type", content_type))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "blob": {
+                        "$type": "blob",
+                        "ref": {"$link": "bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy"},
+                        "mimeType": content_type,
+                        "size": png_bytes.len(),
+                    }
+                })))
+                .mount(&server)
+                .await;
+        }
+        let mut client = BskyClient {
+            service_url: server.uri(),
+            ..test_client()
+        };
+        // 変換せずにアップロードするPNGはimage/pngとして送る
+        let response = client
+            .upload_thumbnail(png_bytes.clone(), "application/octet-stream")
+            .await
+            .unwrap();
+        assert_eq!(response.blob.mime_type, "image/png");
+        // 変換したものはJPEGとして送る
+        let response = client
+            .upload_thumbnail_with_resizing(png_bytes, "image/png", DEFAULT_MAX_THUMB_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(response.blob.mime_type, "image/jpeg");
+        let received_requests = server.received_requests().await.unwrap();
+        assert_eq!(received_requests.len(), 2);
+    }
+
+    fn upload_blob_response(link: &str, mime_type: &str, size: u64) -> UploadBlobResponse {
+        UploadBlobResponse {
+            blob: Blob {
+                r#type: "blob".to_string(),
+                r#ref: Ref {
+                    link: link.to_string(),
+                },
+                mime_type: mime_type.to_string(),
+                size,
+            },
+            uploaded_size: 1024,
+            uploaded_mime_type: "image/jpeg".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_verify_uploaded_blob() {
+        let mut client = test_client();
+        let cid = "bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy";
+        assert!(client.verify_uploaded_blob(&upload_blob_response(cid, "image/jpeg", 1024)));
+        assert!(!client.verify_uploaded_blob(&upload_blob_response("", "image/jpeg", 1024)));
+        assert!(!client.verify_uploaded_blob(&upload_blob_response(cid, "*/*", 1024)));
+        assert!(!client.verify_uploaded_blob(&upload_blob_response(cid, "image/jpeg", 0)));
+        // 有効にした場合は、送った画像と大きさや形式が違う応答も受け付けない
+        assert!(client.verify_uploaded_blob(&upload_blob_response(cid, "image/png", 512)));
+        client.verify_uploaded_blob = true;
+        assert!(client.verify_uploaded_blob(&upload_blob_response(cid, "image/jpeg", 1024)));
+        assert!(!client.verify_uploaded_blob(&upload_blob_response(cid, "image/jpeg", 512)));
+        assert!(!client.verify_uploaded_blob(&upload_blob_response(cid, "image/png", 1024)));
+    }
+
+    #[tokio::test]
+    async fn test_verified_upload_keeps_thumb() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let png_bytes = png_bytes();
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.uploadBlob"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "blob": {
+                    "$type": "blob",
+                    "ref": {"$link": "bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy"},
+                    "mimeType": "image/png",
+                    "size": png_bytes.len(),
+                }
+            })))
+            .mount(&server)
+            .await;
+        let mut client = BskyClient {
+            service_url: server.uri(),
+            verify_uploaded_blob: true,
+            ..test_client()
+        };
+        let upload = client
+            .upload_thumbnail(png_bytes, "image/png")
+            .await
+            .unwrap();
+        assert!(client.verify_uploaded_blob(&upload));
+        // uploadBlobだけで検証し、他のリクエストは送らない
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+        let feed = test_feed("Example Blog");
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: None,
+            twitter_image_url: None,
+            description: None,
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                extract_feed_entries(&feed).remove(0),
+                Some(ogp_info),
+                Some(upload),
+            )
+            .await;
+        assert!(request.record.embed.unwrap().external.thumb.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_without_unverified_thumb() {
+        let client = test_client();
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: None,
+            twitter_image_url: None,
+            description: None,
+        };
+        let upload = upload_blob_response("", "image/jpeg", 1024);
+        let upload = match client.verify_uploaded_blob(&upload) {
+            true => Some(upload),
+            false => None,
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                upload,
+            )
+            .await;
+        assert!(request.record.embed.unwrap().external.thumb.is_none());
+    }
+
+    #[test]
+    fn test_resize_thumbnail_with_feed_max_thumb_bytes() {
+        // 圧縮しにくいノイズ画像
+        let mut seed: u32 = 1;
+        let image = image::RgbImage::from_fn(256, 256, |_, _| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let [r, g, b, _] = seed.to_le_bytes();
+            image::Rgb([r, g, b])
+        });
+        let mut png_bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(
+                &mut Cursor::new(&mut png_bytes),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let png_bytes = Bytes::from(png_bytes);
+        let feed_record = FeedRecord::default();
+        let default_bytes =
+            resize_thumbnail(&png_bytes, get_max_thumb_bytes(&feed_record)).unwrap();
+        let feed_record = FeedRecord {
+            max_thumb_bytes: Some(default_bytes.len() as u64 - 1),
+            ..Default::default()
+        };
+        let recompressed_bytes =
+            resize_thumbnail(&png_bytes, get_max_thumb_bytes(&feed_record)).unwrap();
+        assert!(recompressed_bytes.len() < default_bytes.len());
+        assert!(recompressed_bytes.len() as u64 <= feed_record.max_thumb_bytes.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resize_large_thumbnail_under_limit() {
+        // 最低品質でも元の大きさでは上限を超えるノイズ画像
+        let mut seed: u32 = 1;
+        let image = image::RgbImage::from_fn(400, 400, |_, _| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let [r, g, b, _] = seed.to_le_bytes();
+            image::Rgb([r, g, b])
+        });
+        let mut png_bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(
+                &mut Cursor::new(&mut png_bytes),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let png_bytes = Bytes::from(png_bytes);
+        let max_thumb_bytes = 60000;
+        assert!(png_bytes.len() as u64 > max_thumb_bytes);
+        let resized_bytes = resize_thumbnail(&png_bytes, max_thumb_bytes).unwrap();
+        assert!(resized_bytes.len() as u64 <= max_thumb_bytes);
+        let resized_image = image::load_from_memory(&resized_bytes).unwrap();
+        assert!(resized_image.width() < 400);
+        // 上限に収めた画像はサムネイルとして使われる
+        let feed = test_feed("Example Blog");
+        let feed_record = FeedRecord {
+            max_thumb_bytes: Some(max_thumb_bytes),
+            ..Default::default()
+        };
+        let request = test_client()
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                extract_feed_entries(&feed).remove(0),
+                Some(OGPInfo {
+                    title: None,
+                    image_url: None,
+                    twitter_image_url: None,
+                    description: None,
+                }),
+                Some(upload_blob_response(
+                    "bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy",
+                    "image/jpeg",
+                    resized_bytes.len() as u64,
+                )),
+            )
+            .await;
+        assert!(request.record.embed.unwrap().external.thumb.is_some());
+        // 最小の大きさでも収まらない場合はエラーにする
+        assert!(resize_thumbnail(&png_bytes, 1000).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_format_create_record_request_with_feed_max_thumb_bytes() {
+        let client = test_client();
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: None,
+            twitter_image_url: None,
+            description: None,
+        };
+        let cid = "bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy";
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry.clone(),
+                Some(ogp_info.clone()),
+                Some(upload_blob_response(cid, "image/jpeg", 600000)),
+            )
+            .await;
+        assert!(request.record.embed.unwrap().external.thumb.is_some());
+        let feed_record = FeedRecord {
+            max_thumb_bytes: Some(500000),
+            ..Default::default()
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &feed_record,
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                Some(upload_blob_response(cid, "image/jpeg", 600000)),
+            )
+            .await;
+        assert!(request.record.embed.unwrap().external.thumb.is_none());
+    }
+
+    #[test]
+    fn test_parse_at_uri() {
+        assert_eq!(
+            parse_at_uri("at://did:plc:abc/app.bsky.feed.post/3kl3"),
+            Some(("did:plc:abc", "app.bsky.feed.post", "3kl3"))
+        );
+        assert_eq!(parse_at_uri("at://did:plc:abc/app.bsky.feed.post"), None);
+        assert_eq!(parse_at_uri("https://bsky.app/profile/abc/post/3kl3"), None);
+        assert_eq!(
+            parse_at_uri("at://did:plc:abc/app.bsky.feed.post/3kl3/extra"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_extract_embed_urls() {
+        let response: ListRecordsResponse = serde_json::from_str(
+            r#"{
+                "cursor": "3kl2",
+                "records": [
+                    {
+                        "uri": "at://did:plc:abc/app.bsky.feed.post/3kl3",
+                        "cid": "bafyreia",
+                        "value": {
+                            "$type": "app.bsky.feed.post",
+                            "text": "Announcing Rust 1.76.0 | Rust Blog",
+                            "createdAt": "2024-02-08T00:00:00.000000Z",
+                            "embed": {
+                                "$type": "app.bsky.embed.external",
+                                "external": {
+                                    "uri": "https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html",
+                                    "title": "Announcing Rust 1.76.0",
+                                    "description": ""
+                                }
+                            }
+                        }
+                    },
+                    {
+                        "uri": "at://did:plc:abc/app.bsky.feed.post/3kl2",
+                        "cid": "bafyreib",
+                        "value": {
+                            "$type": "app.bsky.feed.post",
+                            "text": "hello",
+                            "createdAt": "2024-02-07T00:00:00.000000Z"
+                        }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extract_embed_urls(response, None),
+            vec!["https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bot_marker_tag() {
+        let mut client = test_client();
+        client.bot_marker_tag = parse_bot_marker_tag("#bsky-feed-bot").unwrap();
+        let feed = test_feed("Example Blog");
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let ogp_info = OGPInfo {
+            title: Some("Entry title".to_string()),
+            image_url: None,
+            twitter_image_url: None,
+            description: None,
+        };
+        let request = client
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                feed_entry,
+                Some(ogp_info),
+                None,
+            )
+            .await;
+        // 目印は本文ではなくtagsに入る
+        assert!(!request.record.text.contains("bsky-feed-bot"));
+        let record = serde_json::to_value(&request.record).unwrap();
+        assert_eq!(record["tags"], serde_json::json!(["bsky-feed-bot"]));
+
+        // 投稿したレコードを取得した場合にも目印で見分けられる
+        let response: ListRecordsResponse = serde_json::from_value(serde_json::json!({
+            "records": [
+                { "value": record },
+                {
+                    "value": {
+                        "$type": "app.bsky.feed.post",
+                        "text": "Posted by hand",
+                        "createdAt": "2024-02-07T00:00:00.000000Z",
+                        "embed": {
+                            "$type": "app.bsky.embed.external",
+                            "external": {
+                                "uri": "https://example.com/manual",
+                                "title": "Manual",
+                                "description": ""
+                            }
+                        }
+                    }
+                }
+            ]
+        }))
+        .unwrap();
+        assert!(is_bot_post(
+            &response.records[0].value,
+            Some("bsky-feed-bot")
+        ));
+        assert!(!is_bot_post(
+            &response.records[1].value,
+            Some("bsky-feed-bot")
+        ));
+        assert_eq!(
+            extract_embed_urls(response, Some("bsky-feed-bot")),
+            vec!["https://example.com/entry".to_string()]
+        );
+
+        // 無効な場合はtagsを出力しない
+        let request = test_client()
+            .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
+                &feed,
+                extract_feed_entries(&feed).remove(0),
+                None,
+                None,
+            )
+            .await;
+        assert!(serde_json::to_value(&request.record)
+            .unwrap()
+            .get("tags")
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_bot_marker_tag() {
+        assert_eq!(parse_bot_marker_tag("").unwrap(), None);
+        assert_eq!(parse_bot_marker_tag("  ").unwrap(), None);
+        assert_eq!(
+            parse_bot_marker_tag("feedbot").unwrap(),
+            Some("feedbot".to_string())
+        );
+        assert_eq!(
+            parse_bot_marker_tag("#feedbot").unwrap(),
+            Some("feedbot".to_string())
+        );
+        assert!(parse_bot_marker_tag("feed bot").is_err());
+        assert!(parse_bot_marker_tag(&"a".repeat(65)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_posted_embed_urls() {
+        dotenv().ok();
+        let mut client = BskyClient::new(
+            &MockSessionStore::default(),
+            http_client::build_client().unwrap(),
+        )
+        .await
+        .unwrap();
+        let urls = client.list_posted_embed_urls(10).await.unwrap();
+        println!("{:?}", urls);
+    }
+
+    #[tokio::test]
+    async fn test_create_session() {
+        dotenv().ok();
+        let client = BskyClient::new(
+            &MockSessionStore::default(),
+            http_client::build_client().unwrap(),
+        )
+        .await
+        .unwrap();
+        println!("{:?}", client.session);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session() {
+        dotenv().ok();
+        let mut client = BskyClient::new(
+            &MockSessionStore::default(),
+            http_client::build_client().unwrap(),
+        )
+        .await
+        .unwrap();
+        client.refresh_session().await.unwrap();
+        println!("{:?}", client.session);
+    }
+
+    #[tokio::test]
+    async fn test_upload_thumbnail() {
+        dotenv().ok();
+        let og_image = get_og_image(
+            &http_client::build_client().unwrap(),
+            "https://www.rust-lang.org/static/images/rust-social-wide.jpg",
+        )
+        .await
+        .unwrap();
+        let mut client = BskyClient::new(
+            &MockSessionStore::default(),
+            http_client::build_client().unwrap(),
+        )
+        .await
+        .unwrap();
+        let response = client
+            .upload_thumbnail_with_resizing(
+                og_image.image,
+                &og_image.content_type,
+                DEFAULT_MAX_THUMB_BYTES,
+            )
+            .await
+            .unwrap();
+        println!("{:?}", response);
+    }
+
+    #[tokio::test]
+    async fn test_create_record_request() {
+        dotenv().ok();
+        let feed = get_feed(
+            &http_client::build_client().unwrap(),
+            "https://this-week-in-rust.org/atom.xml",
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap()
+        .feed;
         let entries = extract_feed_entries(&feed);
-        let feed_entry = entries.get(0).unwrap();
-        let (ogp_info, og_image) = extract_feed_entry_info(&feed_entry).await.unwrap();
-        let mut bsky_client = BskyClient::new().await.unwrap();
+        let feed_entry = entries.first().unwrap();
+        let (ogp_info, og_image) = extract_feed_entry_info(
+            &http_client::build_client().unwrap(),
+            feed_entry,
+            &EntryInfoOptions::from_env(),
+        )
+        .await
+        .unwrap();
+        let mut bsky_client = BskyClient::new(
+            &MockSessionStore::default(),
+            http_client::build_client().unwrap(),
+        )
+        .await
+        .unwrap();
         let upload_blog_response = match og_image {
             Some(og_image) => Some(
                 bsky_client
-                    .upload_thumbnail_with_resizing(og_image.image)
+                    .upload_thumbnail_with_resizing(
+                        og_image.image,
+                        &og_image.content_type,
+                        DEFAULT_MAX_THUMB_BYTES,
+                    )
                     .await
                     .unwrap(),
             ),
@@ -347,6 +3440,7 @@ mod tests {
         println!("{:?}", upload_blog_response);
         let create_record_request = bsky_client
             .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
                 &feed,
                 feed_entry.clone(),
                 ogp_info,
@@ -359,15 +3453,37 @@ mod tests {
     #[tokio::test]
     async fn test_post_feed_entry() {
         dotenv().ok();
-        let feed = get_feed("https://github.blog/feed/").await.unwrap();
+        let feed = get_feed(
+            &http_client::build_client().unwrap(),
+            "https://github.blog/feed/",
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap()
+        .feed;
         let entries = extract_feed_entries(&feed);
-        let feed_entry = entries.get(0).unwrap();
-        let (ogp_info, og_image) = extract_feed_entry_info(&feed_entry).await.unwrap();
-        let mut bsky_client = BskyClient::new().await.unwrap();
+        let feed_entry = entries.first().unwrap();
+        let (ogp_info, og_image) = extract_feed_entry_info(
+            &http_client::build_client().unwrap(),
+            feed_entry,
+            &EntryInfoOptions::from_env(),
+        )
+        .await
+        .unwrap();
+        let mut bsky_client = BskyClient::new(
+            &MockSessionStore::default(),
+            http_client::build_client().unwrap(),
+        )
+        .await
+        .unwrap();
         let upload_blog_response = match og_image {
             Some(og_image) => Some(
                 bsky_client
-                    .upload_thumbnail_with_resizing(og_image.image)
+                    .upload_thumbnail_with_resizing(
+                        og_image.image,
+                        &og_image.content_type,
+                        DEFAULT_MAX_THUMB_BYTES,
+                    )
                     .await
                     .unwrap(),
             ),
@@ -375,6 +3491,7 @@ mod tests {
         };
         let create_record_request = bsky_client
             .format_create_record_request_from_feed_entry(
+                &FeedRecord::default(),
                 &feed,
                 feed_entry.clone(),
                 ogp_info,
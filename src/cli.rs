@@ -0,0 +1,144 @@
+use std::{env, path::PathBuf};
+
+use aws_config::BehaviorVersion;
+
+use crate::{
+    audit::run_audit,
+    bsky::verify_credentials,
+    duplicate_feeds::merge_duplicate_feeds,
+    execute_post_now,
+    opml::{export_opml, import_opml},
+    OpaqueError, PostNowRequest,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    ImportOpml(PathBuf),
+    ExportOpml(PathBuf),
+    PostNow(PostNowRequest),
+    VerifyCredentials,
+    // --mergeを付けると重複を1件にまとめる
+    FindDuplicateFeeds { merge: bool },
+    // 登録されているすべてのフィードを投稿せずに取得し、失敗したものを報告する
+    Audit,
+}
+
+// 引数がない場合はLambdaとして起動する
+pub fn parse_command(args: &[String]) -> Result<Option<Command>, OpaqueError> {
+    let mut args = args.iter();
+    let Some(flag) = args.next() else {
+        return Ok(None);
+    };
+    let command = match flag.as_str() {
+        "--import-opml" => {
+            let path = args.next().ok_or("--import-opml requires a path")?;
+            Command::ImportOpml(PathBuf::from(path))
+        }
+        "--export-opml" => {
+            let path = args.next().ok_or("--export-opml requires a path")?;
+            Command::ExportOpml(PathBuf::from(path))
+        }
+        "--post-now" => {
+            let feed_url = args.next().ok_or("--post-now requires a feed url")?;
+            let entry = args
+                .next()
+                .ok_or("--post-now requires an entry id or url")?;
+            Command::PostNow(PostNowRequest {
+                feed_url: feed_url.clone(),
+                entry: entry.clone(),
+            })
+        }
+        "--verify-credentials" => Command::VerifyCredentials,
+        "--find-duplicate-feeds" => Command::FindDuplicateFeeds {
+            merge: match args.next().map(|arg| arg.as_str()) {
+                Some("--merge") => true,
+                Some(arg) => return Err(format!("unknown argument: {}", arg).into()),
+                None => false,
+            },
+        },
+        "--audit" => Command::Audit,
+        _ => return Err(format!("unknown argument: {}", flag).into()),
+    };
+    Ok(Some(command))
+}
+
+async fn build_dynamodb_client() -> aws_sdk_dynamodb::Client {
+    let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    aws_sdk_dynamodb::Client::new(&aws_config)
+}
+
+pub async fn run_command(command: Command) -> Result<(), OpaqueError> {
+    match command {
+        Command::ImportOpml(path) => import_opml(&build_dynamodb_client().await, &path).await,
+        Command::ExportOpml(path) => export_opml(&build_dynamodb_client().await, &path).await,
+        Command::PostNow(post_now_request) => execute_post_now(&post_now_request).await,
+        Command::VerifyCredentials => {
+            let account =
+                verify_credentials(&env::var("BSKY_IDENTIFIER")?, &env::var("BSKY_PASSWORD")?)
+                    .await?;
+            println!(
+                "Credentials are valid: {} ({})",
+                account.handle, account.did
+            );
+            Ok(())
+        }
+        Command::FindDuplicateFeeds { merge } => {
+            merge_duplicate_feeds(&build_dynamodb_client().await, merge).await
+        }
+        Command::Audit => run_audit(&build_dynamodb_client().await).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(&args(&[])).unwrap(), None);
+        assert_eq!(
+            parse_command(&args(&["--import-opml", "feeds.opml"])).unwrap(),
+            Some(Command::ImportOpml(PathBuf::from("feeds.opml")))
+        );
+        assert_eq!(
+            parse_command(&args(&["--export-opml", "backup.opml"])).unwrap(),
+            Some(Command::ExportOpml(PathBuf::from("backup.opml")))
+        );
+        assert_eq!(
+            parse_command(&args(&[
+                "--post-now",
+                "https://example.com/feed",
+                "https://example.com/1"
+            ]))
+            .unwrap(),
+            Some(Command::PostNow(PostNowRequest {
+                feed_url: "https://example.com/feed".to_string(),
+                entry: "https://example.com/1".to_string(),
+            }))
+        );
+        assert!(parse_command(&args(&["--post-now", "https://example.com/feed"])).is_err());
+        assert_eq!(
+            parse_command(&args(&["--verify-credentials"])).unwrap(),
+            Some(Command::VerifyCredentials)
+        );
+        assert_eq!(
+            parse_command(&args(&["--find-duplicate-feeds"])).unwrap(),
+            Some(Command::FindDuplicateFeeds { merge: false })
+        );
+        assert_eq!(
+            parse_command(&args(&["--find-duplicate-feeds", "--merge"])).unwrap(),
+            Some(Command::FindDuplicateFeeds { merge: true })
+        );
+        assert!(parse_command(&args(&["--find-duplicate-feeds", "--force"])).is_err());
+        assert_eq!(
+            parse_command(&args(&["--audit"])).unwrap(),
+            Some(Command::Audit)
+        );
+        assert!(parse_command(&args(&["--import-opml"])).is_err());
+        assert!(parse_command(&args(&["--unknown"])).is_err());
+    }
+}
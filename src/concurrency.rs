@@ -0,0 +1,95 @@
+use std::{future::Future, sync::Arc};
+
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::OpaqueError;
+
+// 同時に実行する数を制限しながら入力の順に始め、結果は入力の順に返す
+// 1つがpanicしても他は中断せず、その入力の結果だけをErrにする
+pub async fn map_bounded_concurrent<T, F, Fut>(
+    inputs: Vec<T>,
+    max_concurrency: usize,
+    f: F,
+) -> Result<Vec<Result<Fut::Output, OpaqueError>>, OpaqueError>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(max_concurrency.max(1)));
+    let mut join_set = JoinSet::new();
+    for (index, input) in inputs.into_iter().enumerate() {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let future = f(input);
+        join_set.spawn(async move {
+            let _permit = permit;
+            // JoinErrorからは入力の位置が分からないため、内側のタスクで受け止める
+            let output = tokio::spawn(future).await.map_err(OpaqueError::from);
+            (index, output)
+        });
+    }
+    let mut outputs = Vec::with_capacity(join_set.len());
+    while let Some(output) = join_set.join_next().await {
+        match output {
+            Ok(output) => outputs.push(output),
+            // 外側のタスクは内側の結果を返すだけなので、ここに来るのは中断されたときだけ
+            Err(err) => return Err(err.into()),
+        }
+    }
+    outputs.sort_by_key(|(index, _)| *index);
+    Ok(outputs.into_iter().map(|(_, output)| output).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_map_bounded_concurrent() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        // 先に始めたものほど時間がかかるようにして、完了順と入力順をずらす
+        let outputs = map_bounded_concurrent((0..8u64).collect(), 3, |input| {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10 * (8 - input))).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                input * 10
+            }
+        })
+        .await
+        .unwrap()
+        .into_iter()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+        assert_eq!(outputs, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_map_bounded_concurrent_continues_after_panic() {
+        let outputs = map_bounded_concurrent((0..4u64).collect(), 2, |input| async move {
+            if input == 1 {
+                panic!("task panicked");
+            }
+            // panicしたタスクより後に終わるものも中断されない
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            input * 10
+        })
+        .await
+        .unwrap();
+        assert_eq!(outputs.len(), 4);
+        assert_eq!(outputs[0].as_ref().unwrap(), &0);
+        assert!(outputs[1].is_err());
+        assert_eq!(outputs[2].as_ref().unwrap(), &20);
+        assert_eq!(outputs[3].as_ref().unwrap(), &30);
+    }
+}
@@ -0,0 +1,23 @@
+use std::{env, str::FromStr};
+
+use crate::OpaqueError;
+
+pub fn env_flag(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(value) => matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        Err(_) => default,
+    }
+}
+
+pub fn env_parse<T>(key: &str, default: T) -> Result<T, OpaqueError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Debug,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .parse::<T>()
+            .map_err(|e| format!("invalid {}, {:?}", key, e).into()),
+        Err(_) => Ok(default),
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    dynamodb::{delete_feed, list_registered_feeds, update_feed_last_posted_entry_id, FeedRecord},
+    feed::{extract_feed_entries, get_feed, FeedEntry, FeedFetchOptions},
+    http_client,
+    urls::normalize_dedup_url,
+    OpaqueError,
+};
+
+// httpとhttps、末尾のスラッシュなどの違いを無視して同じフィードかどうかを比較する
+fn normalize_feed_url(url: &str) -> String {
+    let normalized_url = normalize_dedup_url(url.trim());
+    match normalized_url.split_once("://") {
+        Some((scheme, rest)) if scheme == "http" || scheme == "https" => rest.to_string(),
+        _ => normalized_url,
+    }
+}
+
+pub fn find_duplicate_feeds(feeds: &[FeedRecord]) -> Vec<Vec<&FeedRecord>> {
+    let mut groups: BTreeMap<String, Vec<&FeedRecord>> = BTreeMap::new();
+    for feed_record in feeds {
+        groups
+            .entry(normalize_feed_url(&feed_record.url))
+            .or_default()
+            .push(feed_record);
+    }
+    groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FeedMergePlan {
+    pub keep_url: String,
+    pub last_posted_entry_id: Option<String>,
+    pub remove_urls: Vec<String>,
+}
+
+// httpsのURLを残し、フィード上で最も新しい位置まで進んでいるlast_posted_entry_idを引き継ぐ
+pub fn plan_feed_merge(group: &[&FeedRecord], entries: &[FeedEntry]) -> FeedMergePlan {
+    let keep = group
+        .iter()
+        .find(|feed_record| feed_record.url.starts_with("https://"))
+        .unwrap_or(&group[0]);
+    let entry_position = |marker_entry_id: &str| {
+        entries.iter().position(|feed_entry| {
+            feed_entry.id == marker_entry_id || feed_entry.url == marker_entry_id
+        })
+    };
+    let most_progressed = group
+        .iter()
+        .filter(|feed_record| feed_record.last_posted_entry_id.is_some())
+        .min_by_key(|feed_record| {
+            let position = feed_record
+                .last_posted_entry_id
+                .as_deref()
+                .and_then(entry_position);
+            // フィードに見つからない位置は、公開日時が新しいものを優先する
+            (
+                position.unwrap_or(usize::MAX),
+                std::cmp::Reverse(feed_record.last_posted_published),
+            )
+        });
+    FeedMergePlan {
+        keep_url: keep.url.clone(),
+        last_posted_entry_id: most_progressed
+            .and_then(|feed_record| feed_record.last_posted_entry_id.clone()),
+        remove_urls: group
+            .iter()
+            .filter(|feed_record| feed_record.url != keep.url)
+            .map(|feed_record| feed_record.url.clone())
+            .collect(),
+    }
+}
+
+pub async fn merge_duplicate_feeds(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    merge: bool,
+) -> Result<(), OpaqueError> {
+    let feeds = list_registered_feeds(dynamodb_client).await?;
+    let groups = find_duplicate_feeds(&feeds);
+    if groups.is_empty() {
+        println!("No duplicate feeds found");
+        return Ok(());
+    }
+    let reqwest_client = http_client::build_client()?;
+    for group in groups {
+        let urls = group
+            .iter()
+            .map(|feed_record| feed_record.url.as_str())
+            .collect::<Vec<_>>();
+        println!("Duplicate feeds: {}", urls.join(", "));
+        if !merge {
+            continue;
+        }
+        let fetch_options = FeedFetchOptions {
+            use_cookies: group[0].use_cookies,
+            ..Default::default()
+        };
+        let entries = match get_feed(&reqwest_client, &group[0].url, &fetch_options).await {
+            Ok(fetched_feed) => extract_feed_entries(&fetched_feed.feed),
+            Err(err) => {
+                println!(
+                    "Failed to fetch feed, merging by last_posted_published: {:?}",
+                    err
+                );
+                Vec::new()
+            }
+        };
+        let plan = plan_feed_merge(&group, &entries);
+        if let Some(last_posted_entry_id) = &plan.last_posted_entry_id {
+            update_feed_last_posted_entry_id(dynamodb_client, &plan.keep_url, last_posted_entry_id)
+                .await?;
+        }
+        for remove_url in &plan.remove_urls {
+            delete_feed(dynamodb_client, remove_url).await?;
+        }
+        println!(
+            "Merged into {} (last_posted_entry_id: {:?}), removed: {}",
+            plan.keep_url,
+            plan.last_posted_entry_id,
+            plan.remove_urls.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_record(url: &str, last_posted_entry_id: Option<&str>) -> FeedRecord {
+        FeedRecord {
+            url: url.to_string(),
+            last_posted_entry_id: last_posted_entry_id.map(|id| id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn feed_entry(id: &str) -> FeedEntry {
+        FeedEntry {
+            id: id.to_string(),
+            url: format!("https://example.com/{}", id),
+            title: None,
+            published: None,
+            updated: None,
+            summary: None,
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        }
+    }
+
+    #[test]
+    fn test_find_duplicate_feeds() {
+        let feeds = vec![
+            feed_record("http://example.com/feed.xml", Some("1")),
+            feed_record("https://blog.rust-lang.org/feed.xml", None),
+            feed_record("https://Example.com/feed.xml/", Some("2")),
+        ];
+        let groups = find_duplicate_feeds(&feeds);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0]
+                .iter()
+                .map(|feed_record| feed_record.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "http://example.com/feed.xml",
+                "https://Example.com/feed.xml/"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_feed_merge() {
+        let feeds = vec![
+            feed_record("http://example.com/feed.xml", Some("2")),
+            feed_record("https://example.com/feed.xml/", Some("1")),
+        ];
+        let groups = find_duplicate_feeds(&feeds);
+        let entries = vec![feed_entry("3"), feed_entry("2"), feed_entry("1")];
+        // httpのURLのほうが新しい位置まで投稿しているため、そちらの位置を引き継ぐ
+        assert_eq!(
+            plan_feed_merge(&groups[0], &entries),
+            FeedMergePlan {
+                keep_url: "https://example.com/feed.xml/".to_string(),
+                last_posted_entry_id: Some("2".to_string()),
+                remove_urls: vec!["http://example.com/feed.xml".to_string()],
+            }
+        );
+        let feeds = vec![
+            feed_record("http://example.com/feed.xml", None),
+            feed_record(
+                "https://example.com/feed.xml",
+                Some("https://example.com/1"),
+            ),
+        ];
+        let groups = find_duplicate_feeds(&feeds);
+        let plan = plan_feed_merge(&groups[0], &[]);
+        assert_eq!(
+            plan.last_posted_entry_id.as_deref(),
+            Some("https://example.com/1")
+        );
+    }
+}
@@ -1,10 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
 use aws_sdk_dynamodb::{operation::update_item::UpdateItemOutput, types::AttributeValue};
+use chrono::{DateTime, Utc};
+use tracing::warn;
 
-use crate::OpaqueError;
+use crate::{
+    lang::parse_category_langs,
+    pause::PauseState,
+    posted_entries::PostedEntryRecord,
+    rate_limit::PostRateWindow,
+    session_cache::CachedSession,
+    text::{parse_text_case, TextCase},
+    urls::parse_domain_list,
+    OpaqueError,
+};
 
 static TABLE_NAME: &str = "bsky-feed-bot-registered-feeds";
+// フィードをまたいだ状態を保存するテーブル
+pub static CONFIG_TABLE_NAME: &str = "bsky-feed-bot-config";
+static POST_RATE_WINDOW_KEY: &str = "post_rate_window";
+// 緊急時にすべての投稿を止めるための設定
+static PAUSED_KEY: &str = "paused";
+// Blueskyのセッションを実行をまたいで使い回すための設定
+static BSKY_SESSION_KEY: &str = "bsky_session";
+// エントリーのidと投稿のURIの対応を保存するテーブル
+// フィードが違えばidが重なることがあるため、feed_urlとentry_idの組をキーにする
+static POSTED_ENTRIES_TABLE_NAME: &str = "bsky-feed-bot-posted-entries";
 
 fn get_string_from_attribute_value_map(
     map: &HashMap<String, AttributeValue>,
@@ -22,40 +43,336 @@ fn get_optional_string_from_attribute_value_map(
     map: &HashMap<String, AttributeValue>,
     key: &str,
 ) -> Result<Option<String>, OpaqueError> {
-    let value = map.get(key).and_then(|v| v.as_s().ok()).map(|s| s.clone());
+    let value = match map.get(key) {
+        Some(AttributeValue::S(value)) => Some(value.clone()),
+        // 誤ってNumber型で書き込まれた値を読み飛ばすと全件再投稿してしまうため、文字列として扱う
+        Some(AttributeValue::N(value)) => {
+            warn!(key, value, "Coerced attribute from N to S");
+            Some(value.clone())
+        }
+        _ => None,
+    };
     Ok(value)
 }
 
+fn get_optional_number_from_attribute_value_map<T>(
+    map: &HashMap<String, AttributeValue>,
+    key: &str,
+) -> Result<Option<T>, OpaqueError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Debug,
+{
+    match map.get(key).and_then(|v| v.as_n().ok()) {
+        Some(value) => {
+            let value = value
+                .parse::<T>()
+                .map_err(|e| format!("invalid {}, {:?}", key, e))?;
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+
+fn get_optional_datetime_from_attribute_value_map(
+    map: &HashMap<String, AttributeValue>,
+    key: &str,
+) -> Result<Option<DateTime<Utc>>, OpaqueError> {
+    match get_optional_string_from_attribute_value_map(map, key)? {
+        Some(value) => {
+            let value = DateTime::parse_from_rfc3339(&value)
+                .map_err(|e| format!("invalid {}, {:?}", key, e))?;
+            Ok(Some(value.with_timezone(&Utc)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn get_optional_bool_from_attribute_value_map(
+    map: &HashMap<String, AttributeValue>,
+    key: &str,
+) -> Result<Option<bool>, OpaqueError> {
+    let value = map.get(key).and_then(|v| v.as_bool().ok()).copied();
+    Ok(value)
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct FeedRecord {
     pub url: String,
     pub last_posted_entry_id: Option<String>,
+    pub title: Option<String>,
+    // 本文(summary)がこの文字数に満たないエントリーは投稿せずにスキップする
+    pub min_content_length: Option<usize>,
+    // この日時までは投稿せず、last_posted_entry_idの更新だけを行う
+    pub activate_after: Option<DateTime<Utc>>,
+    // 言語を判定できない場合に投稿のlangsに設定する言語
+    pub default_lang: Option<String>,
+    // 1回の投稿ごとに空ける間隔(分)。実行をまたいで投稿を分散させる
+    pub post_interval_minutes: Option<i64>,
+    pub next_post_allowed_at: Option<DateTime<Utc>>,
+    // フィード自体にタイトルがない場合に投稿で使うフィード名
+    pub display_name: Option<String>,
+    // コメント(議論)ページへのリンクを投稿に追加する
+    pub post_comments_link: bool,
+    // フィードから取得したWebSubのハブとselfのURL
+    pub hub_url: Option<String>,
+    pub self_url: Option<String>,
+    // 条件付きリクエストのために保存する、前回取得したときのETagとLast-Modified
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // サムネイルを圧縮し直す、または添付しないと判断するサイズ(バイト)
+    pub max_thumb_bytes: Option<u64>,
+    // 大きいものから先に処理する(未設定は0)
+    pub priority: i64,
+    // og:imageを記事と同じホストか許可リストのドメインに限る
+    pub og_image_same_origin: bool,
+    pub og_image_host_allowlist: Vec<String>,
+    // カテゴリーから投稿の言語を決める対応
+    pub category_langs: Vec<(String, String)>,
+    // 最後に投稿したエントリーの公開日時
+    pub last_posted_published: Option<DateTime<Utc>>,
+    // 公開日時が最後に投稿したエントリー以前のエントリーを、後から差し込まれたものとして読み飛ばす
+    pub skip_entries_older_than_last_posted: bool,
+    // 設定されている場合、各投稿をこの投稿への返信にする
+    pub thread_root_uri: Option<String>,
+    pub thread_root_cid: Option<String>,
+    // フィードのサーバーに429を返された場合、この日時まで取得しない
+    pub next_fetch_allowed_at: Option<DateTime<Utc>>,
+    // 投稿本文から絵文字を取り除く
+    pub strip_emoji: bool,
+    // 投稿済みのエントリーが修正された場合、以前の投稿を削除して投稿し直す
+    pub repost_updated_entries: bool,
+    // 登録した日時。これより前に公開されたエントリーは投稿しない
+    pub posts_after: Option<DateTime<Utc>>,
+    // 連続して処理に失敗した回数と、次に処理を試みる日時
+    pub consecutive_failures: u32,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    // セッションCookieを要求するフィードのため、取得中はCookieを保持する
+    pub use_cookies: bool,
+    // 投稿本文のエントリーのタイトルの大文字・小文字を変換する
+    pub text_case: TextCase,
+    // 転載を禁じる著作権表示(rights)のあるフィード・エントリーを投稿しない
+    pub skip_restrictive_rights: bool,
+    // 公開日時が未来の予約投稿のエントリーは、その日時を過ぎた後の実行まで投稿しない
+    pub defer_future_entries: bool,
+    // og:descriptionもエントリーの本文もない場合に埋め込みカードの説明文に使う
+    pub default_embed_description: Option<String>,
+    // "🦀 {title}"の形式の投稿本文のテンプレート。未設定の場合は"タイトル | フィード名"にする
+    pub post_template: Option<String>,
+}
+
+fn parse_feed_record(item: &HashMap<String, AttributeValue>) -> Result<FeedRecord, OpaqueError> {
+    let url = get_string_from_attribute_value_map(item, "url")?;
+    let last_posted_entry_id =
+        get_optional_string_from_attribute_value_map(item, "last_posted_entry_id")?;
+    let title = get_optional_string_from_attribute_value_map(item, "title")?;
+    let min_content_length =
+        get_optional_number_from_attribute_value_map(item, "min_content_length")?;
+    let activate_after = get_optional_datetime_from_attribute_value_map(item, "activate_after")?;
+    let default_lang = get_optional_string_from_attribute_value_map(item, "default_lang")?;
+    let post_interval_minutes =
+        get_optional_number_from_attribute_value_map(item, "post_interval_minutes")?;
+    let next_post_allowed_at =
+        get_optional_datetime_from_attribute_value_map(item, "next_post_allowed_at")?;
+    let display_name = get_optional_string_from_attribute_value_map(item, "display_name")?;
+    let post_comments_link =
+        get_optional_bool_from_attribute_value_map(item, "post_comments_link")?.unwrap_or(false);
+    let hub_url = get_optional_string_from_attribute_value_map(item, "hub_url")?;
+    let self_url = get_optional_string_from_attribute_value_map(item, "self_url")?;
+    let etag = get_optional_string_from_attribute_value_map(item, "etag")?;
+    let last_modified = get_optional_string_from_attribute_value_map(item, "last_modified")?;
+    let max_thumb_bytes = get_optional_number_from_attribute_value_map(item, "max_thumb_bytes")?;
+    let priority = get_optional_number_from_attribute_value_map(item, "priority")?.unwrap_or(0);
+    let og_image_same_origin =
+        get_optional_bool_from_attribute_value_map(item, "og_image_same_origin")?.unwrap_or(false);
+    let og_image_host_allowlist =
+        get_optional_string_from_attribute_value_map(item, "og_image_host_allowlist")?
+            .map(|domains| parse_domain_list(&domains))
+            .unwrap_or_default();
+    let category_langs = get_optional_string_from_attribute_value_map(item, "category_langs")?
+        .map(|category_langs| parse_category_langs(&category_langs))
+        .unwrap_or_default();
+    let last_posted_published =
+        get_optional_datetime_from_attribute_value_map(item, "last_posted_published")?;
+    let skip_entries_older_than_last_posted =
+        get_optional_bool_from_attribute_value_map(item, "skip_entries_older_than_last_posted")?
+            .unwrap_or(false);
+    let thread_root_uri = get_optional_string_from_attribute_value_map(item, "thread_root_uri")?;
+    let thread_root_cid = get_optional_string_from_attribute_value_map(item, "thread_root_cid")?;
+    let next_fetch_allowed_at =
+        get_optional_datetime_from_attribute_value_map(item, "next_fetch_allowed_at")?;
+    let strip_emoji =
+        get_optional_bool_from_attribute_value_map(item, "strip_emoji")?.unwrap_or(false);
+    let repost_updated_entries =
+        get_optional_bool_from_attribute_value_map(item, "repost_updated_entries")?
+            .unwrap_or(false);
+    let posts_after = get_optional_datetime_from_attribute_value_map(item, "posts_after")?;
+    let consecutive_failures =
+        get_optional_number_from_attribute_value_map(item, "consecutive_failures")?.unwrap_or(0);
+    let next_attempt_at = get_optional_datetime_from_attribute_value_map(item, "next_attempt_at")?;
+    let use_cookies =
+        get_optional_bool_from_attribute_value_map(item, "use_cookies")?.unwrap_or(false);
+    let text_case = get_optional_string_from_attribute_value_map(item, "text_case")?
+        .map(|text_case| parse_text_case(&text_case))
+        .unwrap_or_default();
+    let skip_restrictive_rights =
+        get_optional_bool_from_attribute_value_map(item, "skip_restrictive_rights")?
+            .unwrap_or(false);
+    let defer_future_entries =
+        get_optional_bool_from_attribute_value_map(item, "defer_future_entries")?.unwrap_or(false);
+    let default_embed_description =
+        get_optional_string_from_attribute_value_map(item, "default_embed_description")?
+            .filter(|description| !description.trim().is_empty());
+    let post_template = get_optional_string_from_attribute_value_map(item, "post_template")?
+        .filter(|post_template| !post_template.trim().is_empty());
+    Ok(FeedRecord {
+        url,
+        last_posted_entry_id,
+        title,
+        min_content_length,
+        activate_after,
+        default_lang,
+        post_interval_minutes,
+        next_post_allowed_at,
+        display_name,
+        post_comments_link,
+        hub_url,
+        self_url,
+        etag,
+        last_modified,
+        max_thumb_bytes,
+        priority,
+        og_image_same_origin,
+        og_image_host_allowlist,
+        category_langs,
+        last_posted_published,
+        skip_entries_older_than_last_posted,
+        thread_root_uri,
+        thread_root_cid,
+        next_fetch_allowed_at,
+        strip_emoji,
+        repost_updated_entries,
+        posts_after,
+        consecutive_failures,
+        next_attempt_at,
+        use_cookies,
+        text_case,
+        skip_restrictive_rights,
+        defer_future_entries,
+        default_embed_description,
+        post_template,
+    })
+}
+
+// scanの1ページ分の結果。続きがある場合は次のページの開始キーを持つ
+pub struct ScanPage {
+    pub items: Vec<HashMap<String, AttributeValue>>,
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+}
+
+pub trait FeedScanner {
+    async fn scan_page(
+        &self,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<ScanPage, OpaqueError>;
+}
+
+pub struct DynamoDbFeedScanner<'a> {
+    pub client: &'a aws_sdk_dynamodb::Client,
+}
+
+impl FeedScanner for DynamoDbFeedScanner<'_> {
+    async fn scan_page(
+        &self,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<ScanPage, OpaqueError> {
+        let scan_output = self
+            .client
+            .scan()
+            .table_name(TABLE_NAME)
+            .select(aws_sdk_dynamodb::types::Select::AllAttributes)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        Ok(ScanPage {
+            items: scan_output.items.ok_or("no items")?,
+            last_evaluated_key: scan_output.last_evaluated_key,
+        })
+    }
+}
+
+// scanは1回で最大1MBまでしか返さないため、last_evaluated_keyがなくなるまで続きを取得する
+pub async fn scan_all_items(
+    scanner: &impl FeedScanner,
+) -> Result<Vec<HashMap<String, AttributeValue>>, OpaqueError> {
+    let mut items = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let scan_page = scanner.scan_page(exclusive_start_key).await?;
+        items.extend(scan_page.items);
+        match scan_page.last_evaluated_key {
+            Some(last_evaluated_key) => exclusive_start_key = Some(last_evaluated_key),
+            None => return Ok(items),
+        }
+    }
 }
 
 pub async fn list_registered_feeds(
     dynamodb_client: &aws_sdk_dynamodb::Client,
 ) -> Result<Vec<FeedRecord>, OpaqueError> {
-    let scan_output = dynamodb_client
-        .scan()
-        .table_name(TABLE_NAME)
-        .select(aws_sdk_dynamodb::types::Select::AllAttributes)
-        .send()
-        .await?;
-    let items: Vec<HashMap<String, AttributeValue>> = scan_output.items.ok_or("no items")?;
+    let items = scan_all_items(&DynamoDbFeedScanner {
+        client: dynamodb_client,
+    })
+    .await?;
     let registered_feeds: Vec<FeedRecord> = items
         .iter()
-        .map(|item| {
-            let url = get_string_from_attribute_value_map(item, "url")?;
-            let last_posted_entry_id =
-                get_optional_string_from_attribute_value_map(item, "last_posted_entry_id")?;
-            Ok(FeedRecord {
-                url,
-                last_posted_entry_id,
-            })
-        })
+        .map(parse_feed_record)
         .collect::<Result<Vec<FeedRecord>, OpaqueError>>()?;
     Ok(registered_feeds)
 }
 
+// 既に登録済みのフィードは上書きせずにfalseを返す
+// 登録前に公開された記事を投稿しないよう、登録日時をposts_afterに記録する
+pub async fn put_feed(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    title: Option<&str>,
+    registered_at: DateTime<Utc>,
+) -> Result<bool, OpaqueError> {
+    let mut request = dynamodb_client
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item("url", AttributeValue::S(feed_url.to_string()))
+        .item("posts_after", AttributeValue::S(registered_at.to_rfc3339()))
+        .condition_expression("attribute_not_exists(#url)")
+        .expression_attribute_names("#url", "url");
+    if let Some(title) = title {
+        request = request.item("title", AttributeValue::S(title.to_string()));
+    }
+    match request.send().await {
+        Ok(_) => Ok(true),
+        Err(err) => match err.as_service_error() {
+            Some(service_error) if service_error.is_conditional_check_failed_exception() => {
+                Ok(false)
+            }
+            _ => Err(err.into()),
+        },
+    }
+}
+
+pub async fn delete_feed(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+) -> Result<(), OpaqueError> {
+    dynamodb_client
+        .delete_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()))
+        .send()
+        .await?;
+    Ok(())
+}
+
 pub async fn update_feed_last_posted_entry_id(
     dynamodb_client: &aws_sdk_dynamodb::Client,
     feed_url: &str,
@@ -74,3 +391,696 @@ pub async fn update_feed_last_posted_entry_id(
         .await?;
     Ok(update_output)
 }
+
+pub async fn update_feed_last_posted_published(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    last_posted_published: &DateTime<Utc>,
+) -> Result<UpdateItemOutput, OpaqueError> {
+    let update_output = dynamodb_client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()))
+        .update_expression("SET last_posted_published = :last_posted_published")
+        .expression_attribute_values(
+            ":last_posted_published",
+            AttributeValue::S(last_posted_published.to_rfc3339()),
+        )
+        .send()
+        .await?;
+    Ok(update_output)
+}
+
+pub async fn update_feed_next_fetch_allowed_at(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    next_fetch_allowed_at: &DateTime<Utc>,
+) -> Result<UpdateItemOutput, OpaqueError> {
+    let update_output = dynamodb_client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()))
+        .update_expression("SET next_fetch_allowed_at = :next_fetch_allowed_at")
+        .expression_attribute_values(
+            ":next_fetch_allowed_at",
+            AttributeValue::S(next_fetch_allowed_at.to_rfc3339()),
+        )
+        .send()
+        .await?;
+    Ok(update_output)
+}
+
+pub async fn update_feed_next_post_allowed_at(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    next_post_allowed_at: &DateTime<Utc>,
+) -> Result<UpdateItemOutput, OpaqueError> {
+    let update_output = dynamodb_client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()))
+        .update_expression("SET next_post_allowed_at = :next_post_allowed_at")
+        .expression_attribute_values(
+            ":next_post_allowed_at",
+            AttributeValue::S(next_post_allowed_at.to_rfc3339()),
+        )
+        .send()
+        .await?;
+    Ok(update_output)
+}
+
+// 成功した場合は失敗回数を0に戻し、next_attempt_atを削除する
+pub async fn update_feed_failure_state(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    consecutive_failures: u32,
+    next_attempt_at: Option<&DateTime<Utc>>,
+) -> Result<UpdateItemOutput, OpaqueError> {
+    let request = dynamodb_client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()))
+        .expression_attribute_values(
+            ":consecutive_failures",
+            AttributeValue::N(consecutive_failures.to_string()),
+        );
+    let request = match next_attempt_at {
+        Some(next_attempt_at) => request
+            .update_expression(
+                "SET consecutive_failures = :consecutive_failures, next_attempt_at = :next_attempt_at",
+            )
+            .expression_attribute_values(
+                ":next_attempt_at",
+                AttributeValue::S(next_attempt_at.to_rfc3339()),
+            ),
+        None => request.update_expression(
+            "SET consecutive_failures = :consecutive_failures REMOVE next_attempt_at",
+        ),
+    };
+    let update_output = request.send().await?;
+    Ok(update_output)
+}
+
+pub async fn update_feed_links(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    hub_url: Option<&str>,
+    self_url: Option<&str>,
+) -> Result<UpdateItemOutput, OpaqueError> {
+    let mut request = dynamodb_client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()));
+    let mut assignments = Vec::new();
+    if let Some(hub_url) = hub_url {
+        assignments.push("hub_url = :hub_url");
+        request =
+            request.expression_attribute_values(":hub_url", AttributeValue::S(hub_url.to_string()));
+    }
+    if let Some(self_url) = self_url {
+        assignments.push("self_url = :self_url");
+        request = request
+            .expression_attribute_values(":self_url", AttributeValue::S(self_url.to_string()));
+    }
+    if assignments.is_empty() {
+        return Err("no feed links to update".into());
+    }
+    let update_output = request
+        .update_expression(format!("SET {}", assignments.join(", ")))
+        .send()
+        .await?;
+    Ok(update_output)
+}
+
+// サーバーが送らなくなった値は削除し、古い値で条件付きリクエストをしないようにする
+pub async fn update_feed_validators(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Result<UpdateItemOutput, OpaqueError> {
+    let mut request = dynamodb_client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key("url", AttributeValue::S(feed_url.to_string()));
+    let mut assignments = Vec::new();
+    let mut removals = Vec::new();
+    match etag {
+        Some(etag) => {
+            assignments.push("etag = :etag");
+            request =
+                request.expression_attribute_values(":etag", AttributeValue::S(etag.to_string()));
+        }
+        None => removals.push("etag"),
+    }
+    match last_modified {
+        Some(last_modified) => {
+            assignments.push("last_modified = :last_modified");
+            request = request.expression_attribute_values(
+                ":last_modified",
+                AttributeValue::S(last_modified.to_string()),
+            );
+        }
+        None => removals.push("last_modified"),
+    }
+    let mut update_expression = Vec::new();
+    if !assignments.is_empty() {
+        update_expression.push(format!("SET {}", assignments.join(", ")));
+    }
+    if !removals.is_empty() {
+        update_expression.push(format!("REMOVE {}", removals.join(", ")));
+    }
+    let update_output = request
+        .update_expression(update_expression.join(" "))
+        .send()
+        .await?;
+    Ok(update_output)
+}
+
+pub async fn get_post_rate_window(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+) -> Result<Option<PostRateWindow>, OpaqueError> {
+    let get_item_output = dynamodb_client
+        .get_item()
+        .table_name(CONFIG_TABLE_NAME)
+        .key("key", AttributeValue::S(POST_RATE_WINDOW_KEY.to_string()))
+        .send()
+        .await?;
+    match get_item_output.item {
+        Some(item) => parse_post_rate_window(&item),
+        None => Ok(None),
+    }
+}
+
+fn parse_post_rate_window(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<Option<PostRateWindow>, OpaqueError> {
+    let started_at = get_optional_datetime_from_attribute_value_map(item, "started_at")?;
+    let post_count = get_optional_number_from_attribute_value_map(item, "post_count")?;
+    match (started_at, post_count) {
+        (Some(started_at), Some(post_count)) => Ok(Some(PostRateWindow {
+            started_at,
+            post_count,
+        })),
+        _ => Ok(None),
+    }
+}
+
+pub async fn get_pause_state(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+) -> Result<PauseState, OpaqueError> {
+    let get_item_output = dynamodb_client
+        .get_item()
+        .table_name(CONFIG_TABLE_NAME)
+        .key("key", AttributeValue::S(PAUSED_KEY.to_string()))
+        // 設定した直後の実行から止まるよう、強い整合性で読み込む
+        .consistent_read(true)
+        .send()
+        .await?;
+    match get_item_output.item {
+        Some(item) => parse_pause_state(&item),
+        None => Ok(PauseState::default()),
+    }
+}
+
+fn parse_pause_state(item: &HashMap<String, AttributeValue>) -> Result<PauseState, OpaqueError> {
+    let paused = get_optional_bool_from_attribute_value_map(item, "paused")?.unwrap_or(false);
+    let reason = get_optional_string_from_attribute_value_map(item, "reason")?;
+    Ok(PauseState { paused, reason })
+}
+
+pub async fn put_post_rate_window(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    post_rate_window: &PostRateWindow,
+) -> Result<(), OpaqueError> {
+    dynamodb_client
+        .put_item()
+        .table_name(CONFIG_TABLE_NAME)
+        .item("key", AttributeValue::S(POST_RATE_WINDOW_KEY.to_string()))
+        .item(
+            "started_at",
+            AttributeValue::S(post_rate_window.started_at.to_rfc3339()),
+        )
+        .item(
+            "post_count",
+            AttributeValue::N(post_rate_window.post_count.to_string()),
+        )
+        .send()
+        .await?;
+    Ok(())
+}
+
+pub async fn get_cached_session(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+) -> Result<Option<CachedSession>, OpaqueError> {
+    let get_item_output = dynamodb_client
+        .get_item()
+        .table_name(CONFIG_TABLE_NAME)
+        .key("key", AttributeValue::S(BSKY_SESSION_KEY.to_string()))
+        // 直前の実行で更新したリフレッシュトークンを読むため、強い整合性で読み込む
+        .consistent_read(true)
+        .send()
+        .await?;
+    match get_item_output.item {
+        Some(item) => Ok(Some(parse_cached_session(&item)?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_cached_session(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<CachedSession, OpaqueError> {
+    Ok(CachedSession {
+        identifier: get_string_from_attribute_value_map(item, "identifier")?,
+        access_jwt: get_string_from_attribute_value_map(item, "access_jwt")?,
+        refresh_jwt: get_string_from_attribute_value_map(item, "refresh_jwt")?,
+        handle: get_string_from_attribute_value_map(item, "handle")?,
+        did: get_string_from_attribute_value_map(item, "did")?,
+        service_url: get_string_from_attribute_value_map(item, "service_url")?,
+    })
+}
+
+pub async fn put_cached_session(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    cached_session: &CachedSession,
+) -> Result<(), OpaqueError> {
+    dynamodb_client
+        .put_item()
+        .table_name(CONFIG_TABLE_NAME)
+        .item("key", AttributeValue::S(BSKY_SESSION_KEY.to_string()))
+        .item(
+            "identifier",
+            AttributeValue::S(cached_session.identifier.clone()),
+        )
+        .item(
+            "access_jwt",
+            AttributeValue::S(cached_session.access_jwt.clone()),
+        )
+        .item(
+            "refresh_jwt",
+            AttributeValue::S(cached_session.refresh_jwt.clone()),
+        )
+        .item("handle", AttributeValue::S(cached_session.handle.clone()))
+        .item("did", AttributeValue::S(cached_session.did.clone()))
+        .item(
+            "service_url",
+            AttributeValue::S(cached_session.service_url.clone()),
+        )
+        .send()
+        .await?;
+    Ok(())
+}
+
+pub async fn get_posted_entry(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    feed_url: &str,
+    entry_id: &str,
+) -> Result<Option<PostedEntryRecord>, OpaqueError> {
+    let get_item_output = dynamodb_client
+        .get_item()
+        .table_name(POSTED_ENTRIES_TABLE_NAME)
+        .key("feed_url", AttributeValue::S(feed_url.to_string()))
+        .key("entry_id", AttributeValue::S(entry_id.to_string()))
+        .send()
+        .await?;
+    match get_item_output.item {
+        Some(item) => Ok(Some(parse_posted_entry_record(&item)?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_posted_entry_record(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<PostedEntryRecord, OpaqueError> {
+    let feed_url = get_string_from_attribute_value_map(item, "feed_url")?;
+    let entry_id = get_string_from_attribute_value_map(item, "entry_id")?;
+    let post_uri = get_string_from_attribute_value_map(item, "post_uri")?;
+    let updated = get_optional_datetime_from_attribute_value_map(item, "updated")?;
+    Ok(PostedEntryRecord {
+        feed_url,
+        entry_id,
+        post_uri,
+        updated,
+    })
+}
+
+pub async fn put_posted_entry(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    posted_entry: &PostedEntryRecord,
+) -> Result<(), OpaqueError> {
+    let mut request = dynamodb_client
+        .put_item()
+        .table_name(POSTED_ENTRIES_TABLE_NAME)
+        .item("feed_url", AttributeValue::S(posted_entry.feed_url.clone()))
+        .item("entry_id", AttributeValue::S(posted_entry.entry_id.clone()))
+        .item("post_uri", AttributeValue::S(posted_entry.post_uri.clone()));
+    if let Some(updated) = posted_entry.updated {
+        request = request.item("updated", AttributeValue::S(updated.to_rfc3339()));
+    }
+    request.send().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // 開始キーごとにページを返すscan
+    struct MockFeedScanner {
+        pages: Vec<ScanPage>,
+        requested_start_keys: Mutex<Vec<Option<HashMap<String, AttributeValue>>>>,
+    }
+
+    impl FeedScanner for MockFeedScanner {
+        async fn scan_page(
+            &self,
+            exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+        ) -> Result<ScanPage, OpaqueError> {
+            let page_index = self.requested_start_keys.lock().unwrap().len();
+            self.requested_start_keys
+                .lock()
+                .unwrap()
+                .push(exclusive_start_key);
+            let page = &self.pages[page_index];
+            Ok(ScanPage {
+                items: page.items.clone(),
+                last_evaluated_key: page.last_evaluated_key.clone(),
+            })
+        }
+    }
+
+    fn feed_item(url: &str) -> HashMap<String, AttributeValue> {
+        HashMap::from([("url".to_string(), AttributeValue::S(url.to_string()))])
+    }
+
+    #[tokio::test]
+    async fn test_scan_all_items() {
+        let scanner = MockFeedScanner {
+            pages: vec![
+                ScanPage {
+                    items: vec![
+                        feed_item("https://example.com/1.xml"),
+                        feed_item("https://example.com/2.xml"),
+                    ],
+                    last_evaluated_key: Some(feed_item("https://example.com/2.xml")),
+                },
+                ScanPage {
+                    items: vec![feed_item("https://example.com/3.xml")],
+                    last_evaluated_key: None,
+                },
+            ],
+            requested_start_keys: Mutex::new(Vec::new()),
+        };
+        let feed_records = scan_all_items(&scanner)
+            .await
+            .unwrap()
+            .iter()
+            .map(parse_feed_record)
+            .collect::<Result<Vec<FeedRecord>, OpaqueError>>()
+            .unwrap();
+        assert_eq!(
+            feed_records
+                .iter()
+                .map(|feed_record| feed_record.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "https://example.com/1.xml",
+                "https://example.com/2.xml",
+                "https://example.com/3.xml"
+            ]
+        );
+        // 2ページ目は1ページ目のlast_evaluated_keyから取得する
+        assert_eq!(
+            *scanner.requested_start_keys.lock().unwrap(),
+            vec![None, Some(feed_item("https://example.com/2.xml"))]
+        );
+    }
+
+    #[test]
+    fn test_parse_feed_record() {
+        let item = HashMap::from([
+            (
+                "url".to_string(),
+                AttributeValue::S("https://blog.rust-lang.org/feed.xml".to_string()),
+            ),
+            (
+                "last_posted_entry_id".to_string(),
+                AttributeValue::S(
+                    "https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html".to_string(),
+                ),
+            ),
+            (
+                "min_content_length".to_string(),
+                AttributeValue::N("200".to_string()),
+            ),
+            (
+                "activate_after".to_string(),
+                AttributeValue::S("2024-02-10T09:00:00+09:00".to_string()),
+            ),
+            (
+                "post_interval_minutes".to_string(),
+                AttributeValue::N("60".to_string()),
+            ),
+            (
+                "next_post_allowed_at".to_string(),
+                AttributeValue::S("2024-02-10T01:00:00Z".to_string()),
+            ),
+            (
+                "max_thumb_bytes".to_string(),
+                AttributeValue::N("500000".to_string()),
+            ),
+            ("priority".to_string(), AttributeValue::N("10".to_string())),
+            (
+                "og_image_host_allowlist".to_string(),
+                AttributeValue::S("cdn.example.net, images.example.org".to_string()),
+            ),
+            (
+                "display_name".to_string(),
+                AttributeValue::S("Rust Blog".to_string()),
+            ),
+            ("post_comments_link".to_string(), AttributeValue::Bool(true)),
+            (
+                "category_langs".to_string(),
+                AttributeValue::S("日本語=ja".to_string()),
+            ),
+            (
+                "last_posted_published".to_string(),
+                AttributeValue::S("2024-02-08T00:00:00Z".to_string()),
+            ),
+            (
+                "skip_entries_older_than_last_posted".to_string(),
+                AttributeValue::Bool(true),
+            ),
+            (
+                "thread_root_uri".to_string(),
+                AttributeValue::S("at://did:plc:test/app.bsky.feed.post/3kroot".to_string()),
+            ),
+            (
+                "thread_root_cid".to_string(),
+                AttributeValue::S("bafyreiroot".to_string()),
+            ),
+            (
+                "next_fetch_allowed_at".to_string(),
+                AttributeValue::S("2024-02-10T02:00:00Z".to_string()),
+            ),
+            ("strip_emoji".to_string(), AttributeValue::Bool(true)),
+            (
+                "repost_updated_entries".to_string(),
+                AttributeValue::Bool(true),
+            ),
+            (
+                "posts_after".to_string(),
+                AttributeValue::S("2024-02-09T00:00:00Z".to_string()),
+            ),
+            (
+                "consecutive_failures".to_string(),
+                AttributeValue::N("2".to_string()),
+            ),
+            (
+                "next_attempt_at".to_string(),
+                AttributeValue::S("2024-02-10T03:00:00Z".to_string()),
+            ),
+            ("use_cookies".to_string(), AttributeValue::Bool(true)),
+            (
+                "post_template".to_string(),
+                AttributeValue::S("🦀 {title}".to_string()),
+            ),
+        ]);
+        let feed_record = parse_feed_record(&item).unwrap();
+        assert_eq!(feed_record.url, "https://blog.rust-lang.org/feed.xml");
+        assert_eq!(
+            feed_record.last_posted_entry_id.as_deref(),
+            Some("https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html")
+        );
+        assert_eq!(feed_record.title, None);
+        assert_eq!(feed_record.min_content_length, Some(200));
+        assert_eq!(
+            feed_record.activate_after.unwrap().to_rfc3339(),
+            "2024-02-10T00:00:00+00:00"
+        );
+        assert_eq!(feed_record.post_interval_minutes, Some(60));
+        assert_eq!(feed_record.display_name.as_deref(), Some("Rust Blog"));
+        assert!(feed_record.post_comments_link);
+        assert_eq!(feed_record.hub_url, None);
+        assert_eq!(feed_record.max_thumb_bytes, Some(500000));
+        assert_eq!(feed_record.priority, 10);
+        assert!(!feed_record.og_image_same_origin);
+        assert_eq!(
+            feed_record.og_image_host_allowlist,
+            vec!["cdn.example.net", "images.example.org"]
+        );
+        assert_eq!(
+            feed_record.category_langs,
+            vec![("日本語".to_string(), "ja".to_string())]
+        );
+        assert_eq!(
+            feed_record.last_posted_published.unwrap().to_rfc3339(),
+            "2024-02-08T00:00:00+00:00"
+        );
+        assert!(feed_record.skip_entries_older_than_last_posted);
+        assert_eq!(
+            feed_record.thread_root_uri.as_deref(),
+            Some("at://did:plc:test/app.bsky.feed.post/3kroot")
+        );
+        assert_eq!(feed_record.thread_root_cid.as_deref(), Some("bafyreiroot"));
+        assert_eq!(
+            feed_record.next_fetch_allowed_at.unwrap().to_rfc3339(),
+            "2024-02-10T02:00:00+00:00"
+        );
+        assert!(feed_record.strip_emoji);
+        assert!(feed_record.repost_updated_entries);
+        assert_eq!(
+            feed_record.posts_after.unwrap().to_rfc3339(),
+            "2024-02-09T00:00:00+00:00"
+        );
+        assert_eq!(feed_record.consecutive_failures, 2);
+        assert_eq!(
+            feed_record.next_attempt_at.unwrap().to_rfc3339(),
+            "2024-02-10T03:00:00+00:00"
+        );
+        assert!(feed_record.use_cookies);
+        assert_eq!(feed_record.post_template.as_deref(), Some("🦀 {title}"));
+        assert_eq!(
+            feed_record.next_post_allowed_at.unwrap().to_rfc3339(),
+            "2024-02-10T01:00:00+00:00"
+        );
+
+        let item = HashMap::from([
+            (
+                "url".to_string(),
+                AttributeValue::S("https://example.com/feed".to_string()),
+            ),
+            (
+                "min_content_length".to_string(),
+                AttributeValue::N("-1".to_string()),
+            ),
+        ]);
+        assert!(parse_feed_record(&item).is_err());
+    }
+
+    #[test]
+    fn test_parse_post_rate_window() {
+        let item = HashMap::from([
+            (
+                "key".to_string(),
+                AttributeValue::S(POST_RATE_WINDOW_KEY.to_string()),
+            ),
+            (
+                "started_at".to_string(),
+                AttributeValue::S("2024-02-10T00:00:00+00:00".to_string()),
+            ),
+            ("post_count".to_string(), AttributeValue::N("3".to_string())),
+        ]);
+        let post_rate_window = parse_post_rate_window(&item).unwrap().unwrap();
+        assert_eq!(post_rate_window.post_count, 3);
+        assert_eq!(
+            post_rate_window.started_at.to_rfc3339(),
+            "2024-02-10T00:00:00+00:00"
+        );
+        let item = HashMap::from([(
+            "key".to_string(),
+            AttributeValue::S(POST_RATE_WINDOW_KEY.to_string()),
+        )]);
+        assert_eq!(parse_post_rate_window(&item).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_pause_state() {
+        let item = HashMap::from([
+            ("key".to_string(), AttributeValue::S(PAUSED_KEY.to_string())),
+            ("paused".to_string(), AttributeValue::Bool(true)),
+            (
+                "reason".to_string(),
+                AttributeValue::S("incident".to_string()),
+            ),
+        ]);
+        assert_eq!(
+            parse_pause_state(&item).unwrap(),
+            PauseState {
+                paused: true,
+                reason: Some("incident".to_string()),
+            }
+        );
+        // pausedがない場合は止めない
+        let item = HashMap::from([("key".to_string(), AttributeValue::S(PAUSED_KEY.to_string()))]);
+        assert_eq!(parse_pause_state(&item).unwrap(), PauseState::default());
+    }
+
+    #[test]
+    fn test_parse_posted_entry_record() {
+        let item = HashMap::from([
+            (
+                "feed_url".to_string(),
+                AttributeValue::S("https://example.com/feed.xml".to_string()),
+            ),
+            (
+                "entry_id".to_string(),
+                AttributeValue::S("https://example.com/1".to_string()),
+            ),
+            (
+                "post_uri".to_string(),
+                AttributeValue::S("at://did:plc:test/app.bsky.feed.post/3kl".to_string()),
+            ),
+            (
+                "updated".to_string(),
+                AttributeValue::S("2024-02-10T00:00:00+00:00".to_string()),
+            ),
+        ]);
+        let posted_entry = parse_posted_entry_record(&item).unwrap();
+        assert_eq!(posted_entry.feed_url, "https://example.com/feed.xml");
+        assert_eq!(posted_entry.entry_id, "https://example.com/1");
+        assert_eq!(
+            posted_entry.post_uri,
+            "at://did:plc:test/app.bsky.feed.post/3kl"
+        );
+        assert_eq!(
+            posted_entry.updated.unwrap().to_rfc3339(),
+            "2024-02-10T00:00:00+00:00"
+        );
+        let item = HashMap::from([(
+            "entry_id".to_string(),
+            AttributeValue::S("https://example.com/1".to_string()),
+        )]);
+        assert!(parse_posted_entry_record(&item).is_err());
+    }
+
+    #[test]
+    fn test_parse_feed_record_coerces_number_attributes() {
+        let item = HashMap::from([
+            (
+                "url".to_string(),
+                AttributeValue::S("https://example.com/feed".to_string()),
+            ),
+            (
+                "last_posted_entry_id".to_string(),
+                AttributeValue::N("12345".to_string()),
+            ),
+            ("title".to_string(), AttributeValue::Bool(true)),
+        ]);
+        let feed_record = parse_feed_record(&item).unwrap();
+        assert_eq!(feed_record.last_posted_entry_id.as_deref(), Some("12345"));
+        assert_eq!(feed_record.title, None);
+    }
+}
@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+
+use crate::OpaqueError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BotError {
+    #[error("redirect loop detected: {url}")]
+    RedirectLoop { url: String },
+    #[error("blocked by a bot challenge: {url}")]
+    Blocked { url: String },
+    #[error("feed server is rate limiting requests, retry after {retry_after:?}: {url}")]
+    FeedRateLimited {
+        url: String,
+        retry_after: Option<DateTime<Utc>>,
+    },
+    #[error("feed has not been modified since the last fetch: {url}")]
+    FeedNotModified { url: String },
+    #[error("feed requires authorization ({status}): {url}")]
+    FeedUnauthorized { url: String, status: u16 },
+    #[error("feed server returned an error page ({status}): {url}")]
+    FeedHttpStatus { url: String, status: u16 },
+    #[error("logged in as {actual}, but expected {expected}")]
+    UnexpectedAccount { expected: String, actual: String },
+    #[error("bsky api error ({status}): {error:?} {message:?}")]
+    BskyApi {
+        status: u16,
+        error: Option<String>,
+        message: Option<String>,
+    },
+    // 失敗した処理の分類。再試行や通知を分類ごとに変えられるよう、原因のエラーを包む
+    #[error("failed to fetch feed: {0}")]
+    FeedFetch(#[source] OpaqueError),
+    #[error("failed to parse feed: {0}")]
+    FeedParse(#[source] OpaqueError),
+    #[error("bsky request failed: {0}")]
+    Bsky(#[source] OpaqueError),
+    #[error("dynamodb request failed: {0}")]
+    Dynamo(#[source] OpaqueError),
+    #[error("failed to get ogp: {0}")]
+    Ogp(#[source] OpaqueError),
+    #[error("failed to decode image: {0}")]
+    ImageDecode(#[source] OpaqueError),
+}
+
+impl BotError {
+    // フィードの取得の失敗のうち、解析できなかったものを区別する
+    pub fn feed_fetch(err: OpaqueError) -> Self {
+        if err
+            .downcast_ref::<feed_rs::parser::ParseFeedError>()
+            .is_some()
+        {
+            BotError::FeedParse(err)
+        } else {
+            BotError::FeedFetch(err)
+        }
+    }
+
+    // サムネイルの変換で画像を読み取れなかったものを区別する
+    pub fn bsky(err: OpaqueError) -> Self {
+        if err.downcast_ref::<image::ImageError>().is_some() {
+            BotError::ImageDecode(err)
+        } else {
+            BotError::Bsky(err)
+        }
+    }
+
+    pub fn ogp(err: OpaqueError) -> Self {
+        if err.downcast_ref::<image::ImageError>().is_some() {
+            BotError::ImageDecode(err)
+        } else {
+            BotError::Ogp(err)
+        }
+    }
+
+    fn wrapped_error(&self) -> Option<&OpaqueError> {
+        match self {
+            BotError::FeedFetch(err)
+            | BotError::FeedParse(err)
+            | BotError::Bsky(err)
+            | BotError::Dynamo(err)
+            | BotError::Ogp(err)
+            | BotError::ImageDecode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// 分類されていない失敗はotherにする
+pub fn error_category(err: &OpaqueError) -> &'static str {
+    match err.downcast_ref::<BotError>() {
+        Some(BotError::FeedFetch(_)) => "feed fetch",
+        Some(BotError::FeedParse(_)) => "feed parse",
+        Some(BotError::Bsky(_)) => "bsky",
+        Some(BotError::Dynamo(_)) => "dynamodb",
+        Some(BotError::Ogp(_)) => "ogp",
+        Some(BotError::ImageDecode(_)) => "image decode",
+        _ => "other",
+    }
+}
+
+// 分類のために包まれている場合は、内側にある原因のBotErrorを返す
+pub fn find_bot_error(err: &OpaqueError) -> Option<&BotError> {
+    let bot_error = err.downcast_ref::<BotError>()?;
+    match bot_error.wrapped_error() {
+        Some(wrapped_error) => find_bot_error(wrapped_error).or(Some(bot_error)),
+        None => Some(bot_error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_category() {
+        let parse_error: OpaqueError = feed_rs::parser::parse("not a feed".as_bytes())
+            .unwrap_err()
+            .into();
+        let err: OpaqueError = BotError::feed_fetch(parse_error).into();
+        assert_eq!(error_category(&err), "feed parse");
+        let image_error: OpaqueError = image::load_from_memory(b"not an image").unwrap_err().into();
+        let err: OpaqueError = BotError::bsky(image_error).into();
+        assert_eq!(error_category(&err), "image decode");
+        let err: OpaqueError = BotError::Dynamo("throttled".into()).into();
+        assert_eq!(error_category(&err), "dynamodb");
+        let err: OpaqueError = "unexpected".into();
+        assert_eq!(error_category(&err), "other");
+    }
+
+    #[test]
+    fn test_find_bot_error() {
+        // 分類で包んでも、原因のエラーで処理を分けられる
+        let blocked: OpaqueError = BotError::Blocked {
+            url: "https://example.com/feed.xml".to_string(),
+        }
+        .into();
+        let err: OpaqueError = BotError::feed_fetch(blocked).into();
+        assert_eq!(error_category(&err), "feed fetch");
+        assert!(matches!(
+            find_bot_error(&err),
+            Some(BotError::Blocked { .. })
+        ));
+        // 原因がBotErrorでない場合は分類を返す
+        let err: OpaqueError = BotError::Bsky("timed out".into()).into();
+        assert!(matches!(find_bot_error(&err), Some(BotError::Bsky(_))));
+        assert!(find_bot_error(&"unexpected".into()).is_none());
+    }
+}
@@ -1,15 +1,426 @@
+use std::{env, sync::OnceLock, time::Duration};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
-use feed_rs::model::Feed;
+use feed_rs::model::{Entry, Feed, Link};
+use reqwest::Version;
 use scraper::{Html, Selector};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    error::BotError,
+    host_throttle::HostThrottle,
+    http_client,
+    retry::{is_transient_request_error, retry_with_backoff, RetryPolicy},
+    text::decode_html_entities,
+    urls::{host_matches_domains, is_same_origin_or_allowed, parse_domain_list},
+    OpaqueError,
+};
+
+const FEED_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 新しいエントリーが多いフィードでも記事のサーバーに集中してアクセスしないよう、同じホストのOGPの取得はこの間隔を空ける
+const DEFAULT_OGP_HOST_INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseStatus {
+    Complete,
+    // 壊れたフィードの途中までを読み取れた場合
+    Partial { recovered_entries: usize },
+}
 
-use crate::OpaqueError;
+#[derive(Debug)]
+pub struct FetchedFeed {
+    pub feed: Feed,
+    pub parse_status: ParseStatus,
+    pub feed_links: FeedLinks,
+    pub http_version: Version,
+    pub validators: FeedValidators,
+}
+
+struct FetchedBytes {
+    bytes: Bytes,
+    http_version: Version,
+    status: u16,
+    validators: FeedValidators,
+}
+
+// 条件付きリクエストに使う、前回取得したときのETagとLast-Modified
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
 
-pub async fn get_feed(feed_url: &str) -> Result<Feed, OpaqueError> {
-    let response = reqwest::get(feed_url).await?;
+// WebSubで購読するためのハブと購読対象(self)のURL
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedLinks {
+    pub hub_url: Option<String>,
+    pub self_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeedFetchOptions {
+    // 最初のリクエストで発行されるセッションCookieを要求するフィードのため、Cookieを保持する
+    pub use_cookies: bool,
+    pub validators: FeedValidators,
+}
+
+pub async fn get_feed(
+    client: &reqwest::Client,
+    feed_url: &str,
+    options: &FeedFetchOptions,
+) -> Result<FetchedFeed, OpaqueError> {
+    let fetch_proxy_url = env::var("FEED_FETCH_PROXY_URL")
+        .ok()
+        .filter(|proxy| !proxy.is_empty());
+    get_feed_with_fetch_proxy(client, feed_url, fetch_proxy_url.as_deref(), options).await
+}
+
+async fn get_feed_with_fetch_proxy(
+    client: &reqwest::Client,
+    feed_url: &str,
+    fetch_proxy_url: Option<&str>,
+    options: &FeedFetchOptions,
+) -> Result<FetchedFeed, OpaqueError> {
+    let fetched_bytes = match (
+        fetch_feed_bytes(client, feed_url, options).await,
+        fetch_proxy_url,
+    ) {
+        (Err(err), Some(fetch_proxy_url)) if is_blocked_error(&err) => {
+            // チャレンジページを返されたフィードは設定されたプロキシ経由で取得し直す
+            info!(feed_url, "Retrying blocked feed via fetch proxy");
+            let proxied_url = Url::parse_with_params(fetch_proxy_url, &[("url", feed_url)])?;
+            match fetch_feed_bytes(client, proxied_url.as_str(), options).await {
+                Err(err) if is_blocked_error(&err) => {
+                    return Err(BotError::Blocked {
+                        url: feed_url.to_string(),
+                    }
+                    .into())
+                }
+                result => result?,
+            }
+        }
+        (result, _) => result?,
+    };
+    // HTTP/1.1しか話さない遅いサーバーを見つけられるようにプロトコルを記録する
+    info!(feed_url, http_version = ?fetched_bytes.http_version, "Fetched feed");
+    // エラーページを返された場合は、解析の失敗ではなくステータスコードのエラーにする
+    let (feed, parse_status) = parse_feed(&fetched_bytes.bytes).map_err(|err| {
+        if (200..300).contains(&fetched_bytes.status) {
+            err
+        } else {
+            BotError::FeedHttpStatus {
+                url: feed_url.to_string(),
+                status: fetched_bytes.status,
+            }
+            .into()
+        }
+    })?;
+    let feed_links = extract_feed_links(&feed);
+    Ok(FetchedFeed {
+        feed,
+        parse_status,
+        feed_links,
+        http_version: fetched_bytes.http_version,
+        validators: fetched_bytes.validators,
+    })
+}
+
+// フィードをまたいで同じホストへのOGPの取得の間隔を空けるため、実行全体で共有する
+fn ogp_host_throttle() -> &'static HostThrottle {
+    static OGP_HOST_THROTTLE: OnceLock<HostThrottle> = OnceLock::new();
+    OGP_HOST_THROTTLE.get_or_init(HostThrottle::default)
+}
+
+// Cookieが他のフィードやBlueskyへのリクエストに漏れないよう、取得ごとに新しいクライアントを使う
+fn cookie_http_client() -> Result<reqwest::Client, OpaqueError> {
+    Ok(http_client::client_builder().cookie_store(true).build()?)
+}
+
+async fn fetch_feed_bytes(
+    client: &reqwest::Client,
+    feed_url: &str,
+    options: &FeedFetchOptions,
+) -> Result<FetchedBytes, OpaqueError> {
+    let retry_policy = RetryPolicy::feed_fetch_from_env()?;
+    let client = if options.use_cookies {
+        cookie_http_client()?
+    } else {
+        client.clone()
+    };
+    let send = || fetch_with_retry(&client, feed_url, &options.validators, &retry_policy);
+    let mut response = send()
+        .await
+        .map_err(|err| classify_request_error(feed_url, err))?;
+    // Cookieを発行して拒否された場合は、受け取ったCookieを付けて一度だけ取得し直す
+    if options.use_cookies
+        && matches!(response.status().as_u16(), 401 | 403)
+        && response.headers().contains_key(reqwest::header::SET_COOKIE)
+    {
+        info!(feed_url, "Retrying feed with issued cookies");
+        response = send()
+            .await
+            .map_err(|err| classify_request_error(feed_url, err))?;
+    }
+    let http_version = response.version();
+    let status = response.status().as_u16();
+    // 429の場合は再試行せず、Retry-Afterまでこのフィードの取得を見送る
+    if status == 429 {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        return Err(BotError::FeedRateLimited {
+            url: feed_url.to_string(),
+            retry_after,
+        }
+        .into());
+    }
+    // 前回から更新されていないフィードは本文を受け取らずに処理を終える
+    if status == 304 {
+        return Err(BotError::FeedNotModified {
+            url: feed_url.to_string(),
+        }
+        .into());
+    }
+    let header_value = |name: reqwest::header::HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let validators = FeedValidators {
+        etag: header_value(reqwest::header::ETAG),
+        last_modified: header_value(reqwest::header::LAST_MODIFIED),
+    };
+    let cf_mitigated = response
+        .headers()
+        .get("cf-mitigated")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
     let bytes = response.bytes().await?;
-    let feed = feed_rs::parser::parse(bytes.to_vec().as_slice())?;
-    Ok(feed)
+    if is_cloudflare_challenge(status, cf_mitigated.as_deref(), &bytes) {
+        return Err(BotError::Blocked {
+            url: feed_url.to_string(),
+        }
+        .into());
+    }
+    // 非公開になったフィードは再試行しても取得できないため区別する
+    if status == 401 || status == 403 {
+        return Err(BotError::FeedUnauthorized {
+            url: feed_url.to_string(),
+            status,
+        }
+        .into());
+    }
+    Ok(FetchedBytes {
+        bytes,
+        http_version,
+        status,
+        validators,
+    })
+}
+
+#[derive(Debug)]
+enum FetchAttemptError {
+    Request(reqwest::Error),
+    ServerError(reqwest::Response),
+}
+
+fn is_retryable_fetch_error(err: &FetchAttemptError) -> bool {
+    match err {
+        FetchAttemptError::Request(err) => is_transient_request_error(err),
+        // チャレンジのページは再試行しても変わらない
+        FetchAttemptError::ServerError(response) => {
+            response
+                .headers()
+                .get("cf-mitigated")
+                .and_then(|value| value.to_str().ok())
+                != Some("challenge")
+        }
+    }
+}
+
+// 5xxと接続の失敗、タイムアウトは間隔を空けて再試行する。404などはすぐに返す
+async fn fetch_with_retry(
+    client: &reqwest::Client,
+    feed_url: &str,
+    validators: &FeedValidators,
+    retry_policy: &RetryPolicy,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let result = retry_with_backoff(retry_policy, is_retryable_fetch_error, || async {
+        let mut request = client.get(feed_url).timeout(FEED_FETCH_TIMEOUT);
+        if let Some(etag) = &validators.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await.map_err(FetchAttemptError::Request)?;
+        if response.status().is_server_error() {
+            return Err(FetchAttemptError::ServerError(response));
+        }
+        Ok(response)
+    })
+    .await;
+    match result {
+        Ok(response) => Ok(response),
+        Err(FetchAttemptError::Request(err)) => Err(err),
+        // 再試行しても5xxの場合は、応答の内容で失敗の種類を判断する
+        Err(FetchAttemptError::ServerError(response)) => Ok(response),
+    }
+}
+
+// Retry-Afterは秒数かHTTP日付のどちらかで指定される
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Some(now + chrono::Duration::seconds(seconds.max(0)));
+    }
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|retry_after| retry_after.with_timezone(&Utc))
+}
+
+fn is_blocked_error(err: &OpaqueError) -> bool {
+    matches!(
+        err.downcast_ref::<BotError>(),
+        Some(BotError::Blocked { .. })
+    )
+}
+
+// CloudflareのJSチャレンジはフィードの代わりにHTMLを403/503で返す
+fn is_cloudflare_challenge(status: u16, cf_mitigated: Option<&str>, body: &[u8]) -> bool {
+    if cf_mitigated == Some("challenge") {
+        return true;
+    }
+    if status != 403 && status != 503 {
+        return false;
+    }
+    let body = String::from_utf8_lossy(body);
+    [
+        "cf-chl-",
+        "challenge-platform",
+        "<title>Just a moment...</title>",
+    ]
+    .iter()
+    .any(|signature| body.contains(signature))
+}
+
+pub fn extract_feed_links(feed: &Feed) -> FeedLinks {
+    let find_link = |rel: &str| {
+        feed.links
+            .iter()
+            .find(|link| link.rel.as_deref() == Some(rel))
+            .map(|link| link.href.clone())
+    };
+    FeedLinks {
+        hub_url: find_link("hub"),
+        self_url: find_link("self"),
+    }
+}
+
+// リダイレクトの上限に達した場合はループとみなして区別できるエラーにする
+fn classify_request_error(url: &str, err: reqwest::Error) -> OpaqueError {
+    if err.is_redirect() {
+        BotError::RedirectLoop {
+            url: url.to_string(),
+        }
+        .into()
+    } else {
+        err.into()
+    }
+}
+
+// feed_rsは先頭が{の場合にJSON Feedとして解析する
+pub fn parse_feed(bytes: &[u8]) -> Result<(Feed, ParseStatus), OpaqueError> {
+    let (mut feed, parse_status) = match feed_rs::parser::parse(bytes) {
+        Ok(feed) => (feed, ParseStatus::Complete),
+        Err(err) => match recover_partial_feed(bytes) {
+            Some(feed) => {
+                let recovered_entries = feed.entries.len();
+                (feed, ParseStatus::Partial { recovered_entries })
+            }
+            None => return Err(err.into()),
+        },
+    };
+    attach_rss_comments_links(&mut feed, bytes);
+    Ok((feed, parse_status))
+}
+
+// feed_rsはRSSの<comments>を読み取らないため、Atomと同じrel="replies"のリンクとして追加する
+fn attach_rss_comments_links(feed: &mut Feed, bytes: &[u8]) {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return;
+    };
+    let Ok(document) = roxmltree::Document::parse(text) else {
+        return;
+    };
+    for item in document
+        .descendants()
+        .filter(|node| node.has_tag_name("item"))
+    {
+        let child_text = |name: &str| {
+            item.children()
+                .find(|child| child.has_tag_name(name))
+                .and_then(|child| child.text())
+                .map(|text| text.trim())
+                .filter(|text| !text.is_empty())
+        };
+        let Some(comments_url) = child_text("comments") else {
+            continue;
+        };
+        let guid = child_text("guid");
+        let link = child_text("link");
+        let entry = feed.entries.iter_mut().find(|entry| {
+            Some(entry.id.as_str()) == guid
+                || entry
+                    .links
+                    .first()
+                    .is_some_and(|entry_link| Some(entry_link.href.as_str()) == link)
+        });
+        if let Some(entry) = entry {
+            entry.links.push(Link {
+                href: comments_url.to_string(),
+                rel: Some("replies".to_string()),
+                media_type: None,
+                href_lang: None,
+                title: None,
+                length: None,
+            });
+        }
+    }
+}
+
+// 最後に閉じられているitem/entryまでで切り詰め、閉じタグを補って再度パースする
+fn recover_partial_feed(bytes: &[u8]) -> Option<Feed> {
+    let text = String::from_utf8_lossy(bytes);
+    let candidates = [
+        ("</item>", "</channel></rss>"),
+        ("</item>", "</rdf:RDF>"),
+        ("</entry>", "</feed>"),
+    ];
+    for (end_tag, closing_tags) in candidates {
+        let Some(index) = text.rfind(end_tag) else {
+            continue;
+        };
+        let truncated = format!("{}{}", &text[..index + end_tag.len()], closing_tags);
+        if let Ok(feed) = feed_rs::parser::parse(truncated.as_bytes()) {
+            if !feed.entries.is_empty() {
+                return Some(feed);
+            }
+        }
+    }
+    None
 }
 
 #[derive(Debug, Clone)]
@@ -18,45 +429,207 @@ pub struct FeedEntry {
     pub url: String,
     pub title: Option<String>,
     pub published: Option<DateTime<Utc>>,
+    // 公開後に修正された日時
+    pub updated: Option<DateTime<Utc>>,
+    // HTMLタグを除去したcontent(content:encoded)、なければsummary
+    pub summary: Option<String>,
+    // 本文中の最初の画像
+    pub image_url: Option<String>,
+    // media:thumbnailの画像
+    pub media_thumbnail_url: Option<String>,
+    // Hacker Newsなどのコメント(議論)ページ
+    pub comments_url: Option<String>,
+    pub categories: Vec<String>,
+    // エントリーの著作権表示。エントリーにない場合はフィードのものを引き継ぐ
+    pub rights: Option<String>,
 }
 
 pub fn extract_feed_entries(feed: &Feed) -> Vec<FeedEntry> {
+    let base_url = find_feed_base_url(feed);
     let mut entries = Vec::new();
     for entry in &feed.entries {
-        if let Some(link) = entry.links.get(0) {
+        if let Some(link) = entry.links.first() {
+            let Some(url) = resolve_relative_url(&link.href, base_url.as_ref()) else {
+                warn!(link = %link.href, "Skipping entry with unresolvable link");
+                continue;
+            };
             let title = entry
                 .title
                 .as_ref()
-                .and_then(|title_element| Some(&title_element.content));
+                .map(|title_element| &title_element.content);
+            // WordPressなどはcontent:encodedに全文を入れているため、summaryより優先する
+            let content = entry
+                .content
+                .as_ref()
+                .and_then(|content| content.body.as_deref())
+                .filter(|body| !body.trim().is_empty());
+            let summary = entry
+                .summary
+                .as_ref()
+                .map(|summary| summary.content.as_str());
+            let image_url = content
+                .and_then(find_first_image_url)
+                .or(summary.and_then(find_first_image_url));
+            let comments_url = find_comments_url(entry, content.or(summary));
+            let summary = content
+                .or(summary)
+                .map(html_to_text)
+                .filter(|summary| !summary.is_empty());
+            let media_thumbnail_url = entry
+                .media
+                .iter()
+                .flat_map(|media| media.thumbnails.iter())
+                .map(|thumbnail| thumbnail.image.uri.clone())
+                .next();
             entries.push(FeedEntry {
                 id: entry.id.clone(),
-                url: link.href.clone(),
-                title: title.map(|s| s.to_string()),
+                url,
+                // 二重にエスケープされたタイトルは文字参照が残ったままになる
+                title: title.map(|title| decode_html_entities(title)),
                 published: entry.published,
+                updated: entry.updated,
+                summary,
+                image_url,
+                media_thumbnail_url,
+                comments_url,
+                categories: entry
+                    .categories
+                    .iter()
+                    .map(|category| category.term.clone())
+                    .collect(),
+                rights: entry
+                    .rights
+                    .as_ref()
+                    .or(feed.rights.as_ref())
+                    .map(|rights| rights.content.clone()),
             });
         }
     }
     entries
 }
 
-#[derive(Debug)]
+// 相対URLのエントリーのリンクを解決するため、selfまたはサイトのURLを基準にする
+fn find_feed_base_url(feed: &Feed) -> Option<Url> {
+    let self_link = feed
+        .links
+        .iter()
+        .find(|link| link.rel.as_deref() == Some("self"));
+    self_link
+        .into_iter()
+        .chain(feed.links.iter())
+        .find_map(|link| Url::parse(&link.href).ok())
+}
+
+fn resolve_relative_url(href: &str, base_url: Option<&Url>) -> Option<String> {
+    match Url::parse(href) {
+        Ok(_) => Some(href.to_string()),
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            base_url?.join(href).ok().map(|url| url.to_string())
+        }
+        Err(_) => None,
+    }
+}
+
+fn find_comments_url(entry: &Entry, html: Option<&str>) -> Option<String> {
+    let replies_link = entry
+        .links
+        .iter()
+        .skip(1)
+        .find(|link| link.rel.as_deref() == Some("replies"));
+    if let Some(replies_link) = replies_link {
+        return Some(replies_link.href.clone());
+    }
+    // Hacker NewsやLobstersは本文に"Comments"というリンクを含めている
+    let fragment = Html::parse_fragment(html?);
+    let selector = Selector::parse("a[href]").unwrap();
+    fragment
+        .select(&selector)
+        .find(|element| {
+            element
+                .text()
+                .collect::<String>()
+                .trim()
+                .eq_ignore_ascii_case("comments")
+        })
+        .and_then(|element| element.value().attr("href"))
+        .map(|href| href.to_string())
+}
+
+fn find_first_image_url(html: &str) -> Option<String> {
+    let fragment = Html::parse_fragment(html);
+    let selector = Selector::parse("img[src]").unwrap();
+    fragment
+        .select(&selector)
+        .filter_map(|element| element.value().attr("src"))
+        .map(|src| src.trim())
+        .find(|src| !src.is_empty())
+        .map(|src| src.to_string())
+}
+
+fn html_to_text(html: &str) -> String {
+    let fragment = Html::parse_fragment(html);
+    let text = fragment.root_element().text().collect::<Vec<_>>().join(" ");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Debug, Clone)]
 pub struct OGPInfo {
     pub title: Option<String>,
     pub image_url: Option<String>,
+    pub twitter_image_url: Option<String>,
     pub description: Option<String>,
 }
 
-pub async fn get_ogp_from_url(url: &str) -> Result<OGPInfo, OpaqueError> {
-    let response = reqwest::get(url).await?;
+// OGPとTwitter Cardのほか、<title>などの一般的なタグも1つも見つからない場合はNoneを返す
+pub async fn get_ogp_from_url(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<Option<OGPInfo>, OpaqueError> {
+    let response = client.get(url).send().await?;
+    // リダイレクトされた場合は最終的なページのURLを基準にする
+    let page_url = response.url().clone();
     let text = response.text().await?;
-    let html = Html::parse_document(&text);
-    let title = extract_ogp_info_from_meta_tag(&html, "og:title");
-    let image_url = extract_ogp_info_from_meta_tag(&html, "og:image");
-    let description = extract_ogp_info_from_meta_tag(&html, "og:description");
-    Ok(OGPInfo {
-        title: title.map(|s| s.to_string()),
+    Ok(parse_ogp_info(&text).map(|ogp_info| resolve_ogp_image_urls(ogp_info, &page_url)))
+}
+
+// "/images/cover.png"や"//cdn.example.com/x.jpg"のような相対URLをページのURLで解決する
+fn resolve_ogp_image_urls(ogp_info: OGPInfo, page_url: &Url) -> OGPInfo {
+    let resolve = |image_url: Option<String>| {
+        image_url.and_then(|image_url| resolve_relative_url(image_url.trim(), Some(page_url)))
+    };
+    OGPInfo {
+        image_url: resolve(ogp_info.image_url),
+        twitter_image_url: resolve(ogp_info.twitter_image_url),
+        ..ogp_info
+    }
+}
+
+fn parse_ogp_info(text: &str) -> Option<OGPInfo> {
+    let html = Html::parse_document(text);
+    // OGPとTwitter Cardで足りない項目は<title>などの一般的なタグで補う
+    let title = extract_ogp_info_from_meta_tag(&html, "og:title")
+        .or_else(|| extract_twitter_card_info(&html, "twitter:title"))
+        .map(|title| title.to_string())
+        .or_else(|| extract_document_title(&html));
+    // 動画のページはog:imageがなくても動画のポスター画像を持っていることがある
+    let twitter_image_url = extract_twitter_image_url(&html);
+    let image_url = ["og:image", "og:video:poster", "og:video:image"]
+        .iter()
+        .find_map(|property| extract_ogp_info_from_meta_tag(&html, property))
+        .or(twitter_image_url)
+        .or_else(|| extract_image_src_link(&html));
+    let description = extract_ogp_info_from_meta_tag(&html, "og:description")
+        .or_else(|| extract_twitter_card_info(&html, "twitter:description"))
+        .or_else(|| extract_named_meta_tag(&html, "description"));
+    // どのタグもないページはフィードの情報の方が具体的なため、Noneにする
+    if title.is_none() && image_url.is_none() && description.is_none() {
+        return None;
+    }
+    Some(OGPInfo {
+        title: title.map(|title| decode_html_entities(&title)),
         image_url: image_url.map(|s| s.to_string()),
-        description: description.map(|s| s.to_string()),
+        twitter_image_url: twitter_image_url.map(|s| s.to_string()),
+        description: description.map(decode_html_entities),
     })
 }
 
@@ -70,69 +643,1832 @@ fn extract_ogp_info_from_meta_tag<'a>(html: &'a Html, property: &str) -> Option<
     None
 }
 
+fn extract_named_meta_tag<'a>(html: &'a Html, name: &str) -> Option<&'a str> {
+    let selector = Selector::parse(&format!(r#"meta[name="{name}"]"#)).ok()?;
+    html.select(&selector)
+        .filter_map(|tag| tag.value().attr("content"))
+        .find(|content| !content.trim().is_empty())
+}
+
+// Twitter Cardはname属性を使うが、OGPと同じproperty属性で書いているサイトもある
+fn extract_twitter_card_info<'a>(html: &'a Html, name: &str) -> Option<&'a str> {
+    extract_named_meta_tag(html, name).or_else(|| extract_ogp_info_from_meta_tag(html, name))
+}
+
+fn extract_twitter_image_url(html: &Html) -> Option<&str> {
+    ["twitter:image", "twitter:image:src"]
+        .iter()
+        .find_map(|name| extract_twitter_card_info(html, name))
+}
+
+fn extract_document_title(html: &Html) -> Option<String> {
+    let selector = Selector::parse("head title").ok()?;
+    let title = html.select(&selector).next()?.text().collect::<String>();
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+// 古い形式の<link rel="image_src">
+fn extract_image_src_link(html: &Html) -> Option<&str> {
+    let selector = Selector::parse(r#"link[rel="image_src"][href]"#).ok()?;
+    html.select(&selector)
+        .filter_map(|tag| tag.value().attr("href"))
+        .find(|href| !href.trim().is_empty())
+}
+
 #[derive(Debug)]
 pub struct OGImage {
     pub image: Bytes,
     pub content_type: String,
+    // 画像プロキシでリサイズ済みの場合はtrue
+    pub resized: bool,
+}
+
+// パディングを省略したdata: URIもあるため、パディングの有無を問わない
+const DATA_URI_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+fn is_data_uri(image_url: &str) -> bool {
+    image_url
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+// "data:image/png;base64,..."の形式の画像はネットワークを使わずにデコードする
+fn decode_data_uri(data_uri: &str) -> Result<OGImage, OpaqueError> {
+    let (metadata, data) = data_uri[5..]
+        .split_once(',')
+        .ok_or("invalid data uri: missing data")?;
+    let mut parameters = metadata.split(';').map(|parameter| parameter.trim());
+    let content_type = parameters.next().unwrap_or_default().to_ascii_lowercase();
+    if !content_type.starts_with("image/") {
+        return Err(format!("unsupported data uri content type: {}", content_type).into());
+    }
+    if !parameters.any(|parameter| parameter.eq_ignore_ascii_case("base64")) {
+        return Err("unsupported data uri: not base64 encoded".into());
+    }
+    // 属性値の中で折り返されていることがあるため空白を取り除く
+    let data = data
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<String>();
+    let image = DATA_URI_BASE64.decode(data)?;
+    Ok(OGImage {
+        image: Bytes::from(image),
+        content_type,
+        resized: false,
+    })
 }
 
-pub async fn get_og_image(image_url: &str) -> Result<OGImage, OpaqueError> {
-    let response = reqwest::get(image_url).await?;
+pub async fn get_og_image(
+    client: &reqwest::Client,
+    image_url: &str,
+) -> Result<OGImage, OpaqueError> {
+    if is_data_uri(image_url) {
+        return decode_data_uri(image_url);
+    }
+    let response = client
+        .get(image_url)
+        .send()
+        .await
+        .map_err(|err| classify_request_error(image_url, err))?
+        .error_for_status()?;
     let content_type = response
         .headers()
         .get(reqwest::header::CONTENT_TYPE)
-        .unwrap()
-        .to_str()?
-        .to_string();
+        .and_then(|content_type| content_type.to_str().ok())
+        .filter(|content_type| content_type.starts_with("image/"))
+        .map(|content_type| content_type.to_string());
     let bytes = response.bytes().await?;
+    // Content-Typeがない、または画像を示していない場合は画像の内容から判定する
+    let content_type = content_type.unwrap_or_else(|| match image::guess_format(&bytes) {
+        Ok(format) => format.to_mime_type().to_string(),
+        Err(_) => "application/octet-stream".to_string(),
+    });
     Ok(OGImage {
         image: bytes,
         content_type,
+        resized: false,
+    })
+}
+
+// weservなどの画像プロキシでリサイズ済みのJPEGを取得する
+fn build_image_proxy_url(image_proxy_url: &str, image_url: &str) -> Result<Url, OpaqueError> {
+    let url = Url::parse_with_params(
+        image_proxy_url,
+        &[
+            ("url", image_url),
+            ("w", "1000"),
+            ("h", "1000"),
+            ("fit", "inside"),
+            ("output", "jpg"),
+        ],
+    )?;
+    Ok(url)
+}
+
+pub async fn get_og_image_via_proxy(
+    client: &reqwest::Client,
+    image_proxy_url: &str,
+    image_url: &str,
+) -> Result<OGImage, OpaqueError> {
+    let proxy_url = build_image_proxy_url(image_proxy_url, image_url)?;
+    let response = client.get(proxy_url).send().await?.error_for_status()?;
+    let bytes = response.bytes().await?;
+    Ok(OGImage {
+        image: bytes,
+        content_type: "image/jpeg".to_string(),
+        resized: true,
     })
 }
 
+async fn fetch_og_image(
+    client: &reqwest::Client,
+    image_url: &str,
+    image_proxy_url: Option<&str>,
+) -> Option<OGImage> {
+    // 画像プロキシはdata: URIを取得できない
+    if let Some(image_proxy_url) = image_proxy_url.filter(|_| !is_data_uri(image_url)) {
+        match get_og_image_via_proxy(client, image_proxy_url, image_url).await {
+            Ok(og_image) => return Some(og_image),
+            Err(err) => warn!(
+                error = ?err,
+                "Failed to fetch image via proxy, falling back to local resizing"
+            ),
+        }
+    }
+    get_og_image(client, image_url).await.ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageSource {
+    OgImage,
+    TwitterImage,
+    MediaThumbnail,
+    // 本文中の最初の画像
+    ContentImage,
+}
+
+const DEFAULT_IMAGE_SOURCE_ORDER: [ImageSource; 4] = [
+    ImageSource::OgImage,
+    ImageSource::TwitterImage,
+    ImageSource::MediaThumbnail,
+    ImageSource::ContentImage,
+];
+
+// "og:image,twitter:image,media:thumbnail,content"の形式で画像を探す順番を設定する
+fn parse_image_source_order(value: &str) -> Vec<ImageSource> {
+    value
+        .split(',')
+        .map(|source| source.trim())
+        .filter(|source| !source.is_empty())
+        .filter_map(|source| match source {
+            "og:image" => Some(ImageSource::OgImage),
+            "twitter:image" => Some(ImageSource::TwitterImage),
+            "media:thumbnail" => Some(ImageSource::MediaThumbnail),
+            "content" => Some(ImageSource::ContentImage),
+            _ => {
+                warn!(source, "Ignored unknown image source");
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EntryInfoOptions {
+    pub image_proxy_url: Option<String>,
+    // 遅い・スクレイピングを嫌うドメインはOGPを取得せずフィードの情報だけで埋め込みを作る
+    pub ogp_skip_domains: Vec<String>,
+    // 記事と異なるホストのog:imageは広告やトラッキング画像のことがあるため使わない
+    pub require_same_origin_image: bool,
+    pub image_host_allowlist: Vec<String>,
+    // 空の場合は既定の順番を使う
+    pub image_source_order: Vec<ImageSource>,
+    // 同じホストのOGPを続けて取得する場合に空ける間隔(0の場合は空けない)
+    pub ogp_host_interval: Duration,
+}
+
+impl EntryInfoOptions {
+    pub fn from_env() -> Self {
+        Self {
+            image_proxy_url: env::var("IMAGE_PROXY_URL").ok(),
+            ogp_skip_domains: env::var("OGP_SKIP_DOMAINS")
+                .map(|domains| parse_domain_list(&domains))
+                .unwrap_or_default(),
+            require_same_origin_image: false,
+            image_host_allowlist: Vec::new(),
+            image_source_order: env::var("IMAGE_SOURCE_ORDER")
+                .map(|order| parse_image_source_order(&order))
+                .unwrap_or_default(),
+            ogp_host_interval: Duration::from_millis(
+                env::var("OGP_HOST_INTERVAL_MS")
+                    .ok()
+                    .and_then(|interval| interval.parse().ok())
+                    .unwrap_or(DEFAULT_OGP_HOST_INTERVAL_MS),
+            ),
+        }
+    }
+}
+
+fn ogp_info_from_feed_entry(feed_entry: &FeedEntry) -> OGPInfo {
+    OGPInfo {
+        title: feed_entry.title.clone(),
+        image_url: feed_entry.image_url.clone(),
+        twitter_image_url: None,
+        description: feed_entry.summary.clone(),
+    }
+}
+
 pub async fn extract_feed_entry_info(
+    client: &reqwest::Client,
     feed_entry: &FeedEntry,
+    options: &EntryInfoOptions,
 ) -> Result<(Option<OGPInfo>, Option<OGImage>), OpaqueError> {
-    let ogp_info = get_ogp_from_url(&feed_entry.url).await.ok();
-    let og_image = match &ogp_info {
-        Some(OGPInfo {
-            image_url: Some(image_url),
-            ..
-        }) => get_og_image(image_url).await.ok(),
-        _ => None,
+    if host_matches_domains(&feed_entry.url, &options.ogp_skip_domains) {
+        info!(entry_url = %feed_entry.url, "Skipped fetching OGP for denylisted domain");
+        return Ok((Some(ogp_info_from_feed_entry(feed_entry)), None));
+    }
+    ogp_host_throttle()
+        .wait(&feed_entry.url, options.ogp_host_interval)
+        .await;
+    let ogp_info = match get_ogp_from_url(client, &feed_entry.url).await {
+        Ok(Some(ogp_info)) => Some(ogp_info),
+        Ok(None) => {
+            // 壊れたHTMLなどでOGPが取れない場合は、薄い埋め込みにならないようフィードの情報を使う
+            info!(entry_url = %feed_entry.url, "No OGP found, using feed data");
+            Some(ogp_info_from_feed_entry(feed_entry))
+        }
+        Err(_) => None,
     };
+    let image_urls = collect_image_urls(feed_entry, ogp_info.as_ref(), options);
+    let og_image =
+        fetch_first_decodable_image(client, &image_urls, options.image_proxy_url.as_deref()).await;
     Ok((ogp_info, og_image))
 }
 
+// 設定された順番で画像のURLを集める。og:imageがない場合は本文中の画像などを使う
+fn collect_image_urls(
+    feed_entry: &FeedEntry,
+    ogp_info: Option<&OGPInfo>,
+    options: &EntryInfoOptions,
+) -> Vec<String> {
+    let image_source_order = if options.image_source_order.is_empty() {
+        &DEFAULT_IMAGE_SOURCE_ORDER[..]
+    } else {
+        &options.image_source_order[..]
+    };
+    let mut image_urls: Vec<String> = Vec::new();
+    for image_source in image_source_order {
+        let image_url = match image_source {
+            ImageSource::OgImage => ogp_info.and_then(|ogp_info| ogp_info.image_url.as_ref()),
+            ImageSource::TwitterImage => {
+                ogp_info.and_then(|ogp_info| ogp_info.twitter_image_url.as_ref())
+            }
+            ImageSource::MediaThumbnail => feed_entry.media_thumbnail_url.as_ref(),
+            ImageSource::ContentImage => feed_entry.image_url.as_ref(),
+        };
+        let Some(image_url) = image_url.filter(|image_url| !image_urls.contains(image_url)) else {
+            continue;
+        };
+        // data: URIはページに埋め込まれた画像のため、記事と同じ出どころとみなす
+        if options.require_same_origin_image
+            && !is_data_uri(image_url)
+            && !is_same_origin_or_allowed(image_url, &feed_entry.url, &options.image_host_allowlist)
+        {
+            info!(image_url, "Skipped cross-origin image");
+            continue;
+        }
+        image_urls.push(image_url.clone());
+    }
+    image_urls
+}
+
+// デコードできない形式の画像で諦めないよう、デコードできる最初の画像を使う
+// AVIFはデコードできないため、IMAGE_PROXY_URLが設定されていなければAVIFしかない記事にサムネイルを付けられない
+// どれもデコードできない場合は最初に取得できた画像を使う
+async fn fetch_first_decodable_image(
+    client: &reqwest::Client,
+    image_urls: &[String],
+    image_proxy_url: Option<&str>,
+) -> Option<OGImage> {
+    let mut first_og_image = None;
+    for image_url in image_urls {
+        let Some(og_image) = fetch_og_image(client, image_url, image_proxy_url).await else {
+            continue;
+        };
+        if image::load_from_memory(&og_image.image).is_ok() {
+            return Some(og_image);
+        }
+        warn!(image_url, "Failed to decode image, trying next source");
+        first_og_image = first_og_image.or(Some(og_image));
+    }
+    first_og_image
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn test_http_client() -> reqwest::Client {
+        http_client::build_client().unwrap()
+    }
+
+    #[test]
+    fn test_extract_feed_entries_summary() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Example Blog</title>
+    <link>https://example.com/</link>
+    <item>
+      <title>Full entry</title>
+      <link>https://example.com/full</link>
+      <guid>https://example.com/full</guid>
+      <description><![CDATA[<p>This is the <b>full</b>
+        article.</p>]]></description>
+    </item>
+    <item>
+      <title>Stub entry</title>
+      <link>https://example.com/stub</link>
+      <guid>https://example.com/stub</guid>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let entries = extract_feed_entries(&feed);
+        assert_eq!(
+            entries[0].summary.as_deref(),
+            Some("This is the full article.")
+        );
+        assert_eq!(entries[1].summary, None);
+    }
+
+    #[test]
+    fn test_extract_feed_entries_prefers_content_encoded() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+  <channel>
+    <title>Example WordPress Blog</title>
+    <link>https://example.com/</link>
+    <item>
+      <title>WordPress entry</title>
+      <link>https://example.com/wordpress</link>
+      <guid>https://example.com/?p=1</guid>
+      <description><![CDATA[Short excerpt [&#8230;]]]></description>
+      <content:encoded><![CDATA[<p><img src="https://example.com/wp-content/uploads/hero.jpg" alt="">The full <em>rich</em> body.</p>]]></content:encoded>
+    </item>
+    <item>
+      <title>Excerpt only</title>
+      <link>https://example.com/excerpt</link>
+      <guid>https://example.com/?p=2</guid>
+      <description><![CDATA[<img src="https://example.com/excerpt.png">Only an excerpt.]]></description>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let entries = extract_feed_entries(&feed);
+        assert_eq!(entries[0].summary.as_deref(), Some("The full rich body."));
+        assert_eq!(
+            entries[0].image_url.as_deref(),
+            Some("https://example.com/wp-content/uploads/hero.jpg")
+        );
+        assert_eq!(entries[1].summary.as_deref(), Some("Only an excerpt."));
+        assert_eq!(
+            entries[1].image_url.as_deref(),
+            Some("https://example.com/excerpt.png")
+        );
+    }
+
+    #[test]
+    fn test_extract_feed_entries_resolves_relative_links() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example Blog</title>
+  <link rel="self" href="https://example.com/blog/atom.xml"/>
+  <entry>
+    <id>urn:example:1</id>
+    <title>Root relative</title>
+    <link href="/blog/posts/1"/>
+  </entry>
+  <entry>
+    <id>urn:example:2</id>
+    <title>Path relative</title>
+    <link href="posts/2"/>
+  </entry>
+  <entry>
+    <id>urn:example:3</id>
+    <title>Absolute</title>
+    <link href="https://other.example.com/3"/>
+  </entry>
+</feed>"#;
+        let (feed, _) = parse_feed(xml.as_bytes()).unwrap();
+        let urls = extract_feed_entries(&feed)
+            .into_iter()
+            .map(|entry| entry.url)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/blog/posts/1",
+                "https://example.com/blog/posts/2",
+                "https://other.example.com/3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_relative_url() {
+        let base_url = Url::parse("https://example.com/feed.xml").unwrap();
+        assert_eq!(
+            resolve_relative_url("/posts/1", Some(&base_url)).as_deref(),
+            Some("https://example.com/posts/1")
+        );
+        // 基準となるURLがなければ解決できない
+        assert_eq!(resolve_relative_url("/posts/1", None), None);
+        assert_eq!(resolve_relative_url("http://[::1", Some(&base_url)), None);
+    }
+
+    #[test]
+    fn test_extract_feed_entries_comments_url() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Lobsters</title>
+    <link>https://lobste.rs/</link>
+    <item>
+      <title>With comments element</title>
+      <link>https://example.com/article</link>
+      <guid>https://lobste.rs/s/abc123</guid>
+      <comments>https://lobste.rs/s/abc123/with_comments_element</comments>
+    </item>
+    <item>
+      <title>With comments anchor</title>
+      <link>https://example.com/other</link>
+      <description><![CDATA[<a href="https://news.ycombinator.com/item?id=1">Comments</a>]]></description>
+    </item>
+    <item>
+      <title>Without comments</title>
+      <link>https://example.com/plain</link>
+    </item>
+  </channel>
+</rss>"#;
+        let (feed, _) = parse_feed(xml.as_bytes()).unwrap();
+        let entries = extract_feed_entries(&feed);
+        assert_eq!(entries[0].url, "https://example.com/article");
+        assert_eq!(
+            entries[0].comments_url.as_deref(),
+            Some("https://lobste.rs/s/abc123/with_comments_element")
+        );
+        assert_eq!(
+            entries[1].comments_url.as_deref(),
+            Some("https://news.ycombinator.com/item?id=1")
+        );
+        assert_eq!(entries[2].comments_url, None);
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example Blog</title>
+  <entry>
+    <id>urn:example:1</id>
+    <title>Atom entry</title>
+    <link href="https://example.com/atom"/>
+    <link rel="replies" href="https://example.com/atom#comments"/>
+  </entry>
+</feed>"#;
+        let (feed, _) = parse_feed(xml.as_bytes()).unwrap();
+        let entries = extract_feed_entries(&feed);
+        assert_eq!(
+            entries[0].comments_url.as_deref(),
+            Some("https://example.com/atom#comments")
+        );
+    }
+
+    #[test]
+    fn test_extract_feed_links() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example Blog</title>
+  <link rel="alternate" href="https://example.com/"/>
+  <link rel="self" href="https://example.com/atom.xml"/>
+  <link rel="hub" href="https://pubsubhubbub.appspot.com/"/>
+  <entry>
+    <id>urn:example:1</id>
+    <title>Atom entry</title>
+    <link href="https://example.com/1"/>
+  </entry>
+</feed>"#;
+        let (feed, _) = parse_feed(xml.as_bytes()).unwrap();
+        assert_eq!(
+            extract_feed_links(&feed),
+            FeedLinks {
+                hub_url: Some("https://pubsubhubbub.appspot.com/".to_string()),
+                self_url: Some("https://example.com/atom.xml".to_string()),
+            }
+        );
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example Blog</title>
+  <link href="https://example.com/"/>
+</feed>"#;
+        let (feed, _) = parse_feed(xml.as_bytes()).unwrap();
+        assert_eq!(extract_feed_links(&feed), FeedLinks::default());
+    }
+
+    #[test]
+    fn test_parse_partial_feed() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Example Blog</title>
+    <item>
+      <title>Second</title>
+      <link>https://example.com/2</link>
+    </item>
+    <item>
+      <title>First</title>
+      <link>https://example.com/1</link>
+    </item>
+    <item>
+      <title>Broken</tit"#;
+        let (feed, parse_status) = parse_feed(xml.as_bytes()).unwrap();
+        assert_eq!(
+            parse_status,
+            ParseStatus::Partial {
+                recovered_entries: 2
+            }
+        );
+        let entries = extract_feed_entries(&feed);
+        assert_eq!(entries[0].url, "https://example.com/2");
+        assert_eq!(entries[1].url, "https://example.com/1");
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example Blog</title>
+  <entry>
+    <id>urn:1</id>
+    <title>First</title>
+    <link href="https://example.com/1"/>
+  </entry>
+  <entry>
+    <id>urn:2"#;
+        let (feed, parse_status) = parse_feed(xml.as_bytes()).unwrap();
+        assert_eq!(
+            parse_status,
+            ParseStatus::Partial {
+                recovered_entries: 1
+            }
+        );
+        assert_eq!(feed.entries[0].id, "urn:1");
+    }
+
+    #[test]
+    fn test_parse_complete_and_broken_feed() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Example Blog</title>
+<item><title>First</title><link>https://example.com/1</link></item>
+</channel></rss>"#;
+        let (_, parse_status) = parse_feed(xml.as_bytes()).unwrap();
+        assert_eq!(parse_status, ParseStatus::Complete);
+        assert!(parse_feed(b"<rss><channel><title>Broken").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "120"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let feed_url = format!("{}/feed.xml", server.uri());
+        let before = Utc::now();
+        let err = get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &feed_url,
+            None,
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        match err.downcast_ref::<BotError>() {
+            Some(BotError::FeedRateLimited { url, retry_after }) => {
+                assert_eq!(url, &feed_url);
+                let retry_after = retry_after.unwrap();
+                assert!(retry_after >= before + chrono::Duration::seconds(120));
+                assert!(retry_after <= Utc::now() + chrono::Duration::seconds(120));
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("120", now).unwrap().to_rfc3339(),
+            "2024-02-10T00:02:00+00:00"
+        );
+        assert_eq!(
+            parse_retry_after("Sat, 10 Feb 2024 01:00:00 GMT", now)
+                .unwrap()
+                .to_rfc3339(),
+            "2024-02-10T01:00:00+00:00"
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_json_feed() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                r#"
+{
+  "version": "https://jsonfeed.org/version/1.1",
+  "title": "JSON Blog",
+  "home_page_url": "https://example.com/",
+  "feed_url": "https://example.com/feed.json",
+  "items": [
+    {
+      "id": "2",
+      "url": "https://example.com/posts/2",
+      "title": "Second post",
+      "content_html": "<p>Body with <img src=\"https://example.com/cover.png\"></p>",
+      "date_published": "2024-02-10T09:00:00+09:00",
+      "tags": ["rust"]
+    },
+    {
+      "id": "1",
+      "url": "https://example.com/posts/1",
+      "title": "First post",
+      "content_text": "Plain text body",
+      "date_published": "2024-02-09T00:00:00Z"
+    },
+    {
+      "id": "0",
+      "content_text": "A note without a link"
+    }
+  ]
+}"#,
+                "application/feed+json",
+            ))
+            .mount(&server)
+            .await;
+        let fetched_feed = get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &format!("{}/feed.json", server.uri()),
+            None,
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetched_feed.parse_status, ParseStatus::Complete);
+        let feed = fetched_feed.feed;
+        assert_eq!(feed.title.as_ref().unwrap().content, "JSON Blog");
+        // リンクのないアイテムは投稿できないため読み飛ばす
+        let entries = extract_feed_entries(&feed);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, "2");
+        assert_eq!(entries[0].url, "https://example.com/posts/2");
+        assert_eq!(entries[0].title.as_deref(), Some("Second post"));
+        assert_eq!(
+            entries[0].published.unwrap().to_rfc3339(),
+            "2024-02-10T00:00:00+00:00"
+        );
+        assert_eq!(
+            entries[0].image_url.as_deref(),
+            Some("https://example.com/cover.png")
+        );
+        assert_eq!(entries[0].categories, vec!["rust"]);
+        assert_eq!(entries[1].summary.as_deref(), Some("Plain text body"));
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_unauthorized() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/private.xml"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/forbidden.xml"))
+            .respond_with(ResponseTemplate::new(403).set_body_string("Forbidden"))
+            .mount(&server)
+            .await;
+        for (feed_path, expected_status) in [("/private.xml", 401), ("/forbidden.xml", 403)] {
+            let feed_url = format!("{}{}", server.uri(), feed_path);
+            let err = get_feed_with_fetch_proxy(
+                &test_http_client(),
+                &feed_url,
+                None,
+                &FeedFetchOptions::default(),
+            )
+            .await
+            .unwrap_err();
+            match err.downcast_ref::<BotError>() {
+                Some(BotError::FeedUnauthorized { url, status }) => {
+                    assert_eq!(url, &feed_url);
+                    assert_eq!(*status, expected_status);
+                }
+                _ => panic!("unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_not_modified() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"v1\"")
+                    .insert_header("last-modified", "Sat, 10 Feb 2024 00:00:00 GMT")
+                    .set_body_string(
+                        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Conditional</title>
+<item><title>Entry 1</title><link>https://example.com/1</link></item>
+</channel></rss>"#,
+                    ),
+            )
+            .mount(&server)
+            .await;
+        let feed_url = format!("{}/feed.xml", server.uri());
+        let fetched_feed = get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &feed_url,
+            None,
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(extract_feed_entries(&fetched_feed.feed).len(), 1);
+        assert_eq!(
+            fetched_feed.validators,
+            FeedValidators {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: Some("Sat, 10 Feb 2024 00:00:00 GMT".to_string()),
+            }
+        );
+
+        // 保存したETagとLast-Modifiedを送り、304の場合はエントリーを返さない
+        let options = FeedFetchOptions {
+            validators: fetched_feed.validators,
+            ..Default::default()
+        };
+        let err = get_feed_with_fetch_proxy(&test_http_client(), &feed_url, None, &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BotError>(),
+            Some(BotError::FeedNotModified { .. })
+        ));
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests[1]
+            .headers
+            .keys()
+            .any(|name| name.as_str() == "if-modified-since"));
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_with_cookies() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .and(header("cookie", "session=abc"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Cookie gated</title>
+<item><title>Entry</title><link>https://example.com/1</link></item>
+</channel></rss>"#,
+            ))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(
+                ResponseTemplate::new(403).insert_header("set-cookie", "session=abc; Path=/"),
+            )
+            .mount(&server)
+            .await;
+        let feed_url = format!("{}/feed.xml", server.uri());
+        let err = get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &feed_url,
+            None,
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BotError>(),
+            Some(BotError::FeedUnauthorized { status: 403, .. })
+        ));
+
+        let options = FeedFetchOptions {
+            use_cookies: true,
+            ..Default::default()
+        };
+        let fetched_feed =
+            get_feed_with_fetch_proxy(&test_http_client(), &feed_url, None, &options)
+                .await
+                .unwrap();
+        assert_eq!(fetched_feed.feed.title.unwrap().content, "Cookie gated");
+        // 取得ごとにCookieを破棄するため、次の取得でも最初はCookieなしでリクエストする
+        get_feed_with_fetch_proxy(&test_http_client(), &feed_url, None, &options)
+            .await
+            .unwrap();
+        let has_cookie = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| request.headers.keys().any(|name| name.as_str() == "cookie"))
+            .collect::<Vec<_>>();
+        assert_eq!(has_cookie, vec![false, false, true, false, true]);
+        // Cookieを使わない設定では共有のクライアントにCookieが残らない
+        assert!(get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &feed_url,
+            None,
+            &FeedFetchOptions::default()
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_redirect_loop() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("location", format!("{}/feed.xml", server.uri()).as_str()),
+            )
+            .mount(&server)
+            .await;
+        let feed_url = format!("{}/feed.xml", server.uri());
+        let err = get_feed(&test_http_client(), &feed_url, &FeedFetchOptions::default())
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<BotError>() {
+            Some(BotError::RedirectLoop { url }) => assert_eq!(url, &feed_url),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        let err = get_og_image(&test_http_client(), &feed_url)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BotError>(),
+            Some(BotError::RedirectLoop { .. })
+        ));
+    }
+
+    const CLOUDFLARE_CHALLENGE_PAGE: &str = r#"<!DOCTYPE html><html lang="en-US"><head><title>Just a moment...</title><meta http-equiv="refresh" content="390"></head><body><div class="main-wrapper" role="main"><noscript>Enable JavaScript and cookies to continue</noscript></div><script>(function(){window._cf_chl_opt={cvId: '3',cZone: "example.com",cType: 'managed'};var a = document.createElement('script');a.src = '/cdn-cgi/challenge-platform/h/g/orchestrate/chl_page/v1?ray=8541';document.getElementsByTagName('head')[0].appendChild(a);}());</script></body></html>"#;
+
+    #[test]
+    fn test_is_cloudflare_challenge() {
+        assert!(is_cloudflare_challenge(
+            403,
+            None,
+            CLOUDFLARE_CHALLENGE_PAGE.as_bytes()
+        ));
+        assert!(is_cloudflare_challenge(200, Some("challenge"), b""));
+        assert!(!is_cloudflare_challenge(
+            200,
+            None,
+            CLOUDFLARE_CHALLENGE_PAGE.as_bytes()
+        ));
+        assert!(!is_cloudflare_challenge(403, None, b"Forbidden"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_retry() {
+        let retry_policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: std::time::Duration::from_millis(1),
+        };
+        let server = MockServer::start().await;
+        // 一時的な503の後に取得できる
+        Mock::given(method("GET"))
+            .and(path("/flaky.xml"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/flaky.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/down.xml"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/gone.xml"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        let client = reqwest::Client::new();
+        let fetch = |feed_path: &str| {
+            let feed_url = format!("{}{}", server.uri(), feed_path);
+            let client = client.clone();
+            async move {
+                fetch_with_retry(
+                    &client,
+                    &feed_url,
+                    &FeedValidators::default(),
+                    &retry_policy,
+                )
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+            }
+        };
+        let request_count = |feed_path: &'static str| {
+            let server = &server;
+            async move {
+                server
+                    .received_requests()
+                    .await
+                    .unwrap()
+                    .iter()
+                    .filter(|request| request.url.path() == feed_path)
+                    .count()
+            }
+        };
+        assert_eq!(fetch("/flaky.xml").await, 200);
+        assert_eq!(request_count("/flaky.xml").await, 3);
+        // 再試行しても失敗する場合は最後の応答を返す
+        assert_eq!(fetch("/down.xml").await, 502);
+        assert_eq!(request_count("/down.xml").await, 3);
+        // 404は再試行しない
+        assert_eq!(fetch("/gone.xml").await, 404);
+        assert_eq!(request_count("/gone.xml").await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_feed_blocked_by_challenge() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(CLOUDFLARE_CHALLENGE_PAGE))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/proxy"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Proxied</title>
+<item><title>Entry</title><link>https://example.com/1</link></item>
+</channel></rss>"#,
+            ))
+            .mount(&server)
+            .await;
+        let feed_url = format!("{}/feed.xml", server.uri());
+        let err = get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &feed_url,
+            None,
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        match err.downcast_ref::<BotError>() {
+            Some(BotError::Blocked { url }) => assert_eq!(url, &feed_url),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+
+        let fetch_proxy_url = format!("{}/proxy", server.uri());
+        let fetched_feed = get_feed_with_fetch_proxy(
+            &test_http_client(),
+            &feed_url,
+            Some(&fetch_proxy_url),
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetched_feed.feed.title.unwrap().content, "Proxied");
+        // モックサーバーは平文のためALPNでのネゴシエーションが行われずHTTP/1.1になる
+        assert_eq!(fetched_feed.http_version, Version::HTTP_11);
+        let requests = server.received_requests().await.unwrap();
+        let proxy_request = requests.last().unwrap();
+        assert_eq!(proxy_request.url.path(), "/proxy");
+        assert_eq!(
+            proxy_request
+                .url
+                .query_pairs()
+                .find(|(key, _)| key == "url")
+                .map(|(_, value)| value.to_string()),
+            Some(feed_url)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_feed_entry_info_skips_denylisted_domain() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head><meta property="og:title" content="OGP title"></head></html>"#,
+            ))
+            .mount(&server)
+            .await;
+        let feed_entry = FeedEntry {
+            id: "1".to_string(),
+            url: format!("{}/entry", server.uri()),
+            title: Some("Entry title".to_string()),
+            published: None,
+            updated: None,
+            summary: Some("Entry summary".to_string()),
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        };
+        let options = EntryInfoOptions {
+            ogp_skip_domains: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        };
+        let (ogp_info, og_image) =
+            extract_feed_entry_info(&test_http_client(), &feed_entry, &options)
+                .await
+                .unwrap();
+        let ogp_info = ogp_info.unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Entry title"));
+        assert_eq!(ogp_info.description.as_deref(), Some("Entry summary"));
+        assert!(og_image.is_none());
+        assert!(server.received_requests().await.unwrap().is_empty());
+
+        let (ogp_info, _) = extract_feed_entry_info(
+            &test_http_client(),
+            &feed_entry,
+            &EntryInfoOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ogp_info.unwrap().title.as_deref(), Some("OGP title"));
+    }
+
+    #[tokio::test]
+    async fn test_extract_feed_entry_info_same_origin_image() {
+        let server = MockServer::start().await;
+        let html = |image_url: &str| {
+            format!(
+                r#"<html><head><meta property="og:image" content="{}"></head></html>"#,
+                image_url
+            )
+        };
+        Mock::given(method("GET"))
+            .and(path("/same-origin"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(html(&format!("{}/cover.png", server.uri()))),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cross-origin"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(html("https://ads.example.org/pixel.gif")),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0x89, 0x50], "image/png"))
+            .mount(&server)
+            .await;
+        let feed_entry = |path: &str| FeedEntry {
+            id: path.to_string(),
+            url: format!("{}{}", server.uri(), path),
+            title: None,
+            published: None,
+            updated: None,
+            summary: None,
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        };
+        let options = EntryInfoOptions {
+            require_same_origin_image: true,
+            ..Default::default()
+        };
+        let (_, og_image) =
+            extract_feed_entry_info(&test_http_client(), &feed_entry("/same-origin"), &options)
+                .await
+                .unwrap();
+        assert_eq!(og_image.unwrap().content_type, "image/png");
+        let (ogp_info, og_image) =
+            extract_feed_entry_info(&test_http_client(), &feed_entry("/cross-origin"), &options)
+                .await
+                .unwrap();
+        assert_eq!(
+            ogp_info.unwrap().image_url.as_deref(),
+            Some("https://ads.example.org/pixel.gif")
+        );
+        assert!(og_image.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_extract_feed_entry_info_skips_undecodable_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/entry"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                r#"<html><head>
+<meta property="og:image" content="{0}/cover.avif">
+<meta name="twitter:image" content="{0}/cover.png">
+</head></html>"#,
+                server.uri()
+            )))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cover.avif"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(b"not decodable".to_vec(), "image/avif"),
+            )
+            .mount(&server)
+            .await;
+        let mut png_bytes: Vec<u8> = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4))
+            .write_to(
+                &mut std::io::Cursor::new(&mut png_bytes),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png_bytes, "image/png"))
+            .mount(&server)
+            .await;
+        let feed_entry = FeedEntry {
+            id: "1".to_string(),
+            url: format!("{}/entry", server.uri()),
+            title: None,
+            published: None,
+            updated: None,
+            summary: None,
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        };
+        let (_, og_image) = extract_feed_entry_info(
+            &test_http_client(),
+            &feed_entry,
+            &EntryInfoOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(og_image.unwrap().content_type, "image/png");
+        // 設定した順番で最初にデコードできた画像を使い、どれもデコードできなければ最初の画像を使う
+        let options = EntryInfoOptions {
+            image_source_order: parse_image_source_order("og:image"),
+            ..Default::default()
+        };
+        let (_, og_image) = extract_feed_entry_info(&test_http_client(), &feed_entry, &options)
+            .await
+            .unwrap();
+        assert_eq!(og_image.unwrap().content_type, "image/avif");
+    }
+
+    #[tokio::test]
+    async fn test_extract_feed_entry_info_waits_between_same_host_requests() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("user-agent", http_client::DEFAULT_USER_AGENT))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("<html><head><title>entry</title></head></html>"),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        let feed_entry = |entry_path: &str| FeedEntry {
+            id: entry_path.to_string(),
+            url: format!("{}{}", server.uri(), entry_path),
+            title: None,
+            published: None,
+            updated: None,
+            summary: None,
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        };
+        let options = EntryInfoOptions {
+            ogp_host_interval: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let started_at = std::time::Instant::now();
+        extract_feed_entry_info(&test_http_client(), &feed_entry("/first"), &options)
+            .await
+            .unwrap();
+        extract_feed_entry_info(&test_http_client(), &feed_entry("/second"), &options)
+            .await
+            .unwrap();
+        // 同じホストへの2回目の取得は間隔を空けてから行う
+        assert!(started_at.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_injected_client() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .and(header("x-run-id", "run-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<rss version="2.0"><channel><title>Example</title></channel></rss>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/entry"))
+            .and(header("x-run-id", "run-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head><meta property="og:title" content="Entry"></head></html>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .and(header("x-run-id", "run-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(b"png".to_vec(), "image/png"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // 渡されたクライアントの設定でリクエストを送る
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-run-id", "run-1".parse().unwrap());
+        let client = http_client::client_builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        let fetched_feed = get_feed(
+            &client,
+            &format!("{}/feed.xml", server.uri()),
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetched_feed.feed.title.unwrap().content, "Example");
+        let ogp_info = get_ogp_from_url(&client, &format!("{}/entry", server.uri()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Entry"));
+        let og_image = get_og_image(&client, &format!("{}/cover.png", server.uri()))
+            .await
+            .unwrap();
+        assert_eq!(og_image.content_type, "image/png");
+    }
+
+    #[test]
+    fn test_collect_image_urls() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>Example Blog</title>
+    <item>
+      <title>Entry</title>
+      <link>https://example.com/entry</link>
+      <description><![CDATA[<img src="https://example.com/content.png">Body]]></description>
+      <media:thumbnail url="https://example.com/thumbnail.jpg"/>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        assert_eq!(
+            feed_entry.media_thumbnail_url.as_deref(),
+            Some("https://example.com/thumbnail.jpg")
+        );
+        let ogp_info = OGPInfo {
+            title: None,
+            image_url: Some("https://example.com/og.png".to_string()),
+            twitter_image_url: Some("https://example.com/og.png".to_string()),
+            description: None,
+        };
+        assert_eq!(
+            collect_image_urls(&feed_entry, Some(&ogp_info), &EntryInfoOptions::default()),
+            vec![
+                "https://example.com/og.png",
+                "https://example.com/thumbnail.jpg",
+                "https://example.com/content.png"
+            ]
+        );
+        let options = EntryInfoOptions {
+            image_source_order: parse_image_source_order("content, media:thumbnail, unknown"),
+            ..Default::default()
+        };
+        assert_eq!(
+            collect_image_urls(&feed_entry, Some(&ogp_info), &options),
+            vec![
+                "https://example.com/content.png",
+                "https://example.com/thumbnail.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ogp_info_without_meta_tags() {
+        assert!(parse_ogp_info("").is_none());
+        assert!(parse_ogp_info("<<<html><head<meta property=>>></body").is_none());
+        assert!(parse_ogp_info("<html><head></head><body><p>No tags</p></body></html>").is_none());
+        // OGPがなくても一般的なタグだけで補う
+        let ogp_info = parse_ogp_info(
+            r#"<html><head><meta name="description" content="Not OGP"></head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(ogp_info.title, None);
+        assert_eq!(ogp_info.description.as_deref(), Some("Not OGP"));
+        let ogp_info =
+            parse_ogp_info("<html><head><title>Document title</title></head></html>").unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Document title"));
+        let ogp_info = parse_ogp_info(
+            r#"<html><head><link rel="image_src" href="https://example.com/image_src.jpg"></head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ogp_info.image_url.as_deref(),
+            Some("https://example.com/image_src.jpg")
+        );
+        let ogp_info = parse_ogp_info(
+            r#"<html><head><meta property="og:title" content="OGP title"><body><p>unclosed"#,
+        )
+        .unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("OGP title"));
+        assert_eq!(ogp_info.image_url, None);
+    }
+
+    #[test]
+    fn test_resolve_ogp_image_urls() {
+        let page_url = Url::parse("https://example.com/posts/2024/entry.html").unwrap();
+        let resolve = |image_url: &str| {
+            let ogp_info = OGPInfo {
+                title: None,
+                image_url: Some(image_url.to_string()),
+                twitter_image_url: Some(image_url.to_string()),
+                description: None,
+            };
+            let ogp_info = resolve_ogp_image_urls(ogp_info, &page_url);
+            assert_eq!(ogp_info.image_url, ogp_info.twitter_image_url);
+            ogp_info.image_url
+        };
+        assert_eq!(
+            resolve("https://cdn.example.net/cover.png").as_deref(),
+            Some("https://cdn.example.net/cover.png")
+        );
+        assert_eq!(
+            resolve("/images/cover.png").as_deref(),
+            Some("https://example.com/images/cover.png")
+        );
+        assert_eq!(
+            resolve("//cdn.example.net/x.jpg").as_deref(),
+            Some("https://cdn.example.net/x.jpg")
+        );
+        assert_eq!(
+            resolve(" cover.png ").as_deref(),
+            Some("https://example.com/posts/2024/cover.png")
+        );
+        assert_eq!(
+            resolve("../cover.png").as_deref(),
+            Some("https://example.com/posts/cover.png")
+        );
+        // data: URIはそのまま残し、解決できないものは使わない
+        assert_eq!(
+            resolve("data:image/png;base64,iVBORw0KGgo=").as_deref(),
+            Some("data:image/png;base64,iVBORw0KGgo=")
+        );
+        assert_eq!(resolve("http://[::1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_ogp_from_url_with_relative_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/entry"))
+            .respond_with(ResponseTemplate::new(301).insert_header("location", "/posts/entry.html"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/posts/entry.html"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+<meta property="og:title" content="Entry">
+<meta property="og:image" content="images/cover.png">
+</head></html>"#,
+            ))
+            .mount(&server)
+            .await;
+        let ogp_info = get_ogp_from_url(&test_http_client(), &format!("{}/entry", server.uri()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ogp_info.image_url,
+            Some(format!("{}/posts/images/cover.png", server.uri()))
+        );
+    }
+
+    #[test]
+    fn test_decode_html_entities_in_titles() {
+        // XMLとして展開された後も文字参照が残る、二重にエスケープされたタイトル
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Example Blog</title>
+    <item>
+      <title>Rust &amp;amp; WebAssembly &amp;#8212; It&amp;#39;s &amp;quot;fast&amp;quot;</title>
+      <link>https://example.com/entry</link>
+    </item>
+  </channel>
+</rss>"#;
+        let feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        assert_eq!(
+            feed_entry.title.as_deref(),
+            Some("Rust & WebAssembly — It's \"fast\"")
+        );
+
+        let ogp_info = parse_ogp_info(
+            r#"<html><head>
+<meta property="og:title" content="Rust &amp;amp; WebAssembly">
+<meta property="og:description" content="&amp;lt;script&amp;gt; &amp;hellip;">
+</head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Rust & WebAssembly"));
+        assert_eq!(ogp_info.description.as_deref(), Some("<script> …"));
+    }
+
+    #[test]
+    fn test_parse_ogp_info_with_twitter_card() {
+        let ogp_info = parse_ogp_info(
+            r#"<html><head>
+<title>Document title | Example Blog</title>
+<meta name="description" content="Meta description">
+<meta name="twitter:card" content="summary_large_image">
+<meta name="twitter:title" content="Twitter title">
+<meta name="twitter:description" content="Twitter description">
+<meta name="twitter:image" content="https://example.com/twitter.png">
+</head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Twitter title"));
+        assert_eq!(ogp_info.description.as_deref(), Some("Twitter description"));
+        assert_eq!(
+            ogp_info.image_url.as_deref(),
+            Some("https://example.com/twitter.png")
+        );
+        assert_eq!(
+            ogp_info.twitter_image_url.as_deref(),
+            Some("https://example.com/twitter.png")
+        );
+
+        // property属性で書かれたTwitter Cardと、<title>・description・image_srcでの補完
+        let ogp_info = parse_ogp_info(
+            r#"<html><head>
+<title>
+  Document title
+</title>
+<meta name="description" content="Meta description">
+<meta property="twitter:image:src" content="https://example.com/twitter.png">
+</head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Document title"));
+        assert_eq!(ogp_info.description.as_deref(), Some("Meta description"));
+        assert_eq!(
+            ogp_info.image_url.as_deref(),
+            Some("https://example.com/twitter.png")
+        );
+        let ogp_info = parse_ogp_info(
+            r#"<html><head>
+<meta property="og:title" content="OGP title">
+<link rel="image_src" href="https://example.com/image_src.jpg">
+</head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ogp_info.image_url.as_deref(),
+            Some("https://example.com/image_src.jpg")
+        );
+        assert_eq!(ogp_info.twitter_image_url, None);
+    }
+
+    #[test]
+    fn test_parse_ogp_info_with_video_poster() {
+        let ogp_info = parse_ogp_info(
+            r#"<html><head>
+<meta property="og:title" content="Video title">
+<meta property="og:video" content="https://example.com/video.mp4">
+<meta property="og:video:poster" content="https://example.com/poster.jpg">
+</head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ogp_info.image_url.as_deref(),
+            Some("https://example.com/poster.jpg")
+        );
+        // og:imageがあればそちらを優先する
+        let ogp_info = parse_ogp_info(
+            r#"<html><head>
+<meta property="og:video:image" content="https://example.com/poster.jpg">
+<meta property="og:image" content="https://example.com/cover.png">
+</head></html>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ogp_info.image_url.as_deref(),
+            Some("https://example.com/cover.png")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_feed_entry_info_without_ogp() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html><body><div>"))
+            .mount(&server)
+            .await;
+        let feed_entry = FeedEntry {
+            id: "1".to_string(),
+            url: format!("{}/entry", server.uri()),
+            title: Some("Entry title".to_string()),
+            published: None,
+            updated: None,
+            summary: Some("Entry summary".to_string()),
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        };
+        let (ogp_info, og_image) = extract_feed_entry_info(
+            &test_http_client(),
+            &feed_entry,
+            &EntryInfoOptions::default(),
+        )
+        .await
+        .unwrap();
+        let ogp_info = ogp_info.unwrap();
+        assert_eq!(ogp_info.title.as_deref(), Some("Entry title"));
+        assert_eq!(ogp_info.description.as_deref(), Some("Entry summary"));
+        assert!(og_image.is_none());
+    }
+
+    #[test]
+    fn test_build_image_proxy_url() {
+        let url = build_image_proxy_url(
+            "https://images.weserv.nl/",
+            "https://example.com/images/cover.png?v=1&size=large",
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://images.weserv.nl/?url=https%3A%2F%2Fexample.com%2Fimages%2Fcover.png%3Fv%3D1%26size%3Dlarge&w=1000&h=1000&fit=inside&output=jpg"
+        );
+        assert!(build_image_proxy_url("not a url", "https://example.com/a.png").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_og_image_via_proxy() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/proxy"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"resized".to_vec()))
+            .mount(&server)
+            .await;
+        let og_image = fetch_og_image(
+            &test_http_client(),
+            &format!("{}/cover.png", server.uri()),
+            Some(&format!("{}/proxy", server.uri())),
+        )
+        .await
+        .unwrap();
+        assert!(og_image.resized);
+        assert_eq!(og_image.content_type, "image/jpeg");
+        assert_eq!(og_image.image, Bytes::from_static(b"resized"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_og_image_falls_back_when_proxy_fails() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/proxy"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/png")
+                    .set_body_bytes(b"original".to_vec()),
+            )
+            .mount(&server)
+            .await;
+        let og_image = fetch_og_image(
+            &test_http_client(),
+            &format!("{}/cover.png", server.uri()),
+            Some(&format!("{}/proxy", server.uri())),
+        )
+        .await
+        .unwrap();
+        assert!(!og_image.resized);
+        assert_eq!(og_image.content_type, "image/png");
+        assert_eq!(og_image.image, Bytes::from_static(b"original"));
+    }
+
+    #[tokio::test]
+    async fn test_get_og_image_without_content_type() {
+        let server = MockServer::start().await;
+        let png_bytes = DATA_URI_BASE64
+            .decode(DATA_URI_PNG.trim_start_matches("data:image/png;base64,"))
+            .unwrap();
+        Mock::given(method("GET"))
+            .and(path("/cover"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(png_bytes))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/missing.png"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .insert_header("content-type", "text/html")
+                    .set_body_string("not found"),
+            )
+            .mount(&server)
+            .await;
+        let og_image = get_og_image(&test_http_client(), &format!("{}/cover", server.uri()))
+            .await
+            .unwrap();
+        assert_eq!(og_image.content_type, "image/png");
+        // エラーページを画像として扱わない
+        assert!(get_og_image(
+            &test_http_client(),
+            &format!("{}/missing.png", server.uri())
+        )
+        .await
+        .is_err());
+    }
+
+    // 2x1の赤と青のPNG
+    const DATA_URI_PNG: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAIAAAB7QOjdAAAADUlEQVR4nGP4zwAE/wEHAAH/4iOeWQAAAABJRU5ErkJggg==";
+
+    #[tokio::test]
+    async fn test_get_og_image_from_data_uri() {
+        let og_image = get_og_image(&test_http_client(), DATA_URI_PNG)
+            .await
+            .unwrap();
+        assert_eq!(og_image.content_type, "image/png");
+        assert!(!og_image.resized);
+        let image = image::load_from_memory(&og_image.image).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        // パディングの省略、折り返し、大文字のスキームも受け付ける
+        let data_uri = DATA_URI_PNG
+            .trim_end_matches('=')
+            .replacen("data:image/png", "DATA:image/PNG", 1)
+            .replacen("AAAA", "AAAA\n  ", 1);
+        let og_image = get_og_image(&test_http_client(), &data_uri).await.unwrap();
+        assert_eq!(og_image.content_type, "image/png");
+        assert!(image::load_from_memory(&og_image.image).is_ok());
+        // 画像以外やbase64でないものはエラーにする
+        assert!(
+            get_og_image(&test_http_client(), "data:text/plain;base64,aGVsbG8=")
+                .await
+                .is_err()
+        );
+        assert!(get_og_image(
+            &test_http_client(),
+            "data:image/svg+xml,%3Csvg%3E%3C/svg%3E"
+        )
+        .await
+        .is_err());
+        assert!(
+            get_og_image(&test_http_client(), "data:image/png;base64,!!!")
+                .await
+                .is_err()
+        );
+    }
+
+    // 1x1のWebP(VP8)
+    const DATA_URI_WEBP: &str =
+        "data:image/webp;base64,UklGRiIAAABXRUJQVlA4IBYAAAAwAQCdASoBAAEADsD+JaQAA3AAAAAA";
+
+    #[tokio::test]
+    async fn test_fetch_first_decodable_image_webp() {
+        // デコードできないAVIFの次の候補にあるWebPを使う
+        let og_image = fetch_first_decodable_image(
+            &test_http_client(),
+            &[
+                "data:image/avif;base64,AAAA".to_string(),
+                DATA_URI_WEBP.to_string(),
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(og_image.content_type, "image/webp");
+        let image = image::load_from_memory(&og_image.image).unwrap();
+        assert_eq!((image.width(), image.height()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_first_decodable_image_from_data_uri() {
+        // 画像プロキシを設定していてもdata: URIはそのままデコードする
+        let og_image = fetch_first_decodable_image(
+            &test_http_client(),
+            &[DATA_URI_PNG.to_string()],
+            Some("http://127.0.0.1:9/proxy"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(og_image.content_type, "image/png");
+        assert!(!og_image.resized);
+    }
 
     #[tokio::test]
     async fn test_get_rss_feed() {
-        let feed = get_feed("https://zed.dev/blog.rss").await.unwrap();
+        let feed = get_feed(
+            &test_http_client(),
+            "https://zed.dev/blog.rss",
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap()
+        .feed;
         println!("{:?}", feed);
         let entries = extract_feed_entries(&feed);
         println!("{:?}", entries);
-        let entry = entries.get(0).unwrap();
-        let ogp_info = get_ogp_from_url(&entry.url).await.unwrap();
+        let entry = entries.first().unwrap();
+        let ogp_info = get_ogp_from_url(&test_http_client(), &entry.url)
+            .await
+            .unwrap()
+            .unwrap();
         println!("{:?}", ogp_info);
-        let og_image = get_og_image(&ogp_info.image_url.unwrap()).await.unwrap();
+        let og_image = get_og_image(&test_http_client(), &ogp_info.image_url.unwrap())
+            .await
+            .unwrap();
         println!("{:?}", og_image);
     }
 
     #[tokio::test]
     async fn test_get_atom_feed() {
-        let feed = get_feed("https://blog.rust-lang.org/feed.xml")
-            .await
-            .unwrap();
+        let feed = get_feed(
+            &test_http_client(),
+            "https://blog.rust-lang.org/feed.xml",
+            &FeedFetchOptions::default(),
+        )
+        .await
+        .unwrap()
+        .feed;
         let entries = extract_feed_entries(&feed);
         println!("{:?}", entries);
-        let entry = entries.get(0).unwrap();
-        let ogp_info = get_ogp_from_url(&entry.url).await.unwrap();
+        let entry = entries.first().unwrap();
+        let ogp_info = get_ogp_from_url(&test_http_client(), &entry.url)
+            .await
+            .unwrap()
+            .unwrap();
         println!("{:?}", ogp_info);
-        let og_image = get_og_image(&ogp_info.image_url.unwrap()).await.unwrap();
+        let og_image = get_og_image(&test_http_client(), &ogp_info.image_url.unwrap())
+            .await
+            .unwrap();
         println!("{:?}", og_image);
     }
 }
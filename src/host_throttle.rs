@@ -0,0 +1,67 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+use url::Url;
+
+// 同じホストへのリクエストの間隔を空け、短時間に集中してアクセスしないようにする
+#[derive(Default)]
+pub struct HostThrottle {
+    // ホストごとに次にリクエストしてよい時刻
+    next_allowed_at: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostThrottle {
+    // 並行して呼ばれても、同じホストへのリクエストは順にmin_intervalずつ空ける
+    pub async fn wait(&self, url: &str, min_interval: Duration) {
+        if min_interval.is_zero() {
+            return;
+        }
+        let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_lowercase()))
+        else {
+            return;
+        };
+        let now = Instant::now();
+        let allowed_at = {
+            let mut next_allowed_at = self.next_allowed_at.lock().unwrap();
+            let allowed_at = next_allowed_at
+                .get(&host)
+                .map_or(now, |next_allowed_at| (*next_allowed_at).max(now));
+            next_allowed_at.insert(host, allowed_at + min_interval);
+            allowed_at
+        };
+        tokio::time::sleep_until(allowed_at).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_host_throttle() {
+        let host_throttle = HostThrottle::default();
+        let min_interval = Duration::from_millis(200);
+        let started_at = Instant::now();
+        host_throttle
+            .wait("https://example.com/posts/1", min_interval)
+            .await;
+        // 別のホストは待たない
+        host_throttle
+            .wait("https://example.org/posts/1", min_interval)
+            .await;
+        assert!(started_at.elapsed() < min_interval);
+        // 同じホストへの2回目は間隔を空ける
+        host_throttle
+            .wait("https://EXAMPLE.com/posts/2", min_interval)
+            .await;
+        assert!(started_at.elapsed() >= min_interval);
+        // 間隔が0の場合は待たない
+        let started_at = Instant::now();
+        host_throttle
+            .wait("https://example.com/posts/3", Duration::ZERO)
+            .await;
+        assert!(started_at.elapsed() < min_interval);
+    }
+}
@@ -0,0 +1,70 @@
+use std::{env, time::Duration};
+
+use reqwest::{header::HeaderValue, redirect};
+
+use crate::OpaqueError;
+
+// 取得先のサーバーの管理者がボットからのアクセスだと分かるようにする
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "bsky-feed-bot/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/yuki-oshima-revenant/bsky-feed-bot)"
+);
+
+// 応答しないサーバーで実行が止まらないようにする
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// リダイレクトがループしている記事のページで止まらないようにする
+const MAX_REDIRECTS: usize = 10;
+
+// 空の場合やヘッダーに使えない文字を含む場合は既定のUser-Agentを使う
+fn user_agent_or_default(user_agent: Option<String>) -> String {
+    user_agent
+        .map(|user_agent| user_agent.trim().to_string())
+        .filter(|user_agent| !user_agent.is_empty() && HeaderValue::from_str(user_agent).is_ok())
+        .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string())
+}
+
+pub fn user_agent() -> String {
+    user_agent_or_default(env::var("BOT_USER_AGENT").ok())
+}
+
+// 外部へのリクエストはすべてこのビルダーから作ったクライアントで送る
+pub fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(user_agent())
+        .timeout(HTTP_TIMEOUT)
+        .connect_timeout(HTTP_CONNECT_TIMEOUT)
+        .redirect(redirect::Policy::limited(MAX_REDIRECTS))
+}
+
+// TLSの接続を使い回すため、実行ごとに1つだけ作って各処理に渡す
+// reqwest 0.11はHTTP/2を常に組み込んでおり、HTTPSではALPNでHTTP/2を優先して対応していないサーバーとはHTTP/1.1で通信する
+pub fn build_client() -> Result<reqwest::Client, OpaqueError> {
+    Ok(client_builder().build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent_or_default() {
+        assert_eq!(user_agent_or_default(None), DEFAULT_USER_AGENT);
+        assert_eq!(
+            user_agent_or_default(Some(" ".to_string())),
+            DEFAULT_USER_AGENT
+        );
+        assert_eq!(
+            user_agent_or_default(Some("my-bot\n1.0".to_string())),
+            DEFAULT_USER_AGENT
+        );
+        assert_eq!(
+            user_agent_or_default(Some("my-bot/1.0 (+https://example.com)".to_string())),
+            "my-bot/1.0 (+https://example.com)"
+        );
+        assert!(
+            DEFAULT_USER_AGENT.starts_with(&format!("bsky-feed-bot/{}", env!("CARGO_PKG_VERSION")))
+        );
+    }
+}
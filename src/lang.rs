@@ -0,0 +1,268 @@
+use whatlang::Lang;
+
+use crate::text::grapheme_len;
+
+// 短いタイトルは誤判定しやすいため、この文字数未満では判定しない
+pub const DEFAULT_MIN_DETECTION_GRAPHEMES: usize = 20;
+pub const DEFAULT_MIN_DETECTION_CONFIDENCE: f64 = 0.8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LangDetectionThresholds {
+    pub min_graphemes: usize,
+    // 判定結果の信頼度(0.0〜1.0)がこの値以上の場合のみ判定結果を使う
+    pub min_confidence: f64,
+}
+
+impl Default for LangDetectionThresholds {
+    fn default() -> Self {
+        Self {
+            min_graphemes: DEFAULT_MIN_DETECTION_GRAPHEMES,
+            min_confidence: DEFAULT_MIN_DETECTION_CONFIDENCE,
+        }
+    }
+}
+
+// 判定できない場合はフィードごとに設定された既定の言語か、フィードで宣言された言語を使う
+pub fn detect_langs(
+    text: &str,
+    fallback_lang: Option<&str>,
+    thresholds: &LangDetectionThresholds,
+) -> Vec<String> {
+    let detected = if grapheme_len(text.trim()) < thresholds.min_graphemes {
+        None
+    } else {
+        whatlang::detect(text)
+            .filter(|info| info.is_reliable() && info.confidence() >= thresholds.min_confidence)
+            .map(|info| to_iso_639_1(info.lang()))
+    };
+    detected
+        .or(fallback_lang)
+        .map(|lang| vec![lang.to_string()])
+        .unwrap_or_default()
+}
+
+// "日本語=ja, English=en"の形式でカテゴリーと言語の対応を設定する
+pub fn parse_category_langs(category_langs: &str) -> Vec<(String, String)> {
+    category_langs
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(category, lang)| (category.trim().to_string(), lang.trim().to_string()))
+        .filter(|(category, lang)| !category.is_empty() && !lang.is_empty())
+        .collect()
+}
+
+// "ja,en"の形式で言語を設定する
+pub fn parse_langs(langs: &str) -> Vec<String> {
+    langs
+        .split(',')
+        .map(|lang| lang.trim().to_string())
+        .filter(|lang| !lang.is_empty())
+        .collect()
+}
+
+// 言語ごとにカテゴリーを分けている集約フィードでは、判定より対応するカテゴリーを優先する
+pub fn langs_from_categories(
+    categories: &[String],
+    category_langs: &[(String, String)],
+) -> Vec<String> {
+    let mut langs: Vec<String> = Vec::new();
+    for category in categories {
+        let lang = category_langs
+            .iter()
+            .find(|(mapped_category, _)| mapped_category == category.trim())
+            .map(|(_, lang)| lang);
+        if let Some(lang) = lang.filter(|lang| !langs.contains(lang)) {
+            langs.push(lang.clone());
+        }
+    }
+    langs
+}
+
+fn to_iso_639_1(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Epo => "eo",
+        Lang::Eng => "en",
+        Lang::Rus => "ru",
+        Lang::Cmn => "zh",
+        Lang::Spa => "es",
+        Lang::Por => "pt",
+        Lang::Ita => "it",
+        Lang::Ben => "bn",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Ukr => "uk",
+        Lang::Kat => "ka",
+        Lang::Ara => "ar",
+        Lang::Hin => "hi",
+        Lang::Jpn => "ja",
+        Lang::Heb => "he",
+        Lang::Yid => "yi",
+        Lang::Pol => "pl",
+        Lang::Amh => "am",
+        Lang::Jav => "jv",
+        Lang::Kor => "ko",
+        Lang::Nob => "nb",
+        Lang::Dan => "da",
+        Lang::Swe => "sv",
+        Lang::Fin => "fi",
+        Lang::Tur => "tr",
+        Lang::Nld => "nl",
+        Lang::Hun => "hu",
+        Lang::Ces => "cs",
+        Lang::Ell => "el",
+        Lang::Bul => "bg",
+        Lang::Bel => "be",
+        Lang::Mar => "mr",
+        Lang::Kan => "kn",
+        Lang::Ron => "ro",
+        Lang::Slv => "sl",
+        Lang::Hrv => "hr",
+        Lang::Srp => "sr",
+        Lang::Mkd => "mk",
+        Lang::Lit => "lt",
+        Lang::Lav => "lv",
+        Lang::Est => "et",
+        Lang::Tam => "ta",
+        Lang::Vie => "vi",
+        Lang::Urd => "ur",
+        Lang::Tha => "th",
+        Lang::Guj => "gu",
+        Lang::Uzb => "uz",
+        Lang::Pan => "pa",
+        Lang::Aze => "az",
+        Lang::Ind => "id",
+        Lang::Tel => "te",
+        Lang::Pes => "fa",
+        Lang::Mal => "ml",
+        Lang::Ori => "or",
+        Lang::Mya => "my",
+        Lang::Nep => "ne",
+        Lang::Sin => "si",
+        Lang::Khm => "km",
+        Lang::Tuk => "tk",
+        Lang::Aka => "ak",
+        Lang::Zul => "zu",
+        Lang::Sna => "sn",
+        Lang::Afr => "af",
+        Lang::Lat => "la",
+        Lang::Slk => "sk",
+        Lang::Cat => "ca",
+        Lang::Tgl => "tl",
+        Lang::Hye => "hy",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_langs() {
+        assert_eq!(
+            detect_langs(
+                "Rustの非同期ランタイムについて詳しく解説します。今回はtokioの内部構造を見ていきます。",
+                Some("en"),
+                &LangDetectionThresholds::default()
+            ),
+            vec!["ja".to_string()]
+        );
+        assert_eq!(
+            detect_langs(
+                "The Rust team is happy to announce a new version of Rust, 1.76.0.",
+                None,
+                &LangDetectionThresholds::default()
+            ),
+            vec!["en".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_parse_langs() {
+        assert_eq!(
+            parse_langs("ja, en,"),
+            vec!["ja".to_string(), "en".to_string()]
+        );
+        assert!(parse_langs("").is_empty());
+    }
+
+    #[test]
+    fn test_langs_from_categories() {
+        let category_langs = parse_category_langs("日本語=ja, English = en,invalid");
+        assert_eq!(
+            category_langs,
+            vec![
+                ("日本語".to_string(), "ja".to_string()),
+                ("English".to_string(), "en".to_string())
+            ]
+        );
+        assert_eq!(
+            langs_from_categories(&["Rust".to_string(), "日本語".to_string()], &category_langs),
+            vec!["ja".to_string()]
+        );
+        assert!(langs_from_categories(&["Rust".to_string()], &category_langs).is_empty());
+    }
+
+    #[test]
+    fn test_detect_langs_short_text_uses_default() {
+        // 短い日本語のタイトルは英語と誤判定されやすいので既定の言語を使う
+        assert_eq!(
+            detect_langs(
+                "Rust 1.76.0 リリース",
+                Some("ja"),
+                &LangDetectionThresholds::default()
+            ),
+            vec!["ja".to_string()]
+        );
+        assert_eq!(
+            detect_langs(
+                "Rust 1.76.0",
+                Some("ja"),
+                &LangDetectionThresholds::default()
+            ),
+            vec!["ja".to_string()]
+        );
+        assert!(detect_langs("Rust 1.76.0", None, &LangDetectionThresholds::default()).is_empty());
+    }
+
+    #[test]
+    fn test_detect_langs_thresholds() {
+        let long_text = "The Rust team is happy to announce a new version of Rust, 1.76.0.";
+        let confidence = whatlang::detect(long_text).unwrap().confidence();
+        let thresholds = LangDetectionThresholds {
+            min_graphemes: grapheme_len(long_text),
+            min_confidence: confidence,
+        };
+        // 文字数と信頼度がどちらも閾値ちょうどであれば判定結果を使う
+        assert_eq!(
+            detect_langs(long_text, Some("ja"), &thresholds),
+            vec!["en".to_string()]
+        );
+        let thresholds = LangDetectionThresholds {
+            min_graphemes: grapheme_len(long_text) + 1,
+            min_confidence: confidence,
+        };
+        assert_eq!(
+            detect_langs(long_text, Some("ja"), &thresholds),
+            vec!["ja".to_string()]
+        );
+        let thresholds = LangDetectionThresholds {
+            min_graphemes: 0,
+            min_confidence: confidence + 0.01,
+        };
+        assert_eq!(
+            detect_langs(long_text, Some("ja"), &thresholds),
+            vec!["ja".to_string()]
+        );
+        // 閾値を下げると短いタイトルも判定する
+        let short_text = "Rust 1.76.0 リリース";
+        let thresholds = LangDetectionThresholds {
+            min_graphemes: 0,
+            min_confidence: 0.0,
+        };
+        assert_eq!(detect_langs(short_text, None, &thresholds).len(), 1);
+        assert_eq!(
+            detect_langs(short_text, None, &LangDetectionThresholds::default()),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -1,72 +1,1119 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
 use aws_config::BehaviorVersion;
 use aws_lambda_events::eventbridge::EventBridgeEvent;
-use bsky::BskyClient;
+use bsky::{get_max_thumb_bytes, is_unsupported_blob_error, BskyClient, CreateRecordResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
 use dynamodb::{list_registered_feeds, FeedRecord};
-use feed::{extract_feed_entries, extract_feed_entry_info, get_feed};
+use feed::{
+    extract_feed_entries, extract_feed_entry_info, get_feed, EntryInfoOptions, FeedEntry,
+    FeedFetchOptions, FeedValidators, OGImage, OGPInfo, ParseStatus,
+};
+use feed_rs::model::Feed;
 use lambda_runtime::{service_fn, LambdaEvent};
+use report::{
+    post_run_summary, report_destination_from_env, summarize_run, webhook_destination_from_env,
+    write_run_report, FeedReport, PostedEntry, RunReport, RunSummary, SkippedEntry,
+};
+use serde::Deserialize;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use tracing_subscriber::EnvFilter;
 
-use crate::dynamodb::update_feed_last_posted_entry_id;
+use crate::{
+    concurrency::map_bounded_concurrent,
+    config::{env_flag, env_parse},
+    dynamodb::{
+        get_post_rate_window, put_post_rate_window, update_feed_failure_state,
+        update_feed_last_posted_entry_id, update_feed_last_posted_published, update_feed_links,
+        update_feed_next_fetch_allowed_at, update_feed_next_post_allowed_at,
+        update_feed_validators,
+    },
+    error::{error_category, find_bot_error, BotError},
+    pause::{is_paused, DynamoDbPauseStore},
+    post_progress::PostProgress,
+    posted_entries::{find_updated_entries, record_posted_entry, DynamoDbPostedEntryStore},
+    rate_limit::PostRateLimiter,
+    run_lock::{acquire_run_lock, release_run_lock, DynamoDbRunLockStore},
+    session_cache::DynamoDbSessionStore,
+    text::grapheme_len,
+    urls::{canonicalize_url, entry_url_dedup_key, normalize_dedup_url},
+};
 
+mod audit;
 mod bsky;
+mod cli;
+mod concurrency;
+mod config;
+mod duplicate_feeds;
 mod dynamodb;
+mod error;
 mod feed;
+mod host_throttle;
+mod http_client;
+mod lang;
+mod opml;
+mod pause;
+mod post_progress;
+mod posted_entries;
+mod rate_limit;
+mod report;
+mod retry;
+mod richtext;
+mod run_lock;
+mod session_cache;
+mod text;
+mod urls;
 
 pub type OpaqueError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+// listRecordsで一度に取得できる最大件数
+const SEED_POST_LIMIT: u32 = 100;
+
+const DEFAULT_OGP_FETCH_CONCURRENCY: usize = 4;
+
+// 同時に処理するフィードの数。取得先のサーバーやメモリに負荷をかけすぎないよう制限する
+const DEFAULT_FEED_CONCURRENCY: usize = 8;
+
+// 失敗したフィードを次に処理するまでの待ち時間(分)。失敗が続くごとに倍にする。0の場合は待たない
+const DEFAULT_ERROR_COOLDOWN_BASE_MINUTES: i64 = 0;
+const DEFAULT_ERROR_COOLDOWN_MAX_MINUTES: i64 = 24 * 60;
+const ERROR_COOLDOWN_SKIP_REASON: &str = "cooling down after errors";
+const NOT_MODIFIED_SKIP_REASON: &str = "not modified";
+const DRY_RUN_SKIP_REASON: &str = "dry run";
+const FUTURE_ENTRY_SKIP_REASON: &str = "published in the future";
+// RUST_LOGが未設定の場合は、依存クレートのログを警告以上に絞る
+const DEFAULT_LOG_FILTER: &str = "warn,bsky_feed_bot=info";
+
+// last_posted_entry_idを見失った場合にもタイムラインを埋め尽くさないよう、1回の実行で投稿する件数を抑える
+const DEFAULT_MAX_ENTRIES_PER_RUN: usize = 5;
+// 古いブログを登録したときに過去の記事をまとめて投稿しないよう、公開からこの日数を過ぎたエントリーは投稿しない。0の場合は無効
+const DEFAULT_MAX_ENTRY_AGE_DAYS: i64 = 0;
+
+// 同じタイトルのエントリーがこの件数以上ある場合に警告する
+const SHARED_TITLE_WARNING_THRESHOLD: usize = 3;
+
+// 0の場合は無効。有効にする場合はLambdaの最大実行時間より長くし、設定テーブルへのPutItemとDeleteItemを実行ロールに許可する
+const DEFAULT_RUN_LOCK_TTL_SECONDS: i64 = 0;
+
 #[tokio::main]
 async fn main() -> Result<(), lambda_runtime::Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = cli::parse_command(&args)? {
+        dotenvy::dotenv().ok();
+        init_tracing();
+        cli::run_command(command).await?;
+        return Ok(());
+    }
+    init_tracing();
     lambda_runtime::run(service_fn(lambda_handler)).await?;
     Ok(())
 }
 
+// Lambdaの環境ではCloudWatch Logs Insightsでフィールドを検索できるようJSONで出力する
+fn init_tracing() {
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(env_filter);
+    if std::env::var("AWS_LAMBDA_FUNCTION_NAME").is_ok() {
+        subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init();
+    } else {
+        subscriber.init();
+    }
+}
+
 async fn lambda_handler(
-    _: LambdaEvent<EventBridgeEvent<serde_json::Value>>,
+    event: LambdaEvent<EventBridgeEvent<serde_json::Value>>,
 ) -> Result<(), lambda_runtime::Error> {
-    match execute().await {
+    let result = match parse_post_now_request(&event.payload.detail) {
+        Some(post_now_request) => execute_post_now(&post_now_request).await,
+        None => execute().await.map(|_| ()),
+    };
+    match result {
         Ok(_) => Ok(()),
         Err(err) => {
-            println!("Error: {:?}", err);
-            Err(err.into())
+            error!(error = ?err, "Run failed");
+            Err(err)
+        }
+    }
+}
+
+async fn execute() -> Result<RunSummary, OpaqueError> {
+    let started_at = Utc::now();
+    let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&aws_config);
+    if is_paused(&DynamoDbPauseStore {
+        client: &dynamodb_client,
+    })
+    .await?
+    {
+        return Ok(RunSummary::default());
+    }
+    // 新しいフィードの投稿内容を確かめるため、投稿とフィードの状態の書き込み、レポートと通知を行わずに投稿する内容をログに出す
+    let dry_run = env_flag("DRY_RUN", false);
+    // 0の場合はロックを取らない。書き込まないドライランでもロックを取らない
+    let run_lock_ttl = env_parse::<i64>("RUN_LOCK_TTL_SECONDS", DEFAULT_RUN_LOCK_TTL_SECONDS)?;
+    // フィードと記事のページ、Blueskyへのリクエストで接続を使い回す
+    let reqwest_client = http_client::build_client()?;
+    if run_lock_ttl <= 0 || dry_run {
+        return execute_feeds(
+            &aws_config,
+            &dynamodb_client,
+            &reqwest_client,
+            started_at,
+            dry_run,
+        )
+        .await;
+    }
+    let run_lock_store = DynamoDbRunLockStore {
+        client: &dynamodb_client,
+    };
+    let owner = format!(
+        "{}-{}",
+        started_at.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        std::process::id()
+    );
+    let Some(run_lock) =
+        acquire_run_lock(&run_lock_store, &owner, started_at, run_lock_ttl).await?
+    else {
+        // 前回の実行が終わっていないため、重複して投稿しないよう何もせずに終了する
+        info!("Another run is in progress, exiting");
+        return Ok(RunSummary::default());
+    };
+    let result = execute_feeds(
+        &aws_config,
+        &dynamodb_client,
+        &reqwest_client,
+        started_at,
+        dry_run,
+    )
+    .await;
+    // 解放できなかったロックも期限が切れれば次の実行が取得できるため、実行の結果を優先して返す
+    if let Err(err) = release_run_lock(&run_lock_store, &run_lock).await {
+        error!(error = ?err, "Failed to release run lock");
+    }
+    result
+}
+
+async fn execute_feeds(
+    aws_config: &aws_config::SdkConfig,
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    reqwest_client: &reqwest::Client,
+    started_at: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<RunSummary, OpaqueError> {
+    // 投稿はセッションを共有するクライアントで1件ずつ行う
+    let session_store = DynamoDbSessionStore {
+        client: dynamodb_client,
+    };
+    let bsky_client = Arc::new(tokio::sync::Mutex::new(
+        bsky::BskyClient::new(&session_store, reqwest_client.clone()).await?,
+    ));
+    let mut feed_records = list_registered_feeds(dynamodb_client).await?;
+    sort_feed_records_by_priority(&mut feed_records);
+    let feed_concurrency = env_parse::<usize>("FEED_CONCURRENCY", DEFAULT_FEED_CONCURRENCY)?;
+    let run_time_budget = env_parse::<i64>("RUN_TIME_BUDGET_SECONDS", 0)?;
+    let error_cooldown_base_minutes = env_parse::<i64>(
+        "ERROR_COOLDOWN_BASE_MINUTES",
+        DEFAULT_ERROR_COOLDOWN_BASE_MINUTES,
+    )?;
+    let error_cooldown_max_minutes = env_parse::<i64>(
+        "ERROR_COOLDOWN_MAX_MINUTES",
+        DEFAULT_ERROR_COOLDOWN_MAX_MINUTES,
+    )?;
+    // 実行の頻度に関わらず1時間あたりの投稿数を抑える
+    let post_rate_limiter = match env_parse::<u32>("MAX_POSTS_PER_HOUR", 0)? {
+        0 => None,
+        max_posts_per_hour => Some(Arc::new(Mutex::new(PostRateLimiter::new(
+            max_posts_per_hour,
+            get_post_rate_window(dynamodb_client).await?,
+            started_at,
+        )))),
+    };
+    // 通知の設定を誤っていても投稿は続けられるよう、読めない場合は通知しない
+    let webhook_destination = webhook_destination_from_env().unwrap_or_else(|err| {
+        error!(error = ?err, "Invalid run summary webhook settings, disabled the webhook");
+        None
+    });
+    let feed_run = FeedRun {
+        bsky_client,
+        dynamodb_client: dynamodb_client.clone(),
+        reqwest_client: reqwest_client.clone(),
+        post_rate_limiter,
+        // 同じ記事が複数のフィードに載っている場合に、1回の実行で二重に投稿しない
+        posted_urls_in_run: Arc::new(Mutex::new(HashSet::new())),
+        started_at,
+        run_time_budget,
+        error_cooldown_base_minutes,
+        error_cooldown_max_minutes,
+        dry_run,
+    };
+    let feed_reports = process_feeds(&feed_run, feed_records, feed_concurrency).await?;
+    // 実行中にセッションを更新した場合は次の実行で使えるよう保存する
+    // 更新すると以前のリフレッシュトークンは使えなくなるため、ドライランでも保存する
+    feed_run
+        .bsky_client
+        .lock()
+        .await
+        .save_session_if_updated(&session_store)
+        .await;
+    if let Some(post_rate_limiter) = feed_run.post_rate_limiter.as_ref().filter(|_| !dry_run) {
+        let window = post_rate_limiter.lock().unwrap().window().clone();
+        put_post_rate_window(dynamodb_client, &window).await?;
+    }
+    let run_report = RunReport {
+        started_at,
+        finished_at: Utc::now(),
+        feeds: feed_reports,
+    };
+    if let Some(report_destination) = report_destination_from_env(aws_config).filter(|_| !dry_run) {
+        // レポートの書き出しに失敗しても実行結果には影響させない
+        match write_run_report(
+            &report_destination.store,
+            &report_destination.key_prefix,
+            &run_report,
+        )
+        .await
+        {
+            Ok(key) => info!(key, "Wrote run report"),
+            Err(err) => error!(error = ?err, "Failed to write run report"),
+        }
+    }
+    if let Some(webhook_destination) = webhook_destination.as_ref().filter(|_| !dry_run) {
+        // 通知に失敗しても実行結果には影響させない
+        match post_run_summary(webhook_destination, &run_report).await {
+            Ok(true) => info!("Posted run summary to webhook"),
+            Ok(false) => {}
+            Err(err) => error!(error = ?err, "Failed to post run summary to webhook"),
+        }
+    }
+    let run_summary = summarize_run(&run_report.feeds);
+    info!(
+        feeds = run_report.feeds.len(),
+        succeeded = run_summary.succeeded,
+        skipped = run_summary.skipped,
+        failed = run_summary.failed,
+        "Processed feeds"
+    );
+    Ok(run_summary)
+}
+
+// フィードの処理結果をログに出し、連続した失敗の状態を更新する
+// 並行して処理するフィードの間で共有するもの
+#[derive(Clone)]
+struct FeedRun {
+    bsky_client: Arc<tokio::sync::Mutex<BskyClient>>,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+    reqwest_client: reqwest::Client,
+    post_rate_limiter: Option<Arc<Mutex<PostRateLimiter>>>,
+    posted_urls_in_run: Arc<Mutex<HashSet<String>>>,
+    started_at: DateTime<Utc>,
+    run_time_budget: i64,
+    error_cooldown_base_minutes: i64,
+    error_cooldown_max_minutes: i64,
+    dry_run: bool,
+}
+
+// 優先度の高いフィードから順に始め、結果はフィードの順に集める
+// 一部のフィードが失敗しても、他のフィードの処理と実行全体は続ける
+async fn process_feeds(
+    feed_run: &FeedRun,
+    feed_records: Vec<FeedRecord>,
+    feed_concurrency: usize,
+) -> Result<Vec<FeedReport>, OpaqueError> {
+    let feed_urls = feed_records
+        .iter()
+        .map(|feed_record| feed_record.url.clone())
+        .collect::<Vec<_>>();
+    let feed_results = map_bounded_concurrent(feed_records, feed_concurrency, |feed_record| {
+        let feed_run = feed_run.clone();
+        // 1つのフィードのログを相関させるため、フィードのURLを持つspanの中で処理する
+        let feed_span = info_span!("feed", url = %feed_record.url);
+        async move {
+            if is_run_time_budget_exhausted(
+                feed_run.started_at,
+                Utc::now(),
+                feed_run.run_time_budget,
+            ) {
+                // 優先度の低いフィードは次回の実行に回す
+                info!("Run time budget exhausted, skipped feed");
+                return FeedReport {
+                    skip_reason: Some("run time budget exhausted".to_string()),
+                    ..FeedReport::new(&feed_record.url)
+                };
+            }
+            let feed_process_result = process_feed(
+                &feed_record,
+                &feed_run.bsky_client,
+                &feed_run.dynamodb_client,
+                &feed_run.reqwest_client,
+                feed_run.post_rate_limiter.as_deref(),
+                &feed_run.posted_urls_in_run,
+                feed_run.dry_run,
+            )
+            .await;
+            if !feed_run.dry_run {
+                update_feed_after_process(
+                    &feed_record,
+                    &feed_process_result,
+                    &feed_run.dynamodb_client,
+                    feed_run.error_cooldown_base_minutes,
+                    feed_run.error_cooldown_max_minutes,
+                )
+                .await;
+            }
+            match feed_process_result {
+                Ok(feed_report) => feed_report,
+                Err(err) => FeedReport {
+                    error: Some(err.to_string()),
+                    ..FeedReport::new(&feed_record.url)
+                },
+            }
         }
+        .instrument(feed_span)
+    })
+    .await?;
+    Ok(feed_urls
+        .into_iter()
+        .zip(feed_results)
+        .map(|(feed_url, feed_result)| {
+            feed_result.unwrap_or_else(|err| {
+                // panicしたフィードも失敗として報告し、実行の後始末は続ける
+                error!(url = %feed_url, error = ?err, "Feed task failed");
+                FeedReport {
+                    error: Some(err.to_string()),
+                    ..FeedReport::new(&feed_url)
+                }
+            })
+        })
+        .collect())
+}
+
+async fn update_feed_after_process(
+    feed_record: &FeedRecord,
+    feed_process_result: &Result<FeedReport, OpaqueError>,
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    error_cooldown_base_minutes: i64,
+    error_cooldown_max_minutes: i64,
+) {
+    if let Err(err) = feed_process_result {
+        match find_bot_error(err) {
+            Some(BotError::RedirectLoop { url }) => {
+                error!(redirect_url = %url, "Redirect loop detected while processing feed")
+            }
+            Some(BotError::Blocked { .. }) => {
+                error!("Feed is blocked by a bot challenge, consider FEED_FETCH_PROXY_URL")
+            }
+            Some(BotError::FeedUnauthorized { status, .. }) => error!(
+                status,
+                "Feed requires authorization, check whether it became private"
+            ),
+            // その他の失敗は分類と原因を出す
+            Some(
+                BotError::FeedRateLimited { .. }
+                | BotError::FeedNotModified { .. }
+                | BotError::FeedHttpStatus { .. }
+                | BotError::UnexpectedAccount { .. }
+                | BotError::BskyApi { .. }
+                | BotError::FeedFetch(_)
+                | BotError::FeedParse(_)
+                | BotError::Bsky(_)
+                | BotError::Dynamo(_)
+                | BotError::Ogp(_)
+                | BotError::ImageDecode(_),
+            )
+            | None => error!(
+                category = error_category(err),
+                error = ?err,
+                "Failed to process feed"
+            ),
+        }
+    }
+    let failure_state = get_failure_state(
+        feed_record,
+        feed_process_result,
+        error_cooldown_base_minutes,
+        error_cooldown_max_minutes,
+        Utc::now(),
+    );
+    if let Some((consecutive_failures, next_attempt_at)) = failure_state {
+        match update_feed_failure_state(
+            dynamodb_client,
+            &feed_record.url,
+            consecutive_failures,
+            next_attempt_at.as_ref(),
+        )
+        .await
+        {
+            Ok(_) => info!(
+                consecutive_failures,
+                next_attempt_at = ?next_attempt_at,
+                "Updated failure state"
+            ),
+            Err(err) => error!(error = ?err, "Failed to update failure state"),
+        }
+    }
+}
+
+fn can_post(post_rate_limiter: Option<&Mutex<PostRateLimiter>>) -> bool {
+    match post_rate_limiter {
+        Some(post_rate_limiter) => post_rate_limiter.lock().unwrap().can_post(),
+        None => true,
+    }
+}
+
+fn is_posted_in_run(posted_urls_in_run: &Mutex<HashSet<String>>, url: &str) -> bool {
+    posted_urls_in_run
+        .lock()
+        .unwrap()
+        .contains(&entry_url_dedup_key(url))
+}
+
+fn record_posted_in_run(posted_urls_in_run: &Mutex<HashSet<String>>, url: &str) {
+    posted_urls_in_run
+        .lock()
+        .unwrap()
+        .insert(entry_url_dedup_key(url));
+}
+
+fn record_post(post_rate_limiter: Option<&Mutex<PostRateLimiter>>) {
+    if let Some(post_rate_limiter) = post_rate_limiter {
+        post_rate_limiter.lock().unwrap().record_post();
     }
 }
 
-async fn execute() -> Result<Vec<()>, OpaqueError> {
+fn sort_feed_records_by_priority(feed_records: &mut [FeedRecord]) {
+    feed_records.sort_by_key(|feed_record| std::cmp::Reverse(feed_record.priority));
+}
+
+// 0以下の場合は制限しない
+fn is_run_time_budget_exhausted(
+    started_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    run_time_budget_seconds: i64,
+) -> bool {
+    run_time_budget_seconds > 0
+        && now - started_at >= chrono::Duration::seconds(run_time_budget_seconds)
+}
+
+// 手動で特定のエントリーを投稿するためのリクエスト。フィルターは無視するが重複投稿はしない
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PostNowRequest {
+    pub feed_url: String,
+    // エントリーのidまたはURL
+    pub entry: String,
+}
+
+fn parse_post_now_request(detail: &serde_json::Value) -> Option<PostNowRequest> {
+    let post_now = detail.get("post_now")?;
+    serde_json::from_value(post_now.clone()).ok()
+}
+
+pub async fn execute_post_now(post_now_request: &PostNowRequest) -> Result<(), OpaqueError> {
     let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
     let dynamodb_client = aws_sdk_dynamodb::Client::new(&aws_config);
-    let mut bsky_client = bsky::BskyClient::new().await?;
-    let feed_records = list_registered_feeds(&dynamodb_client).await?;
-    let mut feed_process_results = Vec::new();
-    // todo: process feeds concurrently
-    for feed_record in feed_records {
-        let feed_process_result =
-            process_feed(&feed_record, &mut bsky_client, &dynamodb_client).await;
-        feed_process_results.push(feed_process_result);
-    }
-    let result = feed_process_results
+    let session_store = DynamoDbSessionStore {
+        client: &dynamodb_client,
+    };
+    let reqwest_client = http_client::build_client()?;
+    let mut bsky_client = bsky::BskyClient::new(&session_store, reqwest_client.clone()).await?;
+    let feed_record = list_registered_feeds(&dynamodb_client)
+        .await?
         .into_iter()
-        .collect::<Result<Vec<()>, OpaqueError>>()?;
-    Ok(result)
+        .find(|feed_record| feed_record.url == post_now_request.feed_url)
+        .ok_or(format!(
+            "feed is not registered: {}",
+            post_now_request.feed_url
+        ))?;
+    // 指定されたエントリーを探すため、更新されていなくても本文を取得する
+    let feed_fetch_options = FeedFetchOptions {
+        validators: FeedValidators::default(),
+        ..feed_fetch_options_for_feed(&feed_record)
+    };
+    let fetched_feed = get_feed(&reqwest_client, &feed_record.url, &feed_fetch_options).await?;
+    let feed = fetched_feed.feed;
+    let entries = extract_feed_entries_with_canonical_urls(&feed);
+    let posted_urls = bsky_client.list_posted_embed_urls(SEED_POST_LIMIT).await?;
+    let feed_entry = select_post_now_entry(&entries, &post_now_request.entry, &posted_urls)?;
+    info!(entry_id = %feed_entry.id, "Posting entry now");
+    // ドメインの除外設定も無視してOGPを取得する
+    let entry_info_options = EntryInfoOptions {
+        ogp_skip_domains: Vec::new(),
+        ..entry_info_options_for_feed(&feed_record)
+    };
+    let (ogp_info, og_image) =
+        extract_feed_entry_info(&reqwest_client, &feed_entry, &entry_info_options).await?;
+    post_feed_entry(
+        &feed_record,
+        &feed,
+        &feed_entry,
+        ogp_info,
+        og_image,
+        &mut bsky_client,
+    )
+    .await?;
+    bsky_client.save_session_if_updated(&session_store).await;
+    if let Some(last_posted_entry_id) = get_post_now_marker(
+        &entries,
+        feed_record.last_posted_entry_id.as_ref(),
+        &feed_entry.id,
+        env_flag("NORMALIZE_DEDUP_URLS", false),
+    ) {
+        update_feed_last_posted_entry_id(&dynamodb_client, &feed_record.url, &last_posted_entry_id)
+            .await?;
+        info!(%last_posted_entry_id, "Updated last_posted_entry_id");
+    }
+    Ok(())
+}
+
+// 通常の実行で再投稿しないよう、次に投稿される予定だった最も古い未投稿のエントリーの場合だけ位置を進める
+// それより新しいエントリーで位置を進めると、間の未投稿のエントリーが投稿されなくなる
+fn get_post_now_marker(
+    entries: &[FeedEntry],
+    marker_entry_id: Option<&String>,
+    posted_entry_id: &str,
+    normalize_dedup_urls: bool,
+) -> Option<String> {
+    marker_entry_id?;
+    let (unposted_entries, _) =
+        select_target_entries(entries, marker_entry_id, normalize_dedup_urls);
+    unposted_entries
+        .last()
+        .filter(|oldest_entry| oldest_entry.id == posted_entry_id)
+        .map(|oldest_entry| oldest_entry.id.clone())
+}
+
+// 投稿済みのURLと一致する場合はエラーにして重複投稿を防ぐ
+fn select_post_now_entry(
+    entries: &[FeedEntry],
+    entry_key: &str,
+    posted_urls: &[String],
+) -> Result<FeedEntry, OpaqueError> {
+    let feed_entry = entries
+        .iter()
+        .find(|feed_entry| feed_entry.id == entry_key || feed_entry.url == entry_key)
+        .ok_or(format!("entry not found in feed: {}", entry_key))?;
+    if posted_urls.contains(&feed_entry.url) {
+        return Err(format!("entry is already posted: {}", feed_entry.url).into());
+    }
+    Ok(feed_entry.clone())
 }
 
 async fn process_feed(
     feed_record: &FeedRecord,
-    bsky_client: &mut BskyClient,
+    bsky_client: &tokio::sync::Mutex<BskyClient>,
     dynamodb_client: &aws_sdk_dynamodb::Client,
-) -> Result<(), OpaqueError> {
-    println!("Processing feed: {}", feed_record.url);
-    let feed = get_feed(&feed_record.url).await?;
-    let entries = extract_feed_entries(&feed);
+    reqwest_client: &reqwest::Client,
+    post_rate_limiter: Option<&Mutex<PostRateLimiter>>,
+    posted_urls_in_run: &Mutex<HashSet<String>>,
+    dry_run: bool,
+) -> Result<FeedReport, OpaqueError> {
+    info!("Processing feed");
+    let mut feed_report = FeedReport::new(&feed_record.url);
+    if !is_feed_attempt_allowed(feed_record, Utc::now()) {
+        info!(
+            consecutive_failures = feed_record.consecutive_failures,
+            next_attempt_at = ?feed_record.next_attempt_at,
+            "Feed failed repeatedly, skipped until the cooldown ends"
+        );
+        feed_report.skip_reason = Some(ERROR_COOLDOWN_SKIP_REASON.to_string());
+        return Ok(feed_report);
+    }
+    if !is_feed_fetch_allowed(feed_record, Utc::now()) {
+        info!(
+            next_fetch_allowed_at = ?feed_record.next_fetch_allowed_at,
+            "Feed server asked to retry later, skipped"
+        );
+        feed_report.skip_reason = Some("rate limited by feed server".to_string());
+        return Ok(feed_report);
+    }
+    let feed_fetch_options = feed_fetch_options_for_feed(feed_record);
+    let fetched_feed = match get_feed(reqwest_client, &feed_record.url, &feed_fetch_options).await {
+        Ok(fetched_feed) => fetched_feed,
+        Err(err) => match err.downcast_ref::<BotError>() {
+            // 失敗として扱わず、次回以降の実行で取得し直す
+            Some(BotError::FeedRateLimited { retry_after, .. }) => {
+                if let Some(retry_after) = retry_after.as_ref().filter(|_| !dry_run) {
+                    update_feed_next_fetch_allowed_at(
+                        dynamodb_client,
+                        &feed_record.url,
+                        retry_after,
+                    )
+                    .await
+                    .map_err(BotError::Dynamo)?;
+                }
+                warn!(retry_after = ?retry_after, "Feed server is rate limiting");
+                feed_report.skip_reason = Some("rate limited by feed server".to_string());
+                return Ok(feed_report);
+            }
+            Some(BotError::FeedNotModified { .. }) => {
+                info!("Feed has not been modified, skipped");
+                feed_report.skip_reason = Some(NOT_MODIFIED_SKIP_REASON.to_string());
+                return Ok(feed_report);
+            }
+            _ => return Err(BotError::feed_fetch(err).into()),
+        },
+    };
+    feed_report.http_version = Some(format!("{:?}", fetched_feed.http_version));
+    if let ParseStatus::Partial { recovered_entries } = fetched_feed.parse_status {
+        warn!(recovered_entries, "Feed was only partially parsed");
+    }
+    let feed_links = &fetched_feed.feed_links;
+    if !dry_run
+        && (feed_links.hub_url.is_some() || feed_links.self_url.is_some())
+        && (feed_links.hub_url != feed_record.hub_url
+            || feed_links.self_url != feed_record.self_url)
+    {
+        // 将来のWebSubによる購読のために記録しておく
+        update_feed_links(
+            dynamodb_client,
+            &feed_record.url,
+            feed_links.hub_url.as_deref(),
+            feed_links.self_url.as_deref(),
+        )
+        .await
+        .map_err(BotError::Dynamo)?;
+        info!(feed_links = ?feed_links, "Updated feed links");
+    }
+    let feed = fetched_feed.feed;
+    let entries = extract_feed_entries_with_canonical_urls(&feed);
+    // 重複の判定はidとURLで行うため、"Daily Update"のようにタイトルを使い回すフィードもそれぞれ投稿する
+    for shared_title in find_shared_titles(&entries, SHARED_TITLE_WARNING_THRESHOLD) {
+        if shared_title.distinct_id_count < shared_title.entry_count {
+            warn!(
+                entry_count = shared_title.entry_count,
+                title = %shared_title.title,
+                distinct_id_count = shared_title.distinct_id_count,
+                "Entries share a title with fewer distinct ids, check the feed's id/guid"
+            );
+        } else {
+            warn!(
+                entry_count = shared_title.entry_count,
+                title = %shared_title.title,
+                "Entries share a title, deduplicating by id"
+            );
+        }
+    }
+    if !is_feed_active(feed_record, Utc::now()) {
+        // 登録直後の観察期間中は投稿せず、有効化後に最新の位置から投稿できるよう位置だけ記録する
+        if let Some(newest_entry) = entries.first().filter(|_| !dry_run) {
+            if feed_record.last_posted_entry_id.as_ref() != Some(&newest_entry.id) {
+                update_feed_last_posted_entry_id(
+                    dynamodb_client,
+                    &feed_record.url,
+                    &newest_entry.id,
+                )
+                .await
+                .map_err(BotError::Dynamo)?;
+            }
+        }
+        info!(
+            activate_after = ?feed_record.activate_after,
+            "Feed is not active yet, skipped posting"
+        );
+        feed_report.skip_reason = Some("not active yet".to_string());
+        return Ok(feed_report);
+    }
+    let mut seeded_entry_id = None;
+    if feed_record.last_posted_entry_id.is_none() && !dry_run {
+        // 既存のアカウントから移行した場合に過去の記事を再投稿しないよう、アカウントの投稿から位置を復元する
+        seeded_entry_id = match seed_from_account(
+            feed_record,
+            &entries,
+            &mut *bsky_client.lock().await,
+            dynamodb_client,
+        )
+        .await
+        {
+            Ok(seeded_entry_id) => seeded_entry_id,
+            Err(err) => {
+                warn!(error = ?err, "Failed to seed last_posted_entry_id");
+                None
+            }
+        };
+    }
+    let marker_entry_id = feed_record
+        .last_posted_entry_id
+        .as_ref()
+        .or(seeded_entry_id.as_ref());
+    let normalize_dedup_urls = env_flag("NORMALIZE_DEDUP_URLS", false);
+    let (mut target_entries, dedup_match) =
+        select_target_entries(&entries, marker_entry_id, normalize_dedup_urls);
+    match (&dedup_match, marker_entry_id) {
+        (Some(dedup_match), _) => debug!(
+            field = ?dedup_match.field,
+            value = %dedup_match.value,
+            "Matched last_posted_entry_id"
+        ),
+        (None, Some(marker_entry_id)) => warn!(
+            last_posted_entry_id = %marker_entry_id,
+            "No entry matched last_posted_entry_id by id or url"
+        ),
+        (None, None) => {}
+    }
+    // 前回までに投稿したエントリー。修正されたものを投稿し直すために使う
+    let posted_feed_entries: Vec<FeedEntry> = match &dedup_match {
+        Some(_) if feed_record.repost_updated_entries => entries
+            .iter()
+            .filter(|feed_entry| {
+                !target_entries
+                    .iter()
+                    .any(|target_entry| target_entry.id == feed_entry.id)
+            })
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    };
+    target_entries.reverse();
+    let max_entries_per_run = env_parse("MAX_ENTRIES_PER_RUN", DEFAULT_MAX_ENTRIES_PER_RUN)?;
+    let dropped_count = cap_target_entries(&mut target_entries, max_entries_per_run);
+    if dropped_count > 0 {
+        warn!(
+            dropped_count,
+            max_entries_per_run, "Too many new entries, posting only the newest ones"
+        );
+    }
+    let now = Utc::now();
+    // 次回の実行に残したエントリーがある場合は、304で取得を省略されないようETagなどを保存しない
+    let mut has_pending_entries = false;
+    if feed_record.defer_future_entries {
+        let deferred_entries = defer_future_entries(&mut target_entries, now);
+        if !deferred_entries.is_empty() {
+            has_pending_entries = true;
+            info!(
+                deferred_count = deferred_entries.len(),
+                "Deferred future-dated entries until a later run"
+            );
+            // 他のエントリーは投稿されうるため、フィード全体ではなくエントリーごとに記録する
+            feed_report
+                .skipped
+                .extend(deferred_entries.into_iter().map(|feed_entry| SkippedEntry {
+                    entry_id: feed_entry.id,
+                    reason: FUTURE_ENTRY_SKIP_REASON.to_string(),
+                }));
+        }
+    }
+    let published_cutoff = get_published_cutoff(
+        now,
+        env_parse("MAX_ENTRY_AGE_DAYS", DEFAULT_MAX_ENTRY_AGE_DAYS)?,
+    );
+    let post_allowance = get_post_allowance(feed_record, now);
+    if post_allowance == Some(0) && !target_entries.is_empty() {
+        info!(
+            next_post_allowed_at = ?feed_record.next_post_allowed_at,
+            "Post interval has not elapsed"
+        );
+        feed_report.skip_reason = Some("post interval has not elapsed".to_string());
+        return Ok(feed_report);
+    }
+    let entry_info_options = entry_info_options_for_feed(feed_record);
+    // 投稿する予定のエントリーのOGPは先に並行して取得しておき、投稿は古い順に行う
+    let post_limit = [
+        post_allowance,
+        post_rate_limiter
+            .map(|post_rate_limiter| post_rate_limiter.lock().unwrap().remaining_posts() as usize),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+    let prefetch_entries = target_entries
+        .iter()
+        .filter(|feed_entry| {
+            find_entry_skip_reason_before_cutoff(feed_record, feed_entry, published_cutoff)
+                .is_none()
+        })
+        .take(post_limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    let mut prefetched_entry_infos = prefetch_entry_infos(
+        reqwest_client,
+        prefetch_entries,
+        &entry_info_options,
+        env_parse("OGP_FETCH_CONCURRENCY", DEFAULT_OGP_FETCH_CONCURRENCY)?,
+    )
+    .await?;
+    let posted_entry_store = DynamoDbPostedEntryStore {
+        client: dynamodb_client,
+    };
+    let mut post_progress = PostProgress::new(dynamodb_client, &feed_record.url);
+    let mut last_posted_published = feed_record.last_posted_published;
+    for feed_entry in target_entries {
+        // 投稿間隔が設定されている場合、残りのエントリーは次回以降の実行で投稿する
+        if post_allowance.is_some_and(|post_allowance| feed_report.posts.len() >= post_allowance) {
+            has_pending_entries = true;
+            break;
+        }
+        // 投稿数を確かめてから記録するまでの間に他のフィードが投稿しないよう、投稿を終えるまでクライアントを占有する
+        let mut locked_bsky_client = bsky_client.lock().await;
+        if !can_post(post_rate_limiter) {
+            info!(entry_id = %feed_entry.id, "Hourly post limit reached, deferred");
+            feed_report.skip_reason = Some("hourly post limit reached".to_string());
+            has_pending_entries = true;
+            break;
+        }
+        info!(entry_id = %feed_entry.id, "Processing entry");
+        if let Some(skip_reason) =
+            find_entry_skip_reason_before_cutoff(feed_record, &feed_entry, published_cutoff)
+        {
+            info!(entry_id = %feed_entry.id, skip_reason, "Skipping entry");
+            feed_report.skipped.push(SkippedEntry {
+                entry_id: feed_entry.id.clone(),
+                reason: skip_reason.to_string(),
+            });
+            post_progress.skip(&feed_entry.id);
+            continue;
+        }
+        if is_posted_in_run(posted_urls_in_run, &feed_entry.url) {
+            info!(entry_id = %feed_entry.id, url = %feed_entry.url, "Already posted by another feed in this run, skipped");
+            feed_report.skipped.push(SkippedEntry {
+                entry_id: feed_entry.id.clone(),
+                reason: "already posted by another feed".to_string(),
+            });
+            post_progress.skip(&feed_entry.id);
+            continue;
+        }
+        // 先読みしていないエントリーだけをここで取得する
+        let (ogp_info, og_image) = match prefetched_entry_infos.remove(&feed_entry.id) {
+            Some(entry_info) => entry_info.map_err(BotError::ogp)?,
+            None => extract_feed_entry_info(reqwest_client, &feed_entry, &entry_info_options)
+                .await
+                .map_err(BotError::ogp)?,
+        };
+        if dry_run {
+            // サムネイルはアップロードしないため、画像があるかどうかだけを出す
+            let create_record_request = locked_bsky_client
+                .format_create_record_request_from_feed_entry(
+                    feed_record,
+                    &feed,
+                    feed_entry.clone(),
+                    ogp_info,
+                    None,
+                )
+                .await;
+            info!(
+                entry_id = %feed_entry.id,
+                has_thumbnail = og_image.is_some(),
+                request = %serde_json::to_string(&create_record_request)?,
+                "Dry run, skipped posting"
+            );
+            record_posted_in_run(posted_urls_in_run, &feed_entry.url);
+            feed_report.skipped.push(SkippedEntry {
+                entry_id: feed_entry.id.clone(),
+                reason: DRY_RUN_SKIP_REASON.to_string(),
+            });
+            continue;
+        }
+        let create_record_response = post_feed_entry(
+            feed_record,
+            &feed,
+            &feed_entry,
+            ogp_info,
+            og_image,
+            &mut locked_bsky_client,
+        )
+        .await
+        .map_err(BotError::bsky)?;
+        record_post(post_rate_limiter);
+        record_posted_in_run(posted_urls_in_run, &feed_entry.url);
+        drop(locked_bsky_client);
+        // 後続のエントリーで失敗しても投稿し直さないよう、投稿するたびに位置を保存する
+        post_progress
+            .record_post(&feed_entry.id)
+            .await
+            .map_err(BotError::Dynamo)?;
+        info!(last_posted_entry_id = %feed_entry.id, "Updated last_posted_entry_id");
+        if feed_record.repost_updated_entries {
+            record_posted_entry(
+                &posted_entry_store,
+                &feed_record.url,
+                &feed_entry,
+                &create_record_response.uri,
+            )
+            .await
+            .map_err(BotError::Dynamo)?;
+        }
+        feed_report.posts.push(PostedEntry {
+            entry_id: feed_entry.id.clone(),
+            uri: create_record_response.uri,
+        });
+        last_posted_published = last_posted_published.max(feed_entry.published);
+    }
+    if dry_run {
+        info!("Finished dry run of feed");
+        return Ok(feed_report);
+    }
+    // Blueskyでは投稿を編集できないため、以前の投稿を削除して投稿し直す
+    for (feed_entry, posted_entry) in
+        find_updated_entries(&posted_entry_store, &feed_record.url, &posted_feed_entries)
+            .await
+            .map_err(BotError::Dynamo)?
+    {
+        let mut locked_bsky_client = bsky_client.lock().await;
+        if post_allowance.is_some_and(|post_allowance| feed_report.posts.len() >= post_allowance)
+            || !can_post(post_rate_limiter)
+        {
+            info!(entry_id = %feed_entry.id, "Post limit reached, deferred repost");
+            has_pending_entries = true;
+            break;
+        }
+        info!(
+            entry_id = %feed_entry.id,
+            previous_post = %posted_entry.post_uri,
+            "Reposting updated entry"
+        );
+        let (ogp_info, og_image) =
+            extract_feed_entry_info(reqwest_client, &feed_entry, &entry_info_options)
+                .await
+                .map_err(BotError::ogp)?;
+        let create_record_response = post_feed_entry(
+            feed_record,
+            &feed,
+            &feed_entry,
+            ogp_info,
+            og_image,
+            &mut locked_bsky_client,
+        )
+        .await
+        .map_err(BotError::bsky)?;
+        // 新しい投稿ができてから削除し、投稿が失われないようにする
+        if let Err(err) = locked_bsky_client
+            .delete_record(&posted_entry.post_uri)
+            .await
+        {
+            error!(
+                previous_post = %posted_entry.post_uri,
+                error = ?err,
+                "Failed to delete previous post"
+            );
+        }
+        record_post(post_rate_limiter);
+        drop(locked_bsky_client);
+        record_posted_entry(
+            &posted_entry_store,
+            &feed_record.url,
+            &feed_entry,
+            &create_record_response.uri,
+        )
+        .await
+        .map_err(BotError::Dynamo)?;
+        feed_report.posts.push(PostedEntry {
+            entry_id: feed_entry.id.clone(),
+            uri: create_record_response.uri,
+        });
+    }
+    if let Some(last_posted_entry_id) = post_progress.finish().await.map_err(BotError::Dynamo)? {
+        info!(%last_posted_entry_id, "Updated last_posted_entry_id");
+    }
+    if let Some(last_posted_published) = last_posted_published
+        .filter(|published| Some(*published) > feed_record.last_posted_published)
+    {
+        update_feed_last_posted_published(
+            dynamodb_client,
+            &feed_record.url,
+            &last_posted_published,
+        )
+        .await
+        .map_err(BotError::Dynamo)?;
+        info!(%last_posted_published, "Updated last_posted_published");
+    }
+    if !feed_report.posts.is_empty() {
+        if let Some(next_post_allowed_at) = get_next_post_allowed_at(feed_record, now) {
+            update_feed_next_post_allowed_at(
+                dynamodb_client,
+                &feed_record.url,
+                &next_post_allowed_at,
+            )
+            .await
+            .map_err(BotError::Dynamo)?;
+            info!(%next_post_allowed_at, "Updated next_post_allowed_at");
+        }
+    }
+    let validators = &fetched_feed.validators;
+    if !has_pending_entries
+        && (validators.etag != feed_record.etag
+            || validators.last_modified != feed_record.last_modified)
+    {
+        update_feed_validators(
+            dynamodb_client,
+            &feed_record.url,
+            validators.etag.as_deref(),
+            validators.last_modified.as_deref(),
+        )
+        .await
+        .map_err(BotError::Dynamo)?;
+    }
+    info!("Finished processing feed");
+    Ok(feed_report)
+}
+
+#[derive(Debug, PartialEq)]
+enum DedupField {
+    Id,
+    // feed-rsはguidのないエントリーにidを生成するため、guidを付け直したフィードではidが変わってしまう
+    // URLは変わらないことが多いため、保存した位置がURLと一致する場合も前回投稿したエントリーとみなす
+    Url,
+}
+
+#[derive(Debug, PartialEq)]
+struct DedupMatch {
+    field: DedupField,
+    value: String,
+}
+
+// 設定されている場合、OGPの取得と埋め込みの前にAMPやモバイル版のURLを通常版に書き換える
+fn extract_feed_entries_with_canonical_urls(feed: &Feed) -> Vec<FeedEntry> {
+    let mut entries = extract_feed_entries(feed);
+    if env_flag("CANONICALIZE_ENTRY_URLS", false) {
+        for feed_entry in &mut entries {
+            feed_entry.url = canonicalize_url(&feed_entry.url);
+        }
+    }
+    entries
+}
+
+// 新しい順に並んだエントリーから、前回投稿したエントリーより新しいものを抽出する
+fn select_target_entries(
+    entries: &[FeedEntry],
+    marker_entry_id: Option<&String>,
+    normalize_urls: bool,
+) -> (Vec<FeedEntry>, Option<DedupMatch>) {
+    // URLの表記揺れで位置を見失って再投稿しないよう、設定されている場合は正規化して比較する
+    let is_marker = |value: &str, marker_entry_id: &str| {
+        if normalize_urls {
+            normalize_dedup_url(value) == normalize_dedup_url(marker_entry_id)
+        } else {
+            value == marker_entry_id
+        }
+    };
     let mut target_entries = Vec::new();
     for (index, feed_entry) in entries.iter().enumerate() {
-        if let Some(last_posted_entry_id) = &feed_record.last_posted_entry_id {
-            if feed_entry.id == *last_posted_entry_id {
-                break;
+        if let Some(marker_entry_id) = marker_entry_id {
+            if is_marker(&feed_entry.id, marker_entry_id) {
+                let dedup_match = DedupMatch {
+                    field: DedupField::Id,
+                    value: feed_entry.id.clone(),
+                };
+                return (target_entries, Some(dedup_match));
+            }
+            // idで見つからない場合の代わりの比較。NORMALIZE_DEDUP_URLSもこの比較に使う
+            if is_marker(&feed_entry.url, marker_entry_id) {
+                let dedup_match = DedupMatch {
+                    field: DedupField::Url,
+                    value: feed_entry.url.clone(),
+                };
+                return (target_entries, Some(dedup_match));
             }
         }
         target_entries.push(feed_entry.clone());
         // last_posted_entry_idが登録されていない場合は最新の1件を投稿する
-        if index == 0 && feed_record.last_posted_entry_id.is_none() {
+        if index == 0 && marker_entry_id.is_none() {
             break;
         }
         // 全件投稿してしまうのを防ぐために10件までに制限する
@@ -74,44 +1121,1160 @@ async fn process_feed(
             break;
         }
     }
-    target_entries.reverse();
-    let mut last_posted_entry_id: Option<String> = feed_record.last_posted_entry_id.clone();
-    for feed_entry in target_entries {
-        println!("Processing entry: {}", feed_entry.id);
-        let (ogp_info, og_image) = extract_feed_entry_info(&feed_entry).await?;
-        let upload_blog_response = match og_image {
-            Some(og_image) => Some(
+    (target_entries, None)
+}
+
+#[derive(Debug, PartialEq)]
+struct SharedTitle {
+    title: String,
+    entry_count: usize,
+    distinct_id_count: usize,
+}
+
+fn find_shared_titles(entries: &[FeedEntry], threshold: usize) -> Vec<SharedTitle> {
+    let mut titles: Vec<(&str, Vec<&str>)> = Vec::new();
+    for feed_entry in entries {
+        let Some(title) = feed_entry
+            .title
+            .as_deref()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+        else {
+            continue;
+        };
+        match titles.iter_mut().find(|(shared, _)| *shared == title) {
+            Some((_, ids)) => ids.push(&feed_entry.id),
+            None => titles.push((title, vec![&feed_entry.id])),
+        }
+    }
+    titles
+        .into_iter()
+        .filter(|(_, ids)| ids.len() >= threshold)
+        .map(|(title, ids)| {
+            let mut distinct_ids = ids.clone();
+            distinct_ids.sort();
+            distinct_ids.dedup();
+            SharedTitle {
+                title: title.to_string(),
+                entry_count: ids.len(),
+                distinct_id_count: distinct_ids.len(),
+            }
+        })
+        .collect()
+}
+
+type EntryInfo = (Option<OGPInfo>, Option<OGImage>);
+
+async fn prefetch_entry_infos(
+    reqwest_client: &reqwest::Client,
+    feed_entries: Vec<FeedEntry>,
+    entry_info_options: &EntryInfoOptions,
+    max_concurrency: usize,
+) -> Result<HashMap<String, Result<EntryInfo, OpaqueError>>, OpaqueError> {
+    let entry_ids = feed_entries
+        .iter()
+        .map(|feed_entry| feed_entry.id.clone())
+        .collect::<Vec<_>>();
+    let entry_infos = map_bounded_concurrent(feed_entries, max_concurrency, |feed_entry| {
+        let reqwest_client = reqwest_client.clone();
+        let entry_info_options = entry_info_options.clone();
+        async move {
+            let entry_info =
+                extract_feed_entry_info(&reqwest_client, &feed_entry, &entry_info_options).await;
+            entry_info
+        }
+    })
+    .await?;
+    Ok(entry_ids
+        .into_iter()
+        .zip(entry_infos)
+        .map(|(entry_id, entry_info)| (entry_id, entry_info.and_then(|entry_info| entry_info)))
+        .collect())
+}
+
+fn feed_fetch_options_for_feed(feed_record: &FeedRecord) -> FeedFetchOptions {
+    FeedFetchOptions {
+        use_cookies: feed_record.use_cookies,
+        validators: FeedValidators {
+            etag: feed_record.etag.clone(),
+            last_modified: feed_record.last_modified.clone(),
+        },
+    }
+}
+
+fn entry_info_options_for_feed(feed_record: &FeedRecord) -> EntryInfoOptions {
+    EntryInfoOptions {
+        require_same_origin_image: feed_record.og_image_same_origin,
+        image_host_allowlist: feed_record.og_image_host_allowlist.clone(),
+        ..EntryInfoOptions::from_env()
+    }
+}
+
+async fn post_feed_entry(
+    feed_record: &FeedRecord,
+    feed: &Feed,
+    feed_entry: &FeedEntry,
+    ogp_info: Option<OGPInfo>,
+    og_image: Option<OGImage>,
+    bsky_client: &mut BskyClient,
+) -> Result<CreateRecordResponse, OpaqueError> {
+    let image_bytes = og_image.as_ref().map(|og_image| og_image.image.clone());
+    let max_thumb_bytes = get_max_thumb_bytes(feed_record);
+    let upload_blob_result = match og_image {
+        Some(og_image) if og_image.resized && og_image.image.len() as u64 <= max_thumb_bytes => {
+            Some(
                 bsky_client
-                    .upload_thumbnail_with_resizing(og_image.image)
-                    .await?,
-            ),
-            None => None,
-        };
-        let create_record_request = bsky_client
-            .format_create_record_request_from_feed_entry(
-                &feed,
-                feed_entry.clone(),
-                ogp_info,
-                upload_blog_response,
+                    .upload_thumbnail(og_image.image, &og_image.content_type)
+                    .await,
             )
-            .await;
-        bsky_client.create_record(create_record_request).await?;
-        last_posted_entry_id = Some(feed_entry.id.clone());
+        }
+        Some(og_image) => Some(
+            bsky_client
+                .upload_thumbnail_with_resizing(
+                    og_image.image,
+                    &og_image.content_type,
+                    max_thumb_bytes,
+                )
+                .await,
+        ),
+        None => None,
+    };
+    // サムネイルを用意できなかった場合も、投稿自体は諦めずにサムネイルなしで投稿する
+    let upload_blob_response = match upload_blob_result {
+        Some(Ok(upload_blob_response))
+            if bsky_client.verify_uploaded_blob(&upload_blob_response) =>
+        {
+            Some(upload_blob_response)
+        }
+        Some(Ok(_)) => {
+            warn!(entry_id = %feed_entry.id, "Posting without thumbnail");
+            None
+        }
+        Some(Err(err)) => {
+            warn!(
+                entry_id = %feed_entry.id,
+                error = ?err,
+                "Failed to upload thumbnail, posting without it"
+            );
+            None
+        }
+        None => None,
+    };
+    let create_record_request = bsky_client
+        .format_create_record_request_from_feed_entry(
+            feed_record,
+            feed,
+            feed_entry.clone(),
+            ogp_info.clone(),
+            upload_blob_response,
+        )
+        .await;
+    let err = match bsky_client.create_record(create_record_request).await {
+        Ok(create_record_response) => return Ok(create_record_response),
+        Err(err) => err,
+    };
+    let image_bytes = match image_bytes {
+        Some(image_bytes) if is_unsupported_blob_error(&err) => image_bytes,
+        _ => return Err(err),
+    };
+    // 画像の形式が受け付けられなかった場合はJPEGに変換し直して一度だけ再投稿する
+    warn!(
+        entry_id = %feed_entry.id,
+        "Thumbnail rejected, retrying with re-encoded JPEG"
+    );
+    let upload_blob_response = bsky_client
+        .upload_thumbnail_as_jpeg(image_bytes, max_thumb_bytes)
+        .await?;
+    let create_record_request = bsky_client
+        .format_create_record_request_from_feed_entry(
+            feed_record,
+            feed,
+            feed_entry.clone(),
+            ogp_info,
+            Some(upload_blob_response),
+        )
+        .await;
+    bsky_client.create_record(create_record_request).await
+}
+
+fn is_feed_attempt_allowed(feed_record: &FeedRecord, now: DateTime<Utc>) -> bool {
+    match feed_record.next_attempt_at {
+        Some(next_attempt_at) => next_attempt_at <= now,
+        None => true,
+    }
+}
+
+// 0以下の場合は待たない
+// 失敗が続くフィードは間隔を空けて処理し、不調なサーバーに繰り返しアクセスしない
+// Blueskyへの投稿の失敗などはフィードのサーバーの不調ではないため数えない
+fn get_failure_state(
+    feed_record: &FeedRecord,
+    feed_process_result: &Result<FeedReport, OpaqueError>,
+    error_cooldown_base_minutes: i64,
+    error_cooldown_max_minutes: i64,
+    now: DateTime<Utc>,
+) -> Option<(u32, Option<DateTime<Utc>>)> {
+    match feed_process_result {
+        Err(err) if is_feed_error(err) => {
+            let consecutive_failures = feed_record.consecutive_failures.saturating_add(1);
+            let next_attempt_at = get_error_cooldown(
+                consecutive_failures,
+                error_cooldown_base_minutes,
+                error_cooldown_max_minutes,
+            )
+            .map(|error_cooldown| now + error_cooldown);
+            Some((consecutive_failures, next_attempt_at))
+        }
+        Err(_) => None,
+        // 待ち時間中で処理しなかった場合は失敗回数をそのまま残す
+        Ok(feed_report)
+            if feed_report.skip_reason.as_deref() == Some(ERROR_COOLDOWN_SKIP_REASON) =>
+        {
+            None
+        }
+        Ok(_) if feed_record.consecutive_failures > 0 || feed_record.next_attempt_at.is_some() => {
+            Some((0, None))
+        }
+        Ok(_) => None,
+    }
+}
+
+fn is_feed_error(err: &OpaqueError) -> bool {
+    matches!(
+        err.downcast_ref::<BotError>(),
+        Some(BotError::FeedFetch(_) | BotError::FeedParse(_))
+    )
+}
+
+fn get_error_cooldown(
+    consecutive_failures: u32,
+    base_minutes: i64,
+    max_minutes: i64,
+) -> Option<chrono::Duration> {
+    if consecutive_failures == 0 || base_minutes <= 0 {
+        return None;
+    }
+    let multiplier = 1_i64 << (consecutive_failures - 1).min(32);
+    let minutes = base_minutes.saturating_mul(multiplier).min(max_minutes);
+    Some(chrono::Duration::minutes(minutes))
+}
+
+fn is_feed_fetch_allowed(feed_record: &FeedRecord, now: DateTime<Utc>) -> bool {
+    match feed_record.next_fetch_allowed_at {
+        Some(next_fetch_allowed_at) => next_fetch_allowed_at <= now,
+        None => true,
+    }
+}
+
+fn is_feed_active(feed_record: &FeedRecord, now: DateTime<Utc>) -> bool {
+    match feed_record.activate_after {
+        Some(activate_after) => activate_after <= now,
+        None => true,
+    }
+}
+
+// 今回の実行で投稿できる件数。投稿間隔が設定されていない場合は制限しない
+fn get_post_allowance(feed_record: &FeedRecord, now: DateTime<Utc>) -> Option<usize> {
+    feed_record.post_interval_minutes?;
+    match feed_record.next_post_allowed_at {
+        Some(next_post_allowed_at) if now < next_post_allowed_at => Some(0),
+        _ => Some(1),
+    }
+}
+
+fn get_next_post_allowed_at(feed_record: &FeedRecord, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    feed_record
+        .post_interval_minutes
+        .map(|post_interval_minutes| now + chrono::Duration::minutes(post_interval_minutes))
+}
+
+// 投稿せずに投稿済みとして扱うエントリーの理由
+fn find_entry_skip_reason(
+    feed_record: &FeedRecord,
+    feed_entry: &FeedEntry,
+) -> Option<&'static str> {
+    // 後で本文付きで配信され直すスタブは投稿しない
+    if feed_record
+        .min_content_length
+        .is_some_and(|min_content_length| is_content_too_short(feed_entry, min_content_length))
+    {
+        return Some("content too short");
+    }
+    if feed_record.skip_entries_older_than_last_posted
+        && is_older_than_last_posted(feed_entry, feed_record.last_posted_published)
+    {
+        return Some("older than last posted");
+    }
+    // 初回の実行で最新の1件を投稿する場合も含め、登録前の記事は投稿しない
+    if is_published_before(feed_entry, feed_record.posts_after) {
+        return Some("published before registration");
     }
-    if let Some(last_posted_entry_id) = last_posted_entry_id {
-        update_feed_last_posted_entry_id(dynamodb_client, &feed_record.url, &last_posted_entry_id)
+    if feed_record.skip_restrictive_rights
+        && feed_entry
+            .rights
+            .as_deref()
+            .is_some_and(is_restrictive_rights)
+    {
+        return Some("restrictive rights");
+    }
+    None
+}
+
+// 公開からの日数による除外は実行時刻で決まるため、フィードの設定による除外の後に判断する
+fn find_entry_skip_reason_before_cutoff(
+    feed_record: &FeedRecord,
+    feed_entry: &FeedEntry,
+    published_cutoff: Option<DateTime<Utc>>,
+) -> Option<&'static str> {
+    // 公開日時も更新日時もないエントリーは古さを判断できないため投稿する
+    find_entry_skip_reason(feed_record, feed_entry).or_else(|| {
+        is_published_before(feed_entry, published_cutoff).then_some("older than max entry age")
+    })
+}
+
+// "All rights reserved"はほとんどのフィードにあるため、転載や再配布を明示的に禁じる表現だけを対象にする
+const RESTRICTIVE_RIGHTS_PHRASES: [&str; 8] = [
+    "no republish",
+    "do not republish",
+    "not for republication",
+    "may not be republished",
+    "no redistribution",
+    "do not redistribute",
+    "not for redistribution",
+    "may not be redistributed",
+];
+
+// "re-publish"と"do-not-republish"のどちらの書き方も同じ表現として扱う
+fn is_restrictive_rights(rights: &str) -> bool {
+    let rights = rights.to_lowercase();
+    [rights.replace('-', ""), rights.replace('-', " ")]
+        .iter()
+        .map(|rights| rights.split_whitespace().collect::<Vec<_>>().join(" "))
+        .any(|rights| {
+            RESTRICTIVE_RIGHTS_PHRASES
+                .iter()
+                .any(|phrase| rights.contains(phrase))
+        })
+}
+
+// 古い順に並んだエントリーから新しいものをmax_entries件だけ残し、除いた件数を返す(0の場合は制限しない)
+fn cap_target_entries(target_entries: &mut Vec<FeedEntry>, max_entries: usize) -> usize {
+    if max_entries == 0 || target_entries.len() <= max_entries {
+        return 0;
+    }
+    let dropped_count = target_entries.len() - max_entries;
+    target_entries.drain(..dropped_count);
+    dropped_count
+}
+
+// 古い順に並んだエントリーを最初の未来の日時のエントリーの手前までにし、残したエントリーを返す
+// 以降のエントリーを投稿するとlast_posted_entry_idが未来のエントリーを越えてしまうため、まとめて次回以降に回す
+fn defer_future_entries(target_entries: &mut Vec<FeedEntry>, now: DateTime<Utc>) -> Vec<FeedEntry> {
+    let Some(index) = target_entries.iter().position(|feed_entry| {
+        feed_entry
+            .published
+            .is_some_and(|published| published > now)
+    }) else {
+        return Vec::new();
+    };
+    target_entries.drain(index..).collect()
+}
+
+// 0の場合は古さで除外しない
+fn get_published_cutoff(now: DateTime<Utc>, max_entry_age_days: i64) -> Option<DateTime<Utc>> {
+    (max_entry_age_days > 0).then(|| now - chrono::Duration::days(max_entry_age_days))
+}
+
+fn is_published_before(feed_entry: &FeedEntry, posts_after: Option<DateTime<Utc>>) -> bool {
+    match (feed_entry.published.or(feed_entry.updated), posts_after) {
+        (Some(published), Some(posts_after)) => published < posts_after,
+        _ => false,
+    }
+}
+
+fn is_older_than_last_posted(
+    feed_entry: &FeedEntry,
+    last_posted_published: Option<DateTime<Utc>>,
+) -> bool {
+    match (feed_entry.published, last_posted_published) {
+        (Some(published), Some(last_posted_published)) => published <= last_posted_published,
+        _ => false,
+    }
+}
+
+fn is_content_too_short(feed_entry: &FeedEntry, min_content_length: usize) -> bool {
+    let content_length = feed_entry.summary.as_deref().map(grapheme_len).unwrap_or(0);
+    content_length < min_content_length
+}
+
+async fn seed_from_account(
+    feed_record: &FeedRecord,
+    entries: &[FeedEntry],
+    bsky_client: &mut BskyClient,
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+) -> Result<Option<String>, OpaqueError> {
+    let posted_urls = bsky_client.list_posted_embed_urls(SEED_POST_LIMIT).await?;
+    let seeded_entry_id = find_last_posted_entry_id(entries, &posted_urls);
+    if let Some(seeded_entry_id) = &seeded_entry_id {
+        update_feed_last_posted_entry_id(dynamodb_client, &feed_record.url, seeded_entry_id)
             .await?;
-        println!("last_posted_entry_id: {}", last_posted_entry_id);
+        info!(%seeded_entry_id, "Seeded last_posted_entry_id");
     }
-    println!("Finished processing feed: {}", feed_record.url);
-    Ok(())
+    Ok(seeded_entry_id)
+}
+
+// フィードのエントリーは新しい順に並んでいるため、最初に見つかった投稿済みのエントリーが最新のもの
+fn find_last_posted_entry_id(entries: &[FeedEntry], posted_urls: &[String]) -> Option<String> {
+    entries
+        .iter()
+        .find(|entry| posted_urls.contains(&entry.url))
+        .map(|entry| entry.id.clone())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session_cache::tests::MockSessionStore;
     use dotenvy::dotenv;
 
+    fn feed_entry(id: &str) -> FeedEntry {
+        FeedEntry {
+            id: id.to_string(),
+            url: format!("https://example.com/{}", id),
+            title: Some(id.to_string()),
+            published: None,
+            updated: None,
+            summary: None,
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        }
+    }
+
+    #[test]
+    fn test_find_last_posted_entry_id() {
+        let entries = vec![
+            feed_entry("4"),
+            feed_entry("3"),
+            feed_entry("2"),
+            feed_entry("1"),
+        ];
+        let posted_urls = vec![
+            "https://other.example.com/x".to_string(),
+            "https://example.com/2".to_string(),
+            "https://example.com/1".to_string(),
+        ];
+        assert_eq!(
+            find_last_posted_entry_id(&entries, &posted_urls),
+            Some("2".to_string())
+        );
+        let posted_urls = vec!["https://other.example.com/x".to_string()];
+        assert_eq!(find_last_posted_entry_id(&entries, &posted_urls), None);
+    }
+
+    #[test]
+    fn test_select_target_entries_matches_by_id() {
+        let entries = vec![feed_entry("3"), feed_entry("2"), feed_entry("1")];
+        let (target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&"2".to_string()), false);
+        assert_eq!(
+            target_entries
+                .iter()
+                .map(|e| e.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["3"]
+        );
+        assert_eq!(
+            dedup_match,
+            Some(DedupMatch {
+                field: DedupField::Id,
+                value: "2".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_select_target_entries_matches_by_url() {
+        let entries = vec![feed_entry("3"), feed_entry("2"), feed_entry("1")];
+        let (target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&"https://example.com/1".to_string()), false);
+        assert_eq!(target_entries.len(), 2);
+        assert_eq!(
+            dedup_match,
+            Some(DedupMatch {
+                field: DedupField::Url,
+                value: "https://example.com/1".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_select_target_entries_normalizes_urls() {
+        let entries = vec![feed_entry("3"), feed_entry("2"), feed_entry("1")];
+        let marker_entry_id = "https://example.com/2/".to_string();
+        let (target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&marker_entry_id), false);
+        assert_eq!(target_entries.len(), 3);
+        assert_eq!(dedup_match, None);
+        let (target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&marker_entry_id), true);
+        assert_eq!(target_entries.len(), 1);
+        assert_eq!(
+            dedup_match,
+            Some(DedupMatch {
+                field: DedupField::Url,
+                value: "https://example.com/2".to_string(),
+            })
+        );
+        let marker_entry_id = "https://example.com:443/1#top".to_string();
+        let (target_entries, _) = select_target_entries(&entries, Some(&marker_entry_id), true);
+        assert_eq!(target_entries.len(), 2);
+    }
+
+    #[test]
+    fn test_select_target_entries_with_shared_titles() {
+        let daily_update = |id: &str| FeedEntry {
+            title: Some("Daily Update".to_string()),
+            ..feed_entry(id)
+        };
+        let entries = vec![
+            daily_update("4"),
+            daily_update("3"),
+            daily_update("2"),
+            daily_update("1"),
+        ];
+        assert_eq!(
+            find_shared_titles(&entries, SHARED_TITLE_WARNING_THRESHOLD),
+            vec![SharedTitle {
+                title: "Daily Update".to_string(),
+                entry_count: 4,
+                distinct_id_count: 4,
+            }]
+        );
+        // タイトルが同じでもidで位置を判定し、新しいエントリーはすべて投稿する
+        let (target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&"2".to_string()), false);
+        assert_eq!(
+            target_entries
+                .iter()
+                .map(|e| e.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["4", "3"]
+        );
+        assert_eq!(dedup_match.unwrap().field, DedupField::Id);
+        assert!(target_entries
+            .iter()
+            .all(|e| find_entry_skip_reason(&FeedRecord::default(), e).is_none()));
+        // idまで重複している場合は区別できる
+        let entries = vec![daily_update("1"), daily_update("1"), daily_update("2")];
+        assert_eq!(
+            find_shared_titles(&entries, SHARED_TITLE_WARNING_THRESHOLD)[0].distinct_id_count,
+            2
+        );
+        assert!(find_shared_titles(&entries[..2], SHARED_TITLE_WARNING_THRESHOLD).is_empty());
+    }
+
+    #[test]
+    fn test_select_target_entries_without_match() {
+        let entries = vec![feed_entry("3"), feed_entry("2"), feed_entry("1")];
+        let (target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&"0".to_string()), false);
+        assert_eq!(target_entries.len(), 3);
+        assert_eq!(dedup_match, None);
+        let (target_entries, dedup_match) = select_target_entries(&entries, None, false);
+        assert_eq!(
+            target_entries
+                .iter()
+                .map(|e| e.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["3"]
+        );
+        assert_eq!(dedup_match, None);
+    }
+
+    #[test]
+    fn test_is_feed_fetch_allowed() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(is_feed_fetch_allowed(&FeedRecord::default(), now));
+        let feed_record = FeedRecord {
+            next_fetch_allowed_at: Some(now + chrono::Duration::minutes(2)),
+            ..Default::default()
+        };
+        assert!(!is_feed_fetch_allowed(&feed_record, now));
+        assert!(is_feed_fetch_allowed(
+            &feed_record,
+            now + chrono::Duration::minutes(2)
+        ));
+    }
+
+    #[test]
+    fn test_get_error_cooldown() {
+        let cooldowns = (1..=6)
+            .map(|consecutive_failures| {
+                get_error_cooldown(consecutive_failures, 30, 24 * 60)
+                    .unwrap()
+                    .num_minutes()
+            })
+            .collect::<Vec<_>>();
+        // 失敗が続くほど長く待ち、上限で止まる
+        assert_eq!(cooldowns, vec![30, 60, 120, 240, 480, 960]);
+        assert_eq!(
+            get_error_cooldown(7, 30, 24 * 60).unwrap().num_minutes(),
+            24 * 60
+        );
+        assert_eq!(
+            get_error_cooldown(u32::MAX, 30, 24 * 60)
+                .unwrap()
+                .num_minutes(),
+            24 * 60
+        );
+        assert_eq!(get_error_cooldown(0, 30, 24 * 60), None);
+        assert_eq!(get_error_cooldown(3, 0, 24 * 60), None);
+    }
+
+    #[test]
+    fn test_get_failure_state() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let feed_record = FeedRecord {
+            consecutive_failures: 1,
+            ..Default::default()
+        };
+        let feed_fetch_error: Result<FeedReport, OpaqueError> =
+            Err(BotError::feed_fetch("connection refused".into()).into());
+        assert_eq!(
+            get_failure_state(&feed_record, &feed_fetch_error, 30, 24 * 60, now),
+            Some((2, Some(now + chrono::Duration::minutes(60))))
+        );
+        // 無効の場合も失敗回数は数える
+        assert_eq!(
+            get_failure_state(&feed_record, &feed_fetch_error, 0, 24 * 60, now),
+            Some((2, None))
+        );
+        // Blueskyの失敗ではフィードを休ませない
+        let bsky_error: Result<FeedReport, OpaqueError> =
+            Err(BotError::bsky("upload failed".into()).into());
+        assert_eq!(
+            get_failure_state(&feed_record, &bsky_error, 30, 24 * 60, now),
+            None
+        );
+        let dynamodb_error: Result<FeedReport, OpaqueError> =
+            Err(BotError::Dynamo("throttled".into()).into());
+        assert_eq!(
+            get_failure_state(&feed_record, &dynamodb_error, 30, 24 * 60, now),
+            None
+        );
+        assert_eq!(
+            get_failure_state(
+                &feed_record,
+                &Ok(FeedReport::new(&feed_record.url)),
+                30,
+                24 * 60,
+                now
+            ),
+            Some((0, None))
+        );
+    }
+
+    #[test]
+    fn test_is_feed_attempt_allowed() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(is_feed_attempt_allowed(&FeedRecord::default(), now));
+        let feed_record = FeedRecord {
+            consecutive_failures: 2,
+            next_attempt_at: Some(now + get_error_cooldown(2, 30, 24 * 60).unwrap()),
+            ..Default::default()
+        };
+        assert!(!is_feed_attempt_allowed(&feed_record, now));
+        assert!(!is_feed_attempt_allowed(
+            &feed_record,
+            now + chrono::Duration::minutes(59)
+        ));
+        assert!(is_feed_attempt_allowed(
+            &feed_record,
+            now + chrono::Duration::minutes(60)
+        ));
+    }
+
+    #[test]
+    fn test_is_feed_active() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let feed_record = FeedRecord {
+            url: "https://example.com/feed".to_string(),
+            ..Default::default()
+        };
+        assert!(is_feed_active(&feed_record, now));
+        let feed_record = FeedRecord {
+            activate_after: Some(now + chrono::Duration::hours(1)),
+            ..feed_record
+        };
+        assert!(!is_feed_active(&feed_record, now));
+        assert!(is_feed_active(
+            &feed_record,
+            now + chrono::Duration::hours(1)
+        ));
+        assert!(is_feed_active(
+            &feed_record,
+            now + chrono::Duration::days(1)
+        ));
+    }
+
+    #[test]
+    fn test_post_interval_spreads_burst_across_runs() {
+        let start = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut feed_record = FeedRecord {
+            url: "https://example.com/feed".to_string(),
+            last_posted_entry_id: Some("1".to_string()),
+            post_interval_minutes: Some(60),
+            ..Default::default()
+        };
+        let entries = vec![
+            feed_entry("4"),
+            feed_entry("3"),
+            feed_entry("2"),
+            feed_entry("1"),
+        ];
+        let mut posted = Vec::new();
+        // 30分ごとの実行で、3件のまとめて追加されたエントリーが1時間に1件ずつ投稿される
+        for run in 0..8 {
+            let now = start + chrono::Duration::minutes(30 * run);
+            let (mut target_entries, _) =
+                select_target_entries(&entries, feed_record.last_posted_entry_id.as_ref(), false);
+            target_entries.reverse();
+            let post_allowance = get_post_allowance(&feed_record, now).unwrap();
+            let run_posts = target_entries
+                .into_iter()
+                .take(post_allowance)
+                .collect::<Vec<_>>();
+            if let Some(last_entry) = run_posts.last() {
+                feed_record.last_posted_entry_id = Some(last_entry.id.clone());
+                feed_record.next_post_allowed_at = get_next_post_allowed_at(&feed_record, now);
+                posted.push((run, last_entry.id.clone()));
+            }
+        }
+        assert_eq!(
+            posted,
+            vec![
+                (0, "2".to_string()),
+                (2, "3".to_string()),
+                (4, "4".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_post_allowance() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let feed_record = FeedRecord {
+            url: "https://example.com/feed".to_string(),
+            next_post_allowed_at: Some(now + chrono::Duration::minutes(1)),
+            ..Default::default()
+        };
+        assert_eq!(get_post_allowance(&feed_record, now), None);
+        assert_eq!(get_next_post_allowed_at(&feed_record, now), None);
+        let feed_record = FeedRecord {
+            post_interval_minutes: Some(30),
+            ..feed_record
+        };
+        assert_eq!(get_post_allowance(&feed_record, now), Some(0));
+        assert_eq!(
+            get_post_allowance(&feed_record, now + chrono::Duration::minutes(1)),
+            Some(1)
+        );
+        assert_eq!(
+            get_next_post_allowed_at(&feed_record, now),
+            Some(now + chrono::Duration::minutes(30))
+        );
+    }
+
+    #[test]
+    fn test_priority_feeds_attempted_first_within_budget() {
+        let feed_record = |url: &str, priority: i64| FeedRecord {
+            url: url.to_string(),
+            priority,
+            ..Default::default()
+        };
+        let mut feed_records = vec![
+            feed_record("https://example.com/low", -1),
+            feed_record("https://example.com/default-1", 0),
+            feed_record("https://example.com/primary", 10),
+            feed_record("https://example.com/default-2", 0),
+            feed_record("https://example.com/secondary", 5),
+        ];
+        sort_feed_records_by_priority(&mut feed_records);
+        let started_at = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // 1フィードに20秒かかる想定で、60秒の予算内に処理されるフィード
+        let mut now = started_at;
+        let mut attempted = Vec::new();
+        for feed_record in &feed_records {
+            if is_run_time_budget_exhausted(started_at, now, 60) {
+                continue;
+            }
+            attempted.push(feed_record.url.as_str());
+            now += chrono::Duration::seconds(20);
+        }
+        assert_eq!(
+            attempted,
+            vec![
+                "https://example.com/primary",
+                "https://example.com/secondary",
+                "https://example.com/default-1"
+            ]
+        );
+        assert!(!is_run_time_budget_exhausted(
+            started_at,
+            started_at + chrono::Duration::hours(1),
+            0
+        ));
+    }
+
+    #[test]
+    fn test_parse_post_now_request() {
+        let detail = serde_json::json!({
+            "post_now": {
+                "feed_url": "https://example.com/feed",
+                "entry": "https://example.com/1"
+            }
+        });
+        assert_eq!(
+            parse_post_now_request(&detail),
+            Some(PostNowRequest {
+                feed_url: "https://example.com/feed".to_string(),
+                entry: "https://example.com/1".to_string(),
+            })
+        );
+        assert_eq!(parse_post_now_request(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_select_post_now_entry_bypasses_filters() {
+        let now = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // 通常の実行では投稿されない状態のフィードとエントリー
+        let feed_record = FeedRecord {
+            url: "https://example.com/feed".to_string(),
+            last_posted_entry_id: Some("3".to_string()),
+            min_content_length: Some(100),
+            activate_after: Some(now + chrono::Duration::days(1)),
+            post_interval_minutes: Some(60),
+            next_post_allowed_at: Some(now + chrono::Duration::minutes(30)),
+            ..Default::default()
+        };
+        let entries = vec![feed_entry("3"), feed_entry("2"), feed_entry("1")];
+        assert!(!is_feed_active(&feed_record, now));
+        assert_eq!(get_post_allowance(&feed_record, now), Some(0));
+        assert!(is_content_too_short(&entries[1], 100));
+
+        let selected = select_post_now_entry(&entries, "2", &[]).unwrap();
+        assert_eq!(selected.id, "2");
+        let selected = select_post_now_entry(&entries, "https://example.com/1", &[]).unwrap();
+        assert_eq!(selected.id, "1");
+        assert!(select_post_now_entry(&entries, "0", &[]).is_err());
+        // 重複投稿だけは防ぐ
+        let posted_urls = vec!["https://example.com/2".to_string()];
+        assert!(select_post_now_entry(&entries, "2", &posted_urls).is_err());
+    }
+
+    #[test]
+    fn test_get_post_now_marker() {
+        let entries = vec![
+            feed_entry("4"),
+            feed_entry("3"),
+            feed_entry("2"),
+            feed_entry("1"),
+        ];
+        let marker_entry_id = "1".to_string();
+        // 2と3が未投稿のまま残るため、4では位置を進めない
+        assert_eq!(
+            get_post_now_marker(&entries, Some(&marker_entry_id), "4", false),
+            None
+        );
+        assert_eq!(
+            get_post_now_marker(&entries, Some(&marker_entry_id), "2", false),
+            Some("2".to_string())
+        );
+        // 投稿済みのエントリーや、位置が記録されていないフィードでは変えない
+        assert_eq!(
+            get_post_now_marker(&entries, Some(&marker_entry_id), "1", false),
+            None
+        );
+        assert_eq!(get_post_now_marker(&entries, None, "4", false), None);
+    }
+
+    #[test]
+    fn test_is_content_too_short() {
+        let stub = feed_entry("stub");
+        assert!(is_content_too_short(&stub, 1));
+        let full = FeedEntry {
+            summary: Some("本文がある記事です".to_string()),
+            ..feed_entry("full")
+        };
+        assert!(!is_content_too_short(&full, 9));
+        assert!(is_content_too_short(&full, 10));
+    }
+
+    #[test]
+    fn test_find_entry_skip_reason_older_than_last_posted() {
+        let published = |rfc3339: &str| {
+            Some(
+                DateTime::parse_from_rfc3339(rfc3339)
+                    .unwrap()
+                    .with_timezone(&Utc),
+            )
+        };
+        let feed_record = FeedRecord {
+            last_posted_entry_id: Some("marker".to_string()),
+            last_posted_published: published("2024-02-10T00:00:00Z"),
+            skip_entries_older_than_last_posted: true,
+            ..Default::default()
+        };
+        // マーカーより後に並んでいるが、公開日時はマーカーより古いエントリー
+        let entries = vec![
+            FeedEntry {
+                published: published("2024-02-11T00:00:00Z"),
+                ..feed_entry("newer")
+            },
+            FeedEntry {
+                published: published("2024-02-09T00:00:00Z"),
+                ..feed_entry("inserted")
+            },
+            FeedEntry {
+                published: published("2024-02-10T00:00:00Z"),
+                ..feed_entry("marker")
+            },
+        ];
+        let (target_entries, _) =
+            select_target_entries(&entries, Some(&"marker".to_string()), false);
+        let skip_reasons = target_entries
+            .iter()
+            .map(|feed_entry| find_entry_skip_reason(&feed_record, feed_entry))
+            .collect::<Vec<_>>();
+        assert_eq!(skip_reasons, vec![None, Some("older than last posted")]);
+        // 無効の場合や公開日時がない場合は読み飛ばさない
+        let disabled = FeedRecord {
+            skip_entries_older_than_last_posted: false,
+            ..feed_record.clone()
+        };
+        assert_eq!(find_entry_skip_reason(&disabled, &target_entries[1]), None);
+        assert_eq!(
+            find_entry_skip_reason(&feed_record, &feed_entry("undated")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_entry_skip_reason_published_before_registration() {
+        let published = |rfc3339: &str| {
+            Some(
+                DateTime::parse_from_rfc3339(rfc3339)
+                    .unwrap()
+                    .with_timezone(&Utc),
+            )
+        };
+        let feed_record = FeedRecord {
+            posts_after: published("2024-02-10T00:00:00Z"),
+            ..Default::default()
+        };
+        let entries = [
+            FeedEntry {
+                published: published("2024-02-10T00:00:00Z"),
+                ..feed_entry("after")
+            },
+            FeedEntry {
+                published: published("2024-02-09T23:59:59Z"),
+                ..feed_entry("before")
+            },
+        ];
+        // 登録前の記事は初回の実行でも投稿しない
+        let (target_entries, _) = select_target_entries(&entries[1..], None, false);
+        assert_eq!(
+            find_entry_skip_reason(&feed_record, &target_entries[0]),
+            Some("published before registration")
+        );
+        assert_eq!(find_entry_skip_reason(&feed_record, &entries[0]), None);
+        // 公開日時がなければ更新日時で判断する
+        let updated_before = FeedEntry {
+            updated: published("2024-02-01T00:00:00Z"),
+            ..feed_entry("updated")
+        };
+        assert_eq!(
+            find_entry_skip_reason(&feed_record, &updated_before),
+            Some("published before registration")
+        );
+        assert_eq!(
+            find_entry_skip_reason(&feed_record, &feed_entry("undated")),
+            None
+        );
+        // 未設定の場合は除外しない
+        assert_eq!(
+            find_entry_skip_reason(&FeedRecord::default(), &entries[1]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_entry_skip_reason_before_cutoff() {
+        let datetime = |rfc3339: &str| {
+            DateTime::parse_from_rfc3339(rfc3339)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let now = datetime("2024-02-10T00:00:00Z");
+        let published_cutoff = get_published_cutoff(now, 7);
+        assert_eq!(published_cutoff, Some(datetime("2024-02-03T00:00:00Z")));
+        let feed_record = FeedRecord::default();
+        let recent = FeedEntry {
+            published: Some(datetime("2024-02-03T00:00:00Z")),
+            ..feed_entry("recent")
+        };
+        let old = FeedEntry {
+            published: Some(datetime("2021-05-01T00:00:00Z")),
+            ..feed_entry("old")
+        };
+        assert_eq!(
+            find_entry_skip_reason_before_cutoff(&feed_record, &recent, published_cutoff),
+            None
+        );
+        assert_eq!(
+            find_entry_skip_reason_before_cutoff(&feed_record, &old, published_cutoff),
+            Some("older than max entry age")
+        );
+        // 公開日時がなければ更新日時で判断し、どちらもなければ投稿する
+        let updated_old = FeedEntry {
+            updated: Some(datetime("2021-05-01T00:00:00Z")),
+            ..feed_entry("updated")
+        };
+        assert_eq!(
+            find_entry_skip_reason_before_cutoff(&feed_record, &updated_old, published_cutoff),
+            Some("older than max entry age")
+        );
+        assert_eq!(
+            find_entry_skip_reason_before_cutoff(
+                &feed_record,
+                &feed_entry("undated"),
+                published_cutoff
+            ),
+            None
+        );
+        // 0の場合は古さで除外しない
+        assert_eq!(get_published_cutoff(now, 0), None);
+        assert_eq!(
+            find_entry_skip_reason_before_cutoff(&feed_record, &old, get_published_cutoff(now, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_posted_in_run_across_feeds() {
+        let posted_urls_in_run = Mutex::new(HashSet::new());
+        // 本体のフィードで投稿した記事を、カテゴリーのフィードでは投稿しない
+        assert!(!is_posted_in_run(
+            &posted_urls_in_run,
+            "https://example.com/posts/1"
+        ));
+        record_posted_in_run(&posted_urls_in_run, "https://example.com/posts/1");
+        assert!(is_posted_in_run(
+            &posted_urls_in_run,
+            "https://example.com/posts/1/?utm_source=category-feed"
+        ));
+        assert!(!is_posted_in_run(
+            &posted_urls_in_run,
+            "https://example.com/posts/2"
+        ));
+    }
+
+    #[test]
+    fn test_cap_target_entries() {
+        // last_posted_entry_idに一致するエントリーがなく、フィードのエントリーをすべて集めた場合
+        let entries = (1..=8)
+            .rev()
+            .map(|id| feed_entry(&id.to_string()))
+            .collect::<Vec<_>>();
+        let marker_entry_id = "missing".to_string();
+        let (mut target_entries, dedup_match) =
+            select_target_entries(&entries, Some(&marker_entry_id), false);
+        assert!(dedup_match.is_none());
+        target_entries.reverse();
+        assert_eq!(cap_target_entries(&mut target_entries, 5), 3);
+        // 新しい5件を古い順に投稿する
+        assert_eq!(
+            target_entries
+                .iter()
+                .map(|feed_entry| feed_entry.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["4", "5", "6", "7", "8"]
+        );
+        assert_eq!(cap_target_entries(&mut target_entries, 5), 0);
+        assert_eq!(cap_target_entries(&mut target_entries, 0), 0);
+        assert_eq!(target_entries.len(), 5);
+    }
+
+    #[test]
+    fn test_find_entry_skip_reason_restrictive_rights() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example News</title>
+  <id>https://example.com/</id>
+  <updated>2024-02-10T00:00:00Z</updated>
+  <rights>Copyright 2024 Example News. No re-publish without permission.</rights>
+  <entry>
+    <title>Inherited</title>
+    <id>https://example.com/inherited</id>
+    <link href="https://example.com/inherited"/>
+    <updated>2024-02-10T00:00:00Z</updated>
+  </entry>
+  <entry>
+    <title>Licensed</title>
+    <id>https://example.com/licensed</id>
+    <link href="https://example.com/licensed"/>
+    <updated>2024-02-10T00:00:00Z</updated>
+    <rights>CC BY 4.0. All rights reserved by the author.</rights>
+  </entry>
+</feed>"#;
+        let feed = feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        let entries = extract_feed_entries(&feed);
+        let feed_record = FeedRecord {
+            skip_restrictive_rights: true,
+            ..Default::default()
+        };
+        // エントリーに著作権表示がなければフィードのものを使う
+        assert_eq!(
+            find_entry_skip_reason(&feed_record, &entries[0]),
+            Some("restrictive rights")
+        );
+        // エントリーの著作権表示が優先され、"All rights reserved"だけでは除外しない
+        assert_eq!(find_entry_skip_reason(&feed_record, &entries[1]), None);
+        let do_not_redistribute = FeedEntry {
+            rights: Some("Do-not-\nredistribute".to_string()),
+            ..feed_entry("do-not-redistribute")
+        };
+        assert_eq!(
+            find_entry_skip_reason(&feed_record, &do_not_redistribute),
+            Some("restrictive rights")
+        );
+        // 未設定の場合は除外しない
+        assert_eq!(
+            find_entry_skip_reason(&FeedRecord::default(), &entries[0]),
+            None
+        );
+    }
+
     #[tokio::test]
     async fn test_execute() {
         dotenv().ok();
@@ -123,16 +2286,30 @@ mod tests {
         dotenv().ok();
         let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
         let dynamodb_client = aws_sdk_dynamodb::Client::new(&aws_config);
-        let mut bsky_client = bsky::BskyClient::new().await.unwrap();
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = tokio::sync::Mutex::new(
+            bsky::BskyClient::new(&MockSessionStore::default(), reqwest_client.clone())
+                .await
+                .unwrap(),
+        );
         let feed_record = FeedRecord {
             url: "https://blog.rust-lang.org/feed.xml".to_string(),
             last_posted_entry_id: Some(
                 "https://blog.rust-lang.org/2023/12/28/Rust-1.75.0.html".to_string(),
             ),
+            ..Default::default()
         };
-        process_feed(&feed_record, &mut bsky_client, &dynamodb_client)
-            .await
-            .unwrap();
+        process_feed(
+            &feed_record,
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &Mutex::new(HashSet::new()),
+            false,
+        )
+        .await
+        .unwrap();
     }
 
     #[tokio::test]
@@ -140,13 +2317,1058 @@ mod tests {
         dotenv().ok();
         let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
         let dynamodb_client = aws_sdk_dynamodb::Client::new(&aws_config);
-        let mut bsky_client = bsky::BskyClient::new().await.unwrap();
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = tokio::sync::Mutex::new(
+            bsky::BskyClient::new(&MockSessionStore::default(), reqwest_client.clone())
+                .await
+                .unwrap(),
+        );
         let feed_record = FeedRecord {
             url: "https://blog.rust-lang.org/feed.xml".to_string(),
             last_posted_entry_id: None,
+            ..Default::default()
         };
-        process_feed(&feed_record, &mut bsky_client, &dynamodb_client)
+        process_feed(
+            &feed_record,
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &Mutex::new(HashSet::new()),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    // entry_pathsは新しい順に並べる
+    fn test_feed_response(
+        server: &wiremock::MockServer,
+        entry_paths: &[&str],
+    ) -> wiremock::ResponseTemplate {
+        let items = entry_paths
+            .iter()
+            .map(|entry_path| {
+                format!(
+                    "<item><title>{1}</title><link>{0}/{1}</link><guid>{0}/{1}</guid></item>",
+                    server.uri(),
+                    entry_path
+                )
+            })
+            .collect::<String>();
+        wiremock::ResponseTemplate::new(200).set_body_raw(
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Example Blog</title><link>{}/</link>{}</channel></rss>"#,
+                server.uri(),
+                items
+            ),
+            "application/rss+xml",
+        )
+    }
+
+    async fn mount_test_feed(
+        server: &wiremock::MockServer,
+        feed_path: &str,
+        entry_paths: &[&str],
+        delay: std::time::Duration,
+    ) {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        Mock::given(method("GET"))
+            .and(path(feed_path))
+            .respond_with(test_feed_response(server, entry_paths).set_delay(delay))
+            .mount(server)
+            .await;
+        for entry_path in entry_paths {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", entry_path)))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(
+                    format!(
+                        r#"<html><head><meta property="og:title" content="{}"></head></html>"#,
+                        entry_path
+                    ),
+                    "text/html",
+                ))
+                .mount(server)
+                .await;
+        }
+    }
+
+    // フィードとOGPのページのほか、DynamoDBとBlueskyへのリクエストもすべてこのサーバーで受ける
+    async fn start_test_server(entry_paths: &[&str]) -> wiremock::MockServer {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        mount_test_feed(&server, "/feed.xml", entry_paths, std::time::Duration::ZERO).await;
+        Mock::given(method("POST"))
+            .and(path("/"))
+            .and(header("content-type", "application/x-amz-json-1.0"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw("{}", "application/x-amz-json-1.0"),
+            )
+            .mount(&server)
+            .await;
+        let post_count = AtomicUsize::new(0);
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.createRecord"))
+            .respond_with(move |_: &wiremock::Request| {
+                let post_number = post_count.fetch_add(1, Ordering::SeqCst) + 1;
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "uri": format!("at://did:plc:test/app.bsky.feed.post/{}", post_number),
+                    "cid": format!("cid{}", post_number),
+                }))
+            })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn test_dynamodb_client(server: &wiremock::MockServer) -> aws_sdk_dynamodb::Client {
+        aws_sdk_dynamodb::Client::from_conf(
+            aws_sdk_dynamodb::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(aws_sdk_dynamodb::config::Region::new("ap-northeast-1"))
+                .credentials_provider(aws_sdk_dynamodb::config::Credentials::new(
+                    "test", "test", None, None, "test",
+                ))
+                .endpoint_url(server.uri())
+                .build(),
+        )
+    }
+
+    fn test_bsky_client(
+        server: &wiremock::MockServer,
+        reqwest_client: &reqwest::Client,
+    ) -> tokio::sync::Mutex<BskyClient> {
+        use crate::session_cache::CachedSession;
+
+        tokio::sync::Mutex::new(
+            BskyClient::from_cached_session(
+                CachedSession {
+                    identifier: "bot.example.com".to_string(),
+                    access_jwt: "access".to_string(),
+                    refresh_jwt: "refresh".to_string(),
+                    handle: "bot.example.com".to_string(),
+                    did: "did:plc:test".to_string(),
+                    service_url: server.uri(),
+                },
+                reqwest_client.clone(),
+            )
+            .unwrap(),
+        )
+    }
+
+    // DynamoDBへのリクエストを操作名と本文の組で返す
+    async fn received_dynamodb_requests(
+        server: &wiremock::MockServer,
+    ) -> Vec<(String, serde_json::Value)> {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|request| {
+                let target = request.headers.get(&"x-amz-target".into())?.as_str();
+                Some((
+                    target.trim_start_matches("DynamoDB_20120810.").to_string(),
+                    request.body_json().unwrap(),
+                ))
+            })
+            .collect()
+    }
+
+    // 保存されたlast_posted_entry_idを保存した順に返す
+    async fn saved_last_posted_entry_ids(server: &wiremock::MockServer) -> Vec<String> {
+        received_dynamodb_requests(server)
+            .await
+            .iter()
+            .filter_map(|(operation, body)| {
+                let entry_id = &body["ExpressionAttributeValues"][":last_posted_entry_id"]["S"];
+                (operation == "UpdateItem").then(|| entry_id.as_str())?
+            })
+            .map(|entry_id| entry_id.trim_start_matches(&server.uri()).to_string())
+            .collect()
+    }
+
+    // テスト中に出力されたログを受け取る
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        // 戻り値を破棄するまで、このスレッドのログを受け取る
+        fn capture(&self) -> tracing::subscriber::DefaultGuard {
+            let captured_logs = self.clone();
+            tracing::subscriber::set_default(
+                tracing_subscriber::fmt()
+                    .with_max_level(tracing::Level::DEBUG)
+                    .with_writer(move || captured_logs.clone())
+                    .finish(),
+            )
+        }
+
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    async fn received_paths(server: &wiremock::MockServer) -> Vec<String> {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| request.url.path().to_string())
+            .collect()
+    }
+
+    fn test_feed_run(server: &wiremock::MockServer) -> FeedRun {
+        let reqwest_client = http_client::build_client().unwrap();
+        FeedRun {
+            bsky_client: Arc::new(test_bsky_client(server, &reqwest_client)),
+            dynamodb_client: test_dynamodb_client(server),
+            reqwest_client,
+            post_rate_limiter: None,
+            posted_urls_in_run: Arc::new(Mutex::new(HashSet::new())),
+            started_at: Utc::now(),
+            run_time_budget: 0,
+            error_cooldown_base_minutes: 0,
+            error_cooldown_max_minutes: 0,
+            dry_run: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_feeds_continues_after_failed_feed() {
+        let server = start_test_server(&[]).await;
+        // 最初のフィードが最後に終わるようにして、完了順と結果の順をずらす
+        mount_test_feed(
+            &server,
+            "/slow.xml",
+            &["slow-new", "slow-old"],
+            std::time::Duration::from_millis(500),
+        )
+        .await;
+        mount_test_feed(
+            &server,
+            "/fast.xml",
+            &["fast-new", "fast-old"],
+            std::time::Duration::ZERO,
+        )
+        .await;
+        let feed_record = |feed_path: &str, last_posted_entry_id: &str| FeedRecord {
+            url: format!("{}{}", server.uri(), feed_path),
+            last_posted_entry_id: Some(format!("{}/{}", server.uri(), last_posted_entry_id)),
+            ..Default::default()
+        };
+        let feed_records = vec![
+            feed_record("/slow.xml", "slow-old"),
+            // 取得できないフィード
+            feed_record("/missing.xml", "missing-old"),
+            feed_record("/fast.xml", "fast-old"),
+        ];
+        let feed_reports = process_feeds(&test_feed_run(&server), feed_records.clone(), 3)
             .await
             .unwrap();
+        assert_eq!(
+            feed_reports
+                .iter()
+                .map(|feed_report| feed_report.feed_url.as_str())
+                .collect::<Vec<_>>(),
+            feed_records
+                .iter()
+                .map(|feed_record| feed_record.url.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(feed_reports[1].error.is_some());
+        for (feed_report, entry_path) in [
+            (&feed_reports[0], "slow-new"),
+            (&feed_reports[2], "fast-new"),
+        ] {
+            assert!(feed_report.error.is_none());
+            assert_eq!(
+                feed_report
+                    .posts
+                    .iter()
+                    .map(|posted_entry| posted_entry.entry_id.clone())
+                    .collect::<Vec<_>>(),
+                vec![format!("{}/{}", server.uri(), entry_path)]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_feed_entry_posts_without_thumbnail_on_upload_error() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_test_server(&["entry"]).await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.uploadBlob"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed = feed_rs::parser::parse(
+            reqwest_client
+                .get(format!("{}/feed.xml", server.uri()))
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap()
+                .as_ref(),
+        )
+        .unwrap();
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let og_image = OGImage {
+            image: bytes::Bytes::from_static(b"thumbnail"),
+            content_type: "image/jpeg".to_string(),
+            resized: true,
+        };
+        let ogp_info = OGPInfo {
+            title: Some("OGP title".to_string()),
+            image_url: Some(format!("{}/thumbnail.jpg", server.uri())),
+            twitter_image_url: None,
+            description: None,
+        };
+        // アップロードに失敗してもエントリーは失敗にせず、カードだけで投稿する
+        post_feed_entry(
+            &FeedRecord::default(),
+            &feed,
+            &feed_entry,
+            Some(ogp_info),
+            Some(og_image),
+            &mut *bsky_client.lock().await,
+        )
+        .await
+        .unwrap();
+        let create_record_bodies = server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path() == "/xrpc/com.atproto.repo.createRecord")
+            .map(|request| request.body_json::<serde_json::Value>().unwrap())
+            .collect::<Vec<_>>();
+        assert!(received_paths(&server)
+            .await
+            .contains(&"/xrpc/com.atproto.repo.uploadBlob".to_string()));
+        assert_eq!(create_record_bodies.len(), 1);
+        let external = &create_record_bodies[0]["record"]["embed"]["external"];
+        assert_eq!(external["title"], "OGP title");
+        assert!(external.get("thumb").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_post_feed_entry_retries_rejected_thumbnail_as_jpeg() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_test_server(&["entry"]).await;
+        let upload_count = AtomicUsize::new(0);
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.uploadBlob"))
+            .respond_with(move |request: &wiremock::Request| {
+                let upload_number = upload_count.fetch_add(1, Ordering::SeqCst) + 1;
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "blob": {
+                        "$type": "blob",
+                        "ref": { "$link": format!("bafkblob{}", upload_number) },
+                        "mimeType": request.headers.get(&"content-type".into()).unwrap().as_str(),
+                        "size": request.body.len(),
+                    },
+                }))
+            })
+            .mount(&server)
+            .await;
+        // 最初の投稿だけ画像の形式を理由に拒否し、以降はテスト用のサーバーの応答に任せる
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.createRecord"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "InvalidRequest",
+                "message": "Wrong type of blob: expected image/*, got application/octet-stream (invalid mimetype)",
+            })))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed = feed_rs::parser::parse(
+            reqwest_client
+                .get(format!("{}/feed.xml", server.uri()))
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap()
+                .as_ref(),
+        )
+        .unwrap();
+        let feed_entry = extract_feed_entries(&feed).remove(0);
+        let mut png_bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            4,
+            4,
+            image::Rgba([255, 0, 0, 128]),
+        ))
+        .write_to(
+            &mut std::io::Cursor::new(&mut png_bytes),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+        let og_image = OGImage {
+            image: bytes::Bytes::from(png_bytes),
+            content_type: "image/png".to_string(),
+            resized: true,
+        };
+        let ogp_info = OGPInfo {
+            title: Some("entry".to_string()),
+            image_url: None,
+            twitter_image_url: None,
+            description: None,
+        };
+        let create_record_response = post_feed_entry(
+            &FeedRecord::default(),
+            &feed,
+            &feed_entry,
+            Some(ogp_info),
+            Some(og_image),
+            &mut *bsky_client.lock().await,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            create_record_response.uri,
+            "at://did:plc:test/app.bsky.feed.post/1"
+        );
+        let requests = server.received_requests().await.unwrap();
+        let requests_to = |xrpc_path: &str| {
+            requests
+                .iter()
+                .filter(|request| request.url.path() == xrpc_path)
+                .collect::<Vec<_>>()
+        };
+        // JPEGに変換し直した画像をアップロードし、その画像で一度だけ投稿し直す
+        let upload_requests = requests_to("/xrpc/com.atproto.repo.uploadBlob");
+        assert_eq!(upload_requests.len(), 2);
+        assert_eq!(
+            upload_requests[1]
+                .headers
+                .get(&"content-type".into())
+                .unwrap()
+                .as_str(),
+            "image/jpeg"
+        );
+        assert_eq!(&upload_requests[1].body[..2], &[0xff, 0xd8]);
+        let create_record_requests = requests_to("/xrpc/com.atproto.repo.createRecord");
+        assert_eq!(create_record_requests.len(), 2);
+        assert!(String::from_utf8_lossy(&create_record_requests[0].body).contains("bafkblob1"));
+        assert!(String::from_utf8_lossy(&create_record_requests[1].body).contains("bafkblob2"));
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_dry_run() {
+        let server = start_test_server(&["new", "posted"]).await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed_record = FeedRecord {
+            url: format!("{}/feed.xml", server.uri()),
+            last_posted_entry_id: Some(format!("{}/posted", server.uri())),
+            ..Default::default()
+        };
+        let feed_report = process_feed(
+            &feed_record,
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &Mutex::new(HashSet::new()),
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(feed_report.posts.is_empty());
+        assert_eq!(
+            feed_report
+                .skipped
+                .iter()
+                .map(|skipped_entry| (
+                    skipped_entry.entry_id.as_str(),
+                    skipped_entry.reason.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![(
+                format!("{}/new", server.uri()).as_str(),
+                DRY_RUN_SKIP_REASON
+            )]
+        );
+        // フィードとOGPは取得するが、DynamoDBにもBlueskyにも書き込まない
+        assert_eq!(received_paths(&server).await, vec!["/feed.xml", "/new"]);
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_dry_run_does_not_save_position() {
+        let server = start_test_server(&["new", "old"]).await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed_url = format!("{}/feed.xml", server.uri());
+        // 通常は位置を記録する、登録直後のフィードと位置がまだないフィード
+        for feed_record in [
+            FeedRecord {
+                url: feed_url.clone(),
+                activate_after: Some(Utc::now() + chrono::Duration::days(1)),
+                ..Default::default()
+            },
+            FeedRecord {
+                url: feed_url.clone(),
+                ..Default::default()
+            },
+        ] {
+            process_feed(
+                &feed_record,
+                &bsky_client,
+                &dynamodb_client,
+                &reqwest_client,
+                None,
+                &Mutex::new(HashSet::new()),
+                true,
+            )
+            .await
+            .unwrap();
+        }
+        assert!(received_dynamodb_requests(&server).await.is_empty());
+        assert!(!received_paths(&server)
+            .await
+            .iter()
+            .any(|requested_path| requested_path.starts_with("/xrpc/")));
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_defers_future_entries() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_test_server(&["scheduled", "new", "posted"]).await;
+        // 予約投稿の日時を変えたフィードを、予約前と公開後の2つ用意する
+        for (feed_path, scheduled_at) in [
+            ("/before.xml", Utc::now() + chrono::Duration::days(1)),
+            ("/after.xml", Utc::now() - chrono::Duration::minutes(1)),
+        ] {
+            let items = [
+                ("scheduled", scheduled_at),
+                ("new", Utc::now() - chrono::Duration::hours(1)),
+                ("posted", Utc::now() - chrono::Duration::hours(2)),
+            ]
+            .iter()
+            .map(|(entry_path, published)| {
+                format!(
+                    "<item><title>{1}</title><link>{0}/{1}</link><guid>{0}/{1}</guid><pubDate>{2}</pubDate></item>",
+                    server.uri(),
+                    entry_path,
+                    published.to_rfc2822()
+                )
+            })
+            .collect::<String>();
+            Mock::given(method("GET"))
+                .and(path(feed_path))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(
+                    format!(
+                        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Example Blog</title><link>{}/</link>{}</channel></rss>"#,
+                        server.uri(),
+                        items
+                    ),
+                    "application/rss+xml",
+                ))
+                .mount(&server)
+                .await;
+        }
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let process = |feed_path: &str, last_posted_entry_path: &str| {
+            let feed_record = FeedRecord {
+                url: format!("{}{}", server.uri(), feed_path),
+                last_posted_entry_id: Some(format!("{}/{}", server.uri(), last_posted_entry_path)),
+                defer_future_entries: true,
+                ..Default::default()
+            };
+            let dynamodb_client = &dynamodb_client;
+            let reqwest_client = &reqwest_client;
+            let bsky_client = &bsky_client;
+            async move {
+                process_feed(
+                    &feed_record,
+                    bsky_client,
+                    dynamodb_client,
+                    reqwest_client,
+                    None,
+                    &Mutex::new(HashSet::new()),
+                    false,
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let posted_entry_ids = |feed_report: &FeedReport| {
+            feed_report
+                .posts
+                .iter()
+                .map(|posted_entry| posted_entry.entry_id.clone())
+                .collect::<Vec<_>>()
+        };
+        // 予約投稿の日時より前の実行では、その手前までを投稿して予約投稿は見送る
+        let feed_report = process("/before.xml", "posted").await;
+        assert_eq!(
+            posted_entry_ids(&feed_report),
+            vec![format!("{}/new", server.uri())]
+        );
+        assert_eq!(
+            feed_report.skipped,
+            vec![SkippedEntry {
+                entry_id: format!("{}/scheduled", server.uri()),
+                reason: FUTURE_ENTRY_SKIP_REASON.to_string(),
+            }]
+        );
+        // 投稿したフィードは見送ったエントリーがあっても成功として数える
+        assert_eq!(feed_report.skip_reason, None);
+        assert_eq!(summarize_run(&[feed_report]).succeeded, 1);
+        // last_posted_entry_idは予約投稿を越えない
+        let dynamodb_requests = received_dynamodb_requests(&server).await;
+        assert!(dynamodb_requests
+            .iter()
+            .any(|(_, body)| body.to_string().contains("/new")));
+        assert!(dynamodb_requests
+            .iter()
+            .all(|(_, body)| !body.to_string().contains("/scheduled")));
+        // 日時を過ぎた後の実行で投稿される
+        let feed_report = process("/after.xml", "new").await;
+        assert_eq!(
+            posted_entry_ids(&feed_report),
+            vec![format!("{}/scheduled", server.uri())]
+        );
+        assert!(feed_report.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_reposts_updated_entry() {
+        use wiremock::matchers::{body_partial_json, header, method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_test_server(&["corrected"]).await;
+        let entry_id = format!("{}/corrected", server.uri());
+        let feed_body = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom"><title>Example Blog</title><id>{0}/</id><updated>2024-02-11T00:00:00Z</updated><link href="{0}/"/>
+<entry><title>corrected</title><id>{1}</id><link href="{1}"/><published>2024-02-10T00:00:00Z</published><updated>2024-02-11T00:00:00Z</updated></entry>
+</feed>"#,
+            server.uri(),
+            entry_id
+        );
+        // 同じエントリーを含む2つのフィードのうち、1つ目だけが以前に投稿している
+        for feed_path in ["/posted.xml", "/other.xml"] {
+            Mock::given(method("GET"))
+                .and(path(feed_path))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_raw(feed_body.clone(), "application/atom+xml"),
+                )
+                .mount(&server)
+                .await;
+        }
+        let posted_feed_url = format!("{}/posted.xml", server.uri());
+        Mock::given(method("POST"))
+            .and(path("/"))
+            .and(header("x-amz-target", "DynamoDB_20120810.GetItem"))
+            .and(body_partial_json(serde_json::json!({
+                "Key": {
+                    "feed_url": { "S": posted_feed_url },
+                    "entry_id": { "S": entry_id },
+                },
+            })))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(
+                    serde_json::json!({
+                        "Item": {
+                            "feed_url": { "S": posted_feed_url },
+                            "entry_id": { "S": entry_id },
+                            "post_uri": { "S": "at://did:plc:test/app.bsky.feed.post/previous" },
+                            "updated": { "S": "2024-02-10T00:00:00+00:00" },
+                        },
+                    })
+                    .to_string(),
+                    "application/x-amz-json-1.0",
+                ),
+            )
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.repo.deleteRecord"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let mut feed_reports = Vec::new();
+        for feed_path in ["/posted.xml", "/other.xml"] {
+            let feed_record = FeedRecord {
+                url: format!("{}{}", server.uri(), feed_path),
+                last_posted_entry_id: Some(entry_id.clone()),
+                repost_updated_entries: true,
+                ..Default::default()
+            };
+            feed_reports.push(
+                process_feed(
+                    &feed_record,
+                    &bsky_client,
+                    &dynamodb_client,
+                    &reqwest_client,
+                    None,
+                    &Mutex::new(HashSet::new()),
+                    false,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        // 修正されたエントリーを投稿し直してから、以前の投稿を削除する
+        assert_eq!(
+            feed_reports[0].posts,
+            vec![PostedEntry {
+                entry_id: entry_id.clone(),
+                uri: "at://did:plc:test/app.bsky.feed.post/1".to_string(),
+            }]
+        );
+        let requests = server.received_requests().await.unwrap();
+        let xrpc_requests = requests
+            .iter()
+            .filter(|request| request.url.path().starts_with("/xrpc/"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            xrpc_requests
+                .iter()
+                .map(|request| request.url.path())
+                .collect::<Vec<_>>(),
+            vec![
+                "/xrpc/com.atproto.repo.createRecord",
+                "/xrpc/com.atproto.repo.deleteRecord"
+            ]
+        );
+        assert_eq!(
+            xrpc_requests[1].body_json::<serde_json::Value>().unwrap()["rkey"],
+            "previous"
+        );
+        // 新しい投稿をフィードとエントリーの組に対応付ける
+        assert!(received_dynamodb_requests(&server).await.iter().any(
+            |(operation, body)| operation == "PutItem"
+                && body["Item"]["feed_url"]["S"] == posted_feed_url
+                && body["Item"]["entry_id"]["S"] == entry_id
+                && body["Item"]["post_uri"]["S"] == "at://did:plc:test/app.bsky.feed.post/1"
+        ));
+        // 投稿していないフィードでは同じidのエントリーでも投稿し直さない
+        assert!(feed_reports[1].posts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_saves_position_before_failed_post() {
+        use wiremock::{matchers::method, Mock, ResponseTemplate};
+
+        let server = start_test_server(&["5", "4", "3", "2", "1", "posted"]).await;
+        // 3件目の投稿に失敗する
+        Mock::given(method("POST"))
+            .and(|request: &wiremock::Request| {
+                request.url.path() == "/xrpc/com.atproto.repo.createRecord"
+                    && String::from_utf8_lossy(&request.body).contains("/3\"")
+            })
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed_record = FeedRecord {
+            url: format!("{}/feed.xml", server.uri()),
+            last_posted_entry_id: Some(format!("{}/posted", server.uri())),
+            ..Default::default()
+        };
+        let result = process_feed(
+            &feed_record,
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &Mutex::new(HashSet::new()),
+            false,
+        )
+        .await;
+        assert!(result.is_err());
+        // 失敗する前に投稿したエントリーまでの位置が投稿するたびに残る
+        assert_eq!(saved_last_posted_entry_ids(&server).await, vec!["/1", "/2"]);
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_saves_skipped_entries_once() {
+        let server = start_test_server(&["skipped2", "skipped1", "new", "posted"]).await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let posted_urls_in_run = Mutex::new(HashSet::new());
+        for entry_path in ["skipped1", "skipped2"] {
+            record_posted_in_run(
+                &posted_urls_in_run,
+                &format!("{}/{}", server.uri(), entry_path),
+            );
+        }
+        let feed_record = |last_posted_entry_path: &str| FeedRecord {
+            url: format!("{}/feed.xml", server.uri()),
+            last_posted_entry_id: Some(format!("{}/{}", server.uri(), last_posted_entry_path)),
+            ..Default::default()
+        };
+        let feed_report = process_feed(
+            &feed_record("posted"),
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &posted_urls_in_run,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(feed_report.posts.len(), 1);
+        assert_eq!(feed_report.skipped.len(), 2);
+        // 投稿した後に除外したエントリーは最後にまとめて1回だけ保存する
+        assert_eq!(
+            saved_last_posted_entry_ids(&server).await,
+            vec!["/new", "/skipped2"]
+        );
+        // 新しいエントリーがなければ保存しない
+        process_feed(
+            &feed_record("skipped2"),
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &posted_urls_in_run,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            saved_last_posted_entry_ids(&server).await,
+            vec!["/new", "/skipped2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_logs_dedup_match() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_test_server(&["new", "posted"]).await;
+        // guidを付け直してidがURLと異なるようになったフィード
+        Mock::given(method("GET"))
+            .and(path("/renamed.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom"><title>Example Blog</title><id>{0}/</id><updated>2024-02-11T00:00:00Z</updated>
+<entry><title>new</title><id>urn:uuid:new</id><link href="{0}/new"/><updated>2024-02-11T00:00:00Z</updated></entry>
+<entry><title>posted</title><id>urn:uuid:posted</id><link href="{0}/posted"/><updated>2024-02-10T00:00:00Z</updated></entry>
+</feed>"#,
+                    server.uri()
+                ),
+                "application/atom+xml",
+            ))
+            .mount(&server)
+            .await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let captured_logs = CapturedLogs::default();
+        let _guard = captured_logs.capture();
+        let process = |feed_path: &str, last_posted_entry_id: &str| {
+            let feed_record = FeedRecord {
+                url: format!("{}{}", server.uri(), feed_path),
+                last_posted_entry_id: Some(last_posted_entry_id.to_string()),
+                ..Default::default()
+            };
+            let dynamodb_client = &dynamodb_client;
+            let reqwest_client = &reqwest_client;
+            let bsky_client = &bsky_client;
+            async move {
+                process_feed(
+                    &feed_record,
+                    bsky_client,
+                    dynamodb_client,
+                    reqwest_client,
+                    None,
+                    &Mutex::new(HashSet::new()),
+                    true,
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let posted_url = format!("{}/posted", server.uri());
+        // idで一致したことと、一致した値を出す
+        process("/feed.xml", &posted_url).await;
+        let logs = captured_logs.take();
+        assert!(logs.contains(&format!(
+            "Matched last_posted_entry_id field=Id value={}",
+            posted_url
+        )));
+        // idが変わっていてもURLで一致したことがわかる
+        process("/renamed.xml", &posted_url).await;
+        let logs = captured_logs.take();
+        assert!(logs.contains(&format!(
+            "Matched last_posted_entry_id field=Url value={}",
+            posted_url
+        )));
+        // どのエントリーとも一致しない場合は保存されていた値を出す
+        process("/feed.xml", "urn:uuid:missing").await;
+        let logs = captured_logs.take();
+        assert!(logs.contains(
+            "No entry matched last_posted_entry_id by id or url last_posted_entry_id=urn:uuid:missing"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_posts_in_order_after_concurrent_prefetch() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_test_server(&["3", "2", "1", "posted"]).await;
+        // 最初に投稿するエントリーのOGPが最後に取得し終わるようにする
+        Mock::given(method("GET"))
+            .and(path("/1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(
+                        r#"<html><head><meta property="og:title" content="1"></head></html>"#,
+                        "text/html",
+                    )
+                    .set_delay(std::time::Duration::from_millis(2500)),
+            )
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed_record = FeedRecord {
+            url: format!("{}/feed.xml", server.uri()),
+            last_posted_entry_id: Some(format!("{}/posted", server.uri())),
+            ..Default::default()
+        };
+        let feed_report = process_feed(
+            &feed_record,
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &Mutex::new(HashSet::new()),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            feed_report
+                .posts
+                .iter()
+                .map(|posted_entry| posted_entry.entry_id.trim_start_matches(&server.uri()))
+                .collect::<Vec<_>>(),
+            vec!["/1", "/2", "/3"]
+        );
+        let requests = server.received_requests().await.unwrap();
+        // 投稿を始める前にすべてのエントリーのOGPを取得し始めている
+        let first_post_index = requests
+            .iter()
+            .position(|request| request.url.path() == "/xrpc/com.atproto.repo.createRecord")
+            .unwrap();
+        for entry_path in ["/1", "/2", "/3"] {
+            assert!(requests[..first_post_index]
+                .iter()
+                .any(|request| request.url.path() == entry_path));
+        }
+        // 取得し終わった順ではなく古い順に投稿する
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|request| request.url.path() == "/xrpc/com.atproto.repo.createRecord")
+                .map(
+                    |request| request.body_json::<serde_json::Value>().unwrap()["record"]["text"]
+                        .as_str()
+                        .unwrap()
+                        .to_string()
+                )
+                .collect::<Vec<_>>(),
+            vec!["1 | Example Blog", "2 | Example Blog", "3 | Example Blog"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_feed_uses_prefetch_after_skipped_entry() {
+        let server = start_test_server(&["3", "2", "1", "posted"]).await;
+        let dynamodb_client = test_dynamodb_client(&server);
+        let reqwest_client = http_client::build_client().unwrap();
+        let bsky_client = test_bsky_client(&server, &reqwest_client);
+        let feed_record = FeedRecord {
+            url: format!("{}/feed.xml", server.uri()),
+            last_posted_entry_id: Some(format!("{}/posted", server.uri())),
+            ..Default::default()
+        };
+        // 最も古いエントリーは他のフィードが投稿済み
+        let posted_urls_in_run = Mutex::new(HashSet::new());
+        record_posted_in_run(&posted_urls_in_run, &format!("{}/1", server.uri()));
+        let feed_report = process_feed(
+            &feed_record,
+            &bsky_client,
+            &dynamodb_client,
+            &reqwest_client,
+            None,
+            &posted_urls_in_run,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(feed_report.posts.len(), 2);
+        assert_eq!(feed_report.skipped.len(), 1);
+        // 飛ばしたエントリーより後のエントリーも先読みの結果を使い、取得し直さない
+        let requests = server.received_requests().await.unwrap();
+        for entry_path in ["/2", "/3"] {
+            assert_eq!(
+                requests
+                    .iter()
+                    .filter(|request| request.url.path() == entry_path)
+                    .count(),
+                1
+            );
+        }
     }
 }
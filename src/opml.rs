@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use chrono::Utc;
+
+use crate::{
+    dynamodb::{list_registered_feeds, put_feed},
+    OpaqueError,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpmlFeed {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+// フォルダ分けされたネストしたoutlineも含めて、xmlUrlを持つoutlineをすべて抽出する
+pub fn parse_opml(xml: &str) -> Result<Vec<OpmlFeed>, OpaqueError> {
+    let document = roxmltree::Document::parse(xml)?;
+    let mut feeds: Vec<OpmlFeed> = Vec::new();
+    for node in document
+        .descendants()
+        .filter(|node| node.has_tag_name("outline"))
+    {
+        let Some(url) = node.attribute("xmlUrl") else {
+            continue;
+        };
+        let url = url.trim();
+        if url.is_empty() || feeds.iter().any(|feed| feed.url == url) {
+            continue;
+        }
+        let title = node
+            .attribute("title")
+            .or(node.attribute("text"))
+            .map(|title| title.to_string());
+        feeds.push(OpmlFeed {
+            url: url.to_string(),
+            title,
+        });
+    }
+    Ok(feeds)
+}
+
+pub async fn import_opml(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    path: &Path,
+) -> Result<(), OpaqueError> {
+    let xml = std::fs::read_to_string(path)?;
+    let feeds = parse_opml(&xml)?;
+    for feed in feeds {
+        if put_feed(
+            dynamodb_client,
+            &feed.url,
+            feed.title.as_deref(),
+            Utc::now(),
+        )
+        .await?
+        {
+            println!("Registered feed: {}", feed.url);
+        } else {
+            println!("Skipped already registered feed: {}", feed.url);
+        }
+    }
+    Ok(())
+}
+
+pub fn write_opml(feeds: &[OpmlFeed]) -> String {
+    let mut opml = String::new();
+    opml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    opml.push_str("<opml version=\"2.0\">\n");
+    opml.push_str("  <head>\n    <title>bsky-feed-bot feeds</title>\n  </head>\n");
+    opml.push_str("  <body>\n");
+    for feed in feeds {
+        let text = escape_xml_attribute(feed.title.as_deref().unwrap_or(&feed.url));
+        let url = escape_xml_attribute(&feed.url);
+        match &feed.title {
+            Some(title) => opml.push_str(&format!(
+                "    <outline type=\"rss\" text=\"{}\" title=\"{}\" xmlUrl=\"{}\"/>\n",
+                text,
+                escape_xml_attribute(title),
+                url
+            )),
+            None => opml.push_str(&format!(
+                "    <outline type=\"rss\" text=\"{}\" xmlUrl=\"{}\"/>\n",
+                text, url
+            )),
+        }
+    }
+    opml.push_str("  </body>\n");
+    opml.push_str("</opml>\n");
+    opml
+}
+
+fn escape_xml_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub async fn export_opml(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    path: &Path,
+) -> Result<(), OpaqueError> {
+    let feeds: Vec<OpmlFeed> = list_registered_feeds(dynamodb_client)
+        .await?
+        .into_iter()
+        .map(|feed_record| OpmlFeed {
+            url: feed_record.url,
+            title: feed_record.title,
+        })
+        .collect();
+    std::fs::write(path, write_opml(&feeds))?;
+    println!("Exported {} feeds to {}", feeds.len(), path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_opml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head>
+    <title>Subscriptions</title>
+  </head>
+  <body>
+    <outline text="Rust Blog" type="rss" xmlUrl="https://blog.rust-lang.org/feed.xml" htmlUrl="https://blog.rust-lang.org/"/>
+    <outline text="Tech">
+      <outline text="GitHub Blog" title="The GitHub Blog" type="rss" xmlUrl="https://github.blog/feed/"/>
+      <outline text="Editors">
+        <outline text="Zed" type="rss" xmlUrl="https://zed.dev/blog.rss"/>
+      </outline>
+    </outline>
+    <outline text="Duplicated Rust Blog" type="rss" xmlUrl="https://blog.rust-lang.org/feed.xml"/>
+  </body>
+</opml>"#;
+        let feeds = parse_opml(xml).unwrap();
+        assert_eq!(
+            feeds,
+            vec![
+                OpmlFeed {
+                    url: "https://blog.rust-lang.org/feed.xml".to_string(),
+                    title: Some("Rust Blog".to_string()),
+                },
+                OpmlFeed {
+                    url: "https://github.blog/feed/".to_string(),
+                    title: Some("The GitHub Blog".to_string()),
+                },
+                OpmlFeed {
+                    url: "https://zed.dev/blog.rss".to_string(),
+                    title: Some("Zed".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_opml_round_trip() {
+        let feeds = vec![
+            OpmlFeed {
+                url: "https://blog.rust-lang.org/feed.xml".to_string(),
+                title: Some("Rust Blog".to_string()),
+            },
+            OpmlFeed {
+                url: "https://example.com/feed?format=rss&lang=ja".to_string(),
+                title: Some("\"Tom & Jerry\" <News>".to_string()),
+            },
+            OpmlFeed {
+                url: "https://zed.dev/blog.rss".to_string(),
+                title: None,
+            },
+        ];
+        let opml = write_opml(&feeds);
+        let parsed = parse_opml(&opml).unwrap();
+        assert_eq!(parsed[0], feeds[0]);
+        assert_eq!(parsed[1], feeds[1]);
+        // タイトルが保存されていない場合はtextにURLが入る
+        assert_eq!(parsed[2].url, feeds[2].url);
+        assert_eq!(parsed[2].title, Some(feeds[2].url.clone()));
+    }
+
+    #[test]
+    fn test_parse_invalid_opml() {
+        assert!(parse_opml("<opml><body>").is_err());
+    }
+}
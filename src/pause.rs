@@ -0,0 +1,71 @@
+use tracing::info;
+
+use crate::{dynamodb::get_pause_state, OpaqueError};
+
+// 設定テーブルのpausedが有効な間は、再デプロイせずにすべての処理を止める
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PauseState {
+    pub paused: bool,
+    pub reason: Option<String>,
+}
+
+pub trait PauseStore {
+    async fn get_pause_state(&self) -> Result<PauseState, OpaqueError>;
+}
+
+pub struct DynamoDbPauseStore<'a> {
+    pub client: &'a aws_sdk_dynamodb::Client,
+}
+
+impl PauseStore for DynamoDbPauseStore<'_> {
+    async fn get_pause_state(&self) -> Result<PauseState, OpaqueError> {
+        get_pause_state(self.client).await
+    }
+}
+
+// 設定を読み込めない場合は止まっているか判断できないため、投稿せずにエラーにする
+pub async fn is_paused(store: &impl PauseStore) -> Result<bool, OpaqueError> {
+    let pause_state = store.get_pause_state().await?;
+    if pause_state.paused {
+        match &pause_state.reason {
+            Some(reason) => info!(reason, "Posting is paused, exiting"),
+            None => info!("Posting is paused, exiting"),
+        }
+    }
+    Ok(pause_state.paused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockPauseStore {
+        pause_state: Option<PauseState>,
+    }
+
+    impl PauseStore for MockPauseStore {
+        async fn get_pause_state(&self) -> Result<PauseState, OpaqueError> {
+            self.pause_state
+                .clone()
+                .ok_or_else(|| "config table is unavailable".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_is_paused() {
+        let paused = MockPauseStore {
+            pause_state: Some(PauseState {
+                paused: true,
+                reason: Some("incident".to_string()),
+            }),
+        };
+        assert!(is_paused(&paused).await.unwrap());
+        let unpaused = MockPauseStore {
+            pause_state: Some(PauseState::default()),
+        };
+        assert!(!is_paused(&unpaused).await.unwrap());
+        // 読み込めない場合は処理を続けない
+        let unavailable = MockPauseStore { pause_state: None };
+        assert!(is_paused(&unavailable).await.is_err());
+    }
+}
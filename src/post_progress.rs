@@ -0,0 +1,51 @@
+use crate::{dynamodb::update_feed_last_posted_entry_id, OpaqueError};
+
+// 投稿するたびに位置を保存し、途中で失敗しても投稿済みのエントリーを次の実行で投稿し直さないようにする
+pub struct PostProgress<'a> {
+    dynamodb_client: &'a aws_sdk_dynamodb::Client,
+    feed_url: &'a str,
+    last_entry_id: Option<String>,
+    // last_entry_idを保存済みか
+    saved: bool,
+}
+
+impl<'a> PostProgress<'a> {
+    pub fn new(dynamodb_client: &'a aws_sdk_dynamodb::Client, feed_url: &'a str) -> Self {
+        Self {
+            dynamodb_client,
+            feed_url,
+            last_entry_id: None,
+            saved: true,
+        }
+    }
+
+    // 投稿しなかったエントリーは、次に投稿したときか最後にまとめて保存する
+    pub fn skip(&mut self, entry_id: &str) {
+        self.last_entry_id = Some(entry_id.to_string());
+        self.saved = false;
+    }
+
+    pub async fn record_post(&mut self, entry_id: &str) -> Result<(), OpaqueError> {
+        self.last_entry_id = Some(entry_id.to_string());
+        self.saved = false;
+        self.save().await
+    }
+
+    // まだ保存していない位置を保存し、保存した場合はそのidを返す
+    pub async fn finish(&mut self) -> Result<Option<String>, OpaqueError> {
+        if self.saved {
+            return Ok(None);
+        }
+        self.save().await?;
+        Ok(self.last_entry_id.clone())
+    }
+
+    async fn save(&mut self) -> Result<(), OpaqueError> {
+        if let Some(last_entry_id) = &self.last_entry_id {
+            update_feed_last_posted_entry_id(self.dynamodb_client, self.feed_url, last_entry_id)
+                .await?;
+        }
+        self.saved = true;
+        Ok(())
+    }
+}
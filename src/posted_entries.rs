@@ -0,0 +1,282 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    dynamodb::{get_posted_entry, put_posted_entry},
+    feed::FeedEntry,
+    OpaqueError,
+};
+
+// 投稿したエントリーと投稿のURI、投稿した時点でのエントリーの更新日時
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostedEntryRecord {
+    pub feed_url: String,
+    pub entry_id: String,
+    pub post_uri: String,
+    pub updated: Option<DateTime<Utc>>,
+}
+
+pub trait PostedEntryStore {
+    async fn get_posted_entry(
+        &self,
+        feed_url: &str,
+        entry_id: &str,
+    ) -> Result<Option<PostedEntryRecord>, OpaqueError>;
+    async fn put_posted_entry(&self, posted_entry: &PostedEntryRecord) -> Result<(), OpaqueError>;
+}
+
+pub struct DynamoDbPostedEntryStore<'a> {
+    pub client: &'a aws_sdk_dynamodb::Client,
+}
+
+impl PostedEntryStore for DynamoDbPostedEntryStore<'_> {
+    async fn get_posted_entry(
+        &self,
+        feed_url: &str,
+        entry_id: &str,
+    ) -> Result<Option<PostedEntryRecord>, OpaqueError> {
+        get_posted_entry(self.client, feed_url, entry_id).await
+    }
+
+    async fn put_posted_entry(&self, posted_entry: &PostedEntryRecord) -> Result<(), OpaqueError> {
+        put_posted_entry(self.client, posted_entry).await
+    }
+}
+
+pub async fn record_posted_entry(
+    store: &impl PostedEntryStore,
+    feed_url: &str,
+    feed_entry: &FeedEntry,
+    post_uri: &str,
+) -> Result<(), OpaqueError> {
+    let posted_entry = PostedEntryRecord {
+        feed_url: feed_url.to_string(),
+        entry_id: feed_entry.id.clone(),
+        post_uri: post_uri.to_string(),
+        updated: feed_entry.updated.or(feed_entry.published),
+    };
+    store.put_posted_entry(&posted_entry).await
+}
+
+fn is_updated_since_post(feed_entry: &FeedEntry, posted_entry: &PostedEntryRecord) -> bool {
+    match (feed_entry.updated, posted_entry.updated) {
+        (Some(updated), Some(posted_updated)) => updated > posted_updated,
+        // 投稿した時点の日時がわからない場合は、公開後に更新されていれば修正されたとみなす
+        (Some(updated), None) => feed_entry
+            .published
+            .is_some_and(|published| updated > published),
+        (None, _) => false,
+    }
+}
+
+// 投稿済みのエントリーのうち、投稿した後に修正されたものと以前の投稿を返す
+pub async fn find_updated_entries(
+    store: &impl PostedEntryStore,
+    feed_url: &str,
+    posted_feed_entries: &[FeedEntry],
+) -> Result<Vec<(FeedEntry, PostedEntryRecord)>, OpaqueError> {
+    let mut updated_entries = Vec::new();
+    // 更新日時のないエントリーは修正を判断できないため問い合わせない
+    for feed_entry in posted_feed_entries
+        .iter()
+        .filter(|feed_entry| feed_entry.updated.is_some())
+    {
+        let Some(posted_entry) = store.get_posted_entry(feed_url, &feed_entry.id).await? else {
+            continue;
+        };
+        if is_updated_since_post(feed_entry, &posted_entry) {
+            updated_entries.push((feed_entry.clone(), posted_entry));
+        }
+    }
+    Ok(updated_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockPostedEntryStore {
+        posted_entries: Mutex<HashMap<(String, String), PostedEntryRecord>>,
+    }
+
+    impl PostedEntryStore for MockPostedEntryStore {
+        async fn get_posted_entry(
+            &self,
+            feed_url: &str,
+            entry_id: &str,
+        ) -> Result<Option<PostedEntryRecord>, OpaqueError> {
+            Ok(self
+                .posted_entries
+                .lock()
+                .unwrap()
+                .get(&(feed_url.to_string(), entry_id.to_string()))
+                .cloned())
+        }
+
+        async fn put_posted_entry(
+            &self,
+            posted_entry: &PostedEntryRecord,
+        ) -> Result<(), OpaqueError> {
+            self.posted_entries.lock().unwrap().insert(
+                (posted_entry.feed_url.clone(), posted_entry.entry_id.clone()),
+                posted_entry.clone(),
+            );
+            Ok(())
+        }
+    }
+
+    const FEED_URL: &str = "https://example.com/feed.xml";
+
+    fn datetime(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn feed_entry(id: &str, updated: Option<&str>) -> FeedEntry {
+        FeedEntry {
+            id: id.to_string(),
+            url: format!("https://example.com/{}", id),
+            title: Some(id.to_string()),
+            published: Some(datetime("2024-02-10T00:00:00Z")),
+            updated: updated.map(datetime),
+            summary: None,
+            image_url: None,
+            media_thumbnail_url: None,
+            comments_url: None,
+            categories: Vec::new(),
+            rights: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repost_updated_entries() {
+        let store = MockPostedEntryStore::default();
+        let first = feed_entry("1", Some("2024-02-10T00:00:00Z"));
+        let second = feed_entry("2", Some("2024-02-10T00:00:00Z"));
+        record_posted_entry(
+            &store,
+            FEED_URL,
+            &first,
+            "at://did:plc:test/app.bsky.feed.post/3k1",
+        )
+        .await
+        .unwrap();
+        record_posted_entry(
+            &store,
+            FEED_URL,
+            &second,
+            "at://did:plc:test/app.bsky.feed.post/3k2",
+        )
+        .await
+        .unwrap();
+        // 修正されていなければ投稿し直さない
+        assert!(
+            find_updated_entries(&store, FEED_URL, &[first.clone(), second.clone()])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // 修正されたエントリーは以前の投稿と合わせて返す
+        let corrected = feed_entry("1", Some("2024-02-11T00:00:00Z"));
+        let updated_entries =
+            find_updated_entries(&store, FEED_URL, &[corrected.clone(), second.clone()])
+                .await
+                .unwrap();
+        assert_eq!(updated_entries.len(), 1);
+        let (updated_entry, previous_post) = &updated_entries[0];
+        assert_eq!(updated_entry.id, "1");
+        assert_eq!(
+            previous_post.post_uri,
+            "at://did:plc:test/app.bsky.feed.post/3k1"
+        );
+
+        // 投稿し直した後は新しい投稿に対応付け、次回の実行で再び投稿しない
+        record_posted_entry(
+            &store,
+            FEED_URL,
+            &corrected,
+            "at://did:plc:test/app.bsky.feed.post/3k3",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            store.posted_entries.lock().unwrap()[&(FEED_URL.to_string(), "1".to_string())].post_uri,
+            "at://did:plc:test/app.bsky.feed.post/3k3"
+        );
+        assert!(find_updated_entries(&store, FEED_URL, &[corrected, second])
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_updated_entries_without_mapping() {
+        let store = MockPostedEntryStore::default();
+        // 対応が保存されていないエントリーは以前の投稿を削除できないため対象にしない
+        let corrected = feed_entry("1", Some("2024-02-11T00:00:00Z"));
+        assert!(find_updated_entries(&store, FEED_URL, &[corrected])
+            .await
+            .unwrap()
+            .is_empty());
+        // 更新日時のないエントリーは対象にしない
+        store.posted_entries.lock().unwrap().insert(
+            (FEED_URL.to_string(), "2".to_string()),
+            PostedEntryRecord {
+                feed_url: FEED_URL.to_string(),
+                entry_id: "2".to_string(),
+                post_uri: "at://did:plc:test/app.bsky.feed.post/3k2".to_string(),
+                updated: None,
+            },
+        );
+        assert!(
+            find_updated_entries(&store, FEED_URL, &[feed_entry("2", None)])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        // 投稿時点の日時がない対応は公開日時と比べる
+        assert_eq!(
+            find_updated_entries(
+                &store,
+                FEED_URL,
+                &[feed_entry("2", Some("2024-02-11T00:00:00Z"))]
+            )
+            .await
+            .unwrap()
+            .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_updated_entries_by_feed() {
+        let store = MockPostedEntryStore::default();
+        record_posted_entry(
+            &store,
+            FEED_URL,
+            &feed_entry("1", Some("2024-02-10T00:00:00Z")),
+            "at://did:plc:test/app.bsky.feed.post/3k1",
+        )
+        .await
+        .unwrap();
+        // 別のフィードで同じidのエントリーが修正されても、このフィードの投稿は削除しない
+        let corrected = [feed_entry("1", Some("2024-02-11T00:00:00Z"))];
+        assert!(
+            find_updated_entries(&store, "https://example.org/feed.xml", &corrected)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            find_updated_entries(&store, FEED_URL, &corrected)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
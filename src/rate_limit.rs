@@ -0,0 +1,90 @@
+use chrono::{DateTime, Duration, Utc};
+
+// 1時間あたりの投稿数を数えるための期間と、その期間中の投稿数
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostRateWindow {
+    pub started_at: DateTime<Utc>,
+    pub post_count: u32,
+}
+
+#[derive(Debug)]
+pub struct PostRateLimiter {
+    max_posts_per_hour: u32,
+    window: PostRateWindow,
+}
+
+impl PostRateLimiter {
+    // 保存されている期間が1時間以上前に始まっていれば新しい期間を始める
+    pub fn new(
+        max_posts_per_hour: u32,
+        stored_window: Option<PostRateWindow>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let window = match stored_window {
+            Some(window) if now - window.started_at < Duration::hours(1) => window,
+            _ => PostRateWindow {
+                started_at: now,
+                post_count: 0,
+            },
+        };
+        Self {
+            max_posts_per_hour,
+            window,
+        }
+    }
+
+    pub fn can_post(&self) -> bool {
+        self.window.post_count < self.max_posts_per_hour
+    }
+
+    pub fn remaining_posts(&self) -> u32 {
+        self.max_posts_per_hour
+            .saturating_sub(self.window.post_count)
+    }
+
+    pub fn record_post(&mut self) {
+        self.window.post_count += 1;
+    }
+
+    pub fn window(&self) -> &PostRateWindow {
+        &self.window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_rate_limiter_across_runs() {
+        let start = DateTime::parse_from_rfc3339("2024-02-10T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut stored_window = None;
+        let mut posted = Vec::new();
+        // 15分ごとの実行で毎回3件ずつ新しいエントリーがある場合、1時間に5件までしか投稿しない
+        for run in 0..6 {
+            let now = start + Duration::minutes(15 * run);
+            let mut limiter = PostRateLimiter::new(5, stored_window.clone(), now);
+            let mut run_posts = 0;
+            for _ in 0..3 {
+                if !limiter.can_post() {
+                    break;
+                }
+                limiter.record_post();
+                run_posts += 1;
+            }
+            assert_eq!(limiter.remaining_posts(), 5 - limiter.window().post_count);
+            stored_window = Some(limiter.window().clone());
+            posted.push(run_posts);
+        }
+        assert_eq!(posted, vec![3, 2, 0, 0, 3, 2]);
+        assert_eq!(
+            stored_window,
+            Some(PostRateWindow {
+                started_at: start + Duration::hours(1),
+                post_count: 5,
+            })
+        );
+    }
+}
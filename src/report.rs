@@ -0,0 +1,497 @@
+use std::{env, time::Duration};
+
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::{
+    config::{env_flag, env_parse},
+    http_client,
+    retry::{retry_with_backoff, RetryPolicy},
+    text::truncate_graphemes,
+    OpaqueError,
+};
+
+// Discordのcontentの上限
+const MAX_WEBHOOK_MESSAGE_GRAPHEMES: usize = 2000;
+// 通知先が応答しなくても実行を止めないよう短めにする
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PostedEntry {
+    pub entry_id: String,
+    pub uri: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SkippedEntry {
+    pub entry_id: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct FeedReport {
+    pub feed_url: String,
+    pub posts: Vec<PostedEntry>,
+    pub skipped: Vec<SkippedEntry>,
+    // フィードの取得に使われたHTTPのバージョン
+    pub http_version: Option<String>,
+    // フィード全体を投稿しなかった理由
+    pub skip_reason: Option<String>,
+    pub error: Option<String>,
+}
+
+impl FeedReport {
+    pub fn new(feed_url: &str) -> Self {
+        FeedReport {
+            feed_url: feed_url.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RunReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub feeds: Vec<FeedReport>,
+}
+
+// 実行全体の結果。失敗したフィードがあっても実行自体は成功として扱う
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunSummary {
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+pub fn summarize_run(feed_reports: &[FeedReport]) -> RunSummary {
+    let mut run_summary = RunSummary::default();
+    for feed_report in feed_reports {
+        if feed_report.error.is_some() {
+            run_summary.failed += 1;
+        } else if feed_report.skip_reason.is_some() {
+            run_summary.skipped += 1;
+        } else {
+            run_summary.succeeded += 1;
+        }
+    }
+    run_summary
+}
+
+pub trait ReportStore {
+    async fn put_report(&self, key: &str, body: Vec<u8>) -> Result<(), OpaqueError>;
+}
+
+pub struct S3ReportStore {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl ReportStore for S3ReportStore {
+    async fn put_report(&self, key: &str, body: Vec<u8>) -> Result<(), OpaqueError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct ReportDestination {
+    pub store: S3ReportStore,
+    pub key_prefix: String,
+}
+
+// RUN_REPORT_S3_BUCKETが設定されている場合のみ実行結果をS3に書き出す
+pub fn report_destination_from_env(
+    aws_config: &aws_config::SdkConfig,
+) -> Option<ReportDestination> {
+    let bucket = env::var("RUN_REPORT_S3_BUCKET")
+        .ok()
+        .filter(|bucket| !bucket.is_empty())?;
+    let key_prefix = env::var("RUN_REPORT_S3_PREFIX").unwrap_or("run-reports/".to_string());
+    Some(ReportDestination {
+        store: S3ReportStore {
+            client: aws_sdk_s3::Client::new(aws_config),
+            bucket,
+        },
+        key_prefix,
+    })
+}
+
+fn build_report_key(key_prefix: &str, started_at: &DateTime<Utc>) -> String {
+    format!(
+        "{}{}/{}.json",
+        key_prefix,
+        started_at.format("%Y/%m/%d"),
+        started_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+pub async fn write_run_report(
+    store: &impl ReportStore,
+    key_prefix: &str,
+    run_report: &RunReport,
+) -> Result<String, OpaqueError> {
+    let key = build_report_key(key_prefix, &run_report.started_at);
+    let body = serde_json::to_vec_pretty(run_report)?;
+    store.put_report(&key, body).await?;
+    Ok(key)
+}
+
+pub struct WebhookDestination {
+    pub url: String,
+    // 失敗したフィードがある場合のみ通知する
+    pub only_on_failure: bool,
+    pub retry_policy: RetryPolicy,
+}
+
+// RUN_SUMMARY_WEBHOOK_URLが設定されている場合のみ実行結果の概要を通知する
+pub fn webhook_destination_from_env() -> Result<Option<WebhookDestination>, OpaqueError> {
+    let Some(url) = env::var("RUN_SUMMARY_WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+    else {
+        return Ok(None);
+    };
+    Ok(Some(WebhookDestination {
+        url,
+        only_on_failure: env_flag("RUN_SUMMARY_WEBHOOK_ONLY_ON_FAILURE", false),
+        retry_policy: RetryPolicy {
+            max_retries: env_parse("RUN_SUMMARY_WEBHOOK_RETRIES", 2)?,
+            initial_backoff: Duration::from_millis(env_parse(
+                "RUN_SUMMARY_WEBHOOK_BACKOFF_MS",
+                1000,
+            )?),
+        },
+    }))
+}
+
+// Slackは"text"、Discordは"content"を本文として使うため、両方に同じ内容を入れる
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WebhookPayload {
+    pub text: String,
+    pub content: String,
+}
+
+fn format_run_summary(run_report: &RunReport) -> String {
+    let failed_feeds = run_report
+        .feeds
+        .iter()
+        .filter(|feed_report| feed_report.error.is_some())
+        .collect::<Vec<_>>();
+    let post_count: usize = run_report
+        .feeds
+        .iter()
+        .map(|feed_report| feed_report.posts.len())
+        .sum();
+    let skipped_count: usize = run_report
+        .feeds
+        .iter()
+        .map(|feed_report| feed_report.skipped.len())
+        .sum();
+    let mut lines = vec![format!(
+        "bsky-feed-bot run at {}: {} feeds, {} posts, {} skipped entries, {} failed feeds",
+        run_report
+            .started_at
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        run_report.feeds.len(),
+        post_count,
+        skipped_count,
+        failed_feeds.len()
+    )];
+    for feed_report in failed_feeds {
+        lines.push(format!(
+            "- {}: {}",
+            feed_report.feed_url,
+            feed_report.error.as_deref().unwrap_or_default()
+        ));
+    }
+    lines.join("\n")
+}
+
+pub fn build_webhook_payload(run_report: &RunReport) -> WebhookPayload {
+    let summary = truncate_graphemes(
+        &format_run_summary(run_report),
+        MAX_WEBHOOK_MESSAGE_GRAPHEMES,
+    );
+    WebhookPayload {
+        text: summary.clone(),
+        content: summary,
+    }
+}
+
+// 一時的なエラーのみ再試行する
+fn is_retryable_webhook_error(err: &reqwest::Error) -> bool {
+    err.is_connect()
+        || err.is_timeout()
+        || err
+            .status()
+            .is_some_and(|status| status.is_server_error() || status.as_u16() == 429)
+}
+
+// 通知しなかった場合はfalseを返す
+pub async fn post_run_summary(
+    destination: &WebhookDestination,
+    run_report: &RunReport,
+) -> Result<bool, OpaqueError> {
+    if destination.only_on_failure
+        && run_report
+            .feeds
+            .iter()
+            .all(|feed_report| feed_report.error.is_none())
+    {
+        return Ok(false);
+    }
+    let client = http_client::client_builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()?;
+    let payload = build_webhook_payload(run_report);
+    retry_with_backoff(
+        &destination.retry_policy,
+        is_retryable_webhook_error,
+        || async {
+            client
+                .post(&destination.url)
+                .json(&payload)
+                .send()
+                .await?
+                .error_for_status()
+        },
+    )
+    .await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::text::grapheme_len;
+
+    #[derive(Default)]
+    struct MockReportStore {
+        reports: Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl ReportStore for MockReportStore {
+        async fn put_report(&self, key: &str, body: Vec<u8>) -> Result<(), OpaqueError> {
+            self.reports.lock().unwrap().push((key.to_string(), body));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_run_report() {
+        let started_at = DateTime::parse_from_rfc3339("2024-02-10T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let run_report = RunReport {
+            started_at,
+            finished_at: started_at + chrono::Duration::seconds(30),
+            feeds: vec![
+                FeedReport {
+                    posts: vec![PostedEntry {
+                        entry_id: "https://example.com/1".to_string(),
+                        uri: "at://did:plc:test/app.bsky.feed.post/3kl".to_string(),
+                    }],
+                    skipped: vec![SkippedEntry {
+                        entry_id: "https://example.com/2".to_string(),
+                        reason: "content too short".to_string(),
+                    }],
+                    http_version: Some("HTTP/2.0".to_string()),
+                    ..FeedReport::new("https://example.com/feed")
+                },
+                FeedReport {
+                    error: Some("redirect loop detected".to_string()),
+                    ..FeedReport::new("https://example.org/feed")
+                },
+            ],
+        };
+        let store = MockReportStore::default();
+        let key = write_run_report(&store, "run-reports/", &run_report)
+            .await
+            .unwrap();
+        assert_eq!(key, "run-reports/2024/02/10/2024-02-10T03:04:05Z.json");
+
+        let reports = store.reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0, key);
+        let json: serde_json::Value = serde_json::from_slice(&reports[0].1).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "started_at": "2024-02-10T03:04:05Z",
+                "finished_at": "2024-02-10T03:04:35Z",
+                "feeds": [
+                    {
+                        "feed_url": "https://example.com/feed",
+                        "posts": [{
+                            "entry_id": "https://example.com/1",
+                            "uri": "at://did:plc:test/app.bsky.feed.post/3kl"
+                        }],
+                        "skipped": [{
+                            "entry_id": "https://example.com/2",
+                            "reason": "content too short"
+                        }],
+                        "http_version": "HTTP/2.0",
+                        "skip_reason": null,
+                        "error": null
+                    },
+                    {
+                        "feed_url": "https://example.org/feed",
+                        "posts": [],
+                        "skipped": [],
+                        "http_version": null,
+                        "skip_reason": null,
+                        "error": "redirect loop detected"
+                    }
+                ]
+            })
+        );
+    }
+
+    fn test_run_report() -> RunReport {
+        let started_at = DateTime::parse_from_rfc3339("2024-02-10T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        RunReport {
+            started_at,
+            finished_at: started_at + chrono::Duration::seconds(30),
+            feeds: vec![
+                FeedReport {
+                    posts: vec![PostedEntry {
+                        entry_id: "https://example.com/1".to_string(),
+                        uri: "at://did:plc:test/app.bsky.feed.post/3kl".to_string(),
+                    }],
+                    skipped: vec![SkippedEntry {
+                        entry_id: "https://example.com/2".to_string(),
+                        reason: "content too short".to_string(),
+                    }],
+                    ..FeedReport::new("https://example.com/feed")
+                },
+                FeedReport {
+                    error: Some("redirect loop detected".to_string()),
+                    ..FeedReport::new("https://example.org/feed")
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_summarize_run() {
+        let mut run_report = test_run_report();
+        run_report.feeds.push(FeedReport {
+            skip_reason: Some("run time budget exhausted".to_string()),
+            ..FeedReport::new("https://example.net/feed")
+        });
+        assert_eq!(
+            summarize_run(&run_report.feeds),
+            RunSummary {
+                succeeded: 1,
+                skipped: 1,
+                failed: 1,
+            }
+        );
+        assert_eq!(summarize_run(&[]), RunSummary::default());
+    }
+
+    fn test_destination(url: String, only_on_failure: bool) -> WebhookDestination {
+        WebhookDestination {
+            url,
+            only_on_failure,
+            retry_policy: RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_run_summary() {
+        let server = MockServer::start().await;
+        // 一時的なエラーは再試行する
+        Mock::given(method("POST"))
+            .and(path("/webhook"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/webhook"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        let destination = test_destination(format!("{}/webhook", server.uri()), false);
+        assert!(post_run_summary(&destination, &test_run_report())
+            .await
+            .unwrap());
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let summary = "bsky-feed-bot run at 2024-02-10T03:04:05Z: 2 feeds, 1 posts, 1 skipped entries, 1 failed feeds\n- https://example.org/feed: redirect loop detected";
+        let json: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "text": summary,
+                "content": summary,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_run_summary_only_on_failure() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let destination = test_destination(server.uri(), true);
+        let mut run_report = test_run_report();
+        run_report.feeds.truncate(1);
+        assert!(!post_run_summary(&destination, &run_report).await.unwrap());
+        assert!(server.received_requests().await.unwrap().is_empty());
+        assert!(post_run_summary(&destination, &test_run_report())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_post_run_summary_failure() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        // 再試行しても成功しないエラーはそのまま返し、呼び出し側で記録だけする
+        let destination = test_destination(server.uri(), false);
+        assert!(post_run_summary(&destination, &test_run_report())
+            .await
+            .is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_build_webhook_payload_truncates_long_summary() {
+        let mut run_report = test_run_report();
+        run_report.feeds[1].error = Some("x".repeat(3000));
+        let payload = build_webhook_payload(&run_report);
+        assert_eq!(
+            grapheme_len(&payload.content),
+            MAX_WEBHOOK_MESSAGE_GRAPHEMES
+        );
+        assert_eq!(payload.text, payload.content);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{config::env_parse, OpaqueError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    // 再試行のたびに2倍にする
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    // 最初の取得を含めた回数で指定する
+    // 以前のCONNECT_RETRY_MAXは再試行の回数、CONNECT_RETRY_BACKOFF_MSは同じ意味の設定として引き継ぐ
+    pub fn feed_fetch_from_env() -> Result<Self, OpaqueError> {
+        let max_attempts: u32 = env_parse(
+            "FEED_FETCH_MAX_ATTEMPTS",
+            env_parse::<u32>("CONNECT_RETRY_MAX", 2)?.saturating_add(1),
+        )?;
+        let initial_backoff_ms = env_parse(
+            "FEED_FETCH_RETRY_BACKOFF_MS",
+            env_parse("CONNECT_RETRY_BACKOFF_MS", 500)?,
+        )?;
+        Ok(RetryPolicy {
+            max_retries: max_attempts.saturating_sub(1),
+            initial_backoff: Duration::from_millis(initial_backoff_ms),
+        })
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    should_retry: impl Fn(&E) -> bool,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: std::fmt::Debug,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < policy.max_retries && should_retry(&err) => {
+                let backoff = with_jitter(policy.backoff(attempt));
+                warn!(backoff = ?backoff, error = ?err, "Retrying after error");
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// 複数の実行が同じ間隔で再試行し続けないよう、待ち時間に最大で半分までの揺らぎを加える
+fn with_jitter(backoff: Duration) -> Duration {
+    let jitter_range = backoff.as_millis() as u64 / 2;
+    if jitter_range == 0 {
+        return backoff;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    backoff + Duration::from_millis(random % (jitter_range + 1))
+}
+
+// DNSの名前解決や接続の失敗はLambdaの起動直後に一時的に起こりやすい
+// 応答が返ってこずにタイムアウトした場合も一時的な失敗とみなす
+pub fn is_transient_request_error(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const TEST_POLICY: RetryPolicy = RetryPolicy {
+        max_retries: 2,
+        initial_backoff: Duration::from_millis(1),
+    };
+
+    #[tokio::test]
+    async fn test_retry_with_backoff_dns_error_then_success() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .mount(&server)
+            .await;
+        let attempts = AtomicUsize::new(0);
+        let response = retry_with_backoff(&TEST_POLICY, is_transient_request_error, || {
+            // 1回目は解決できないホストに接続してDNSエラーを起こす
+            let url = match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => "http://feed.invalid/feed".to_string(),
+                _ => format!("{}/feed", server.uri()),
+            };
+            reqwest::get(url)
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff_gives_up() {
+        let attempts = AtomicUsize::new(0);
+        let result = retry_with_backoff(&TEST_POLICY, is_transient_request_error, || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            reqwest::get("http://feed.invalid/feed")
+        })
+        .await;
+        assert!(result.unwrap_err().is_connect());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // 接続エラー以外は再試行しない
+        let attempts = AtomicUsize::new(0);
+        let result: Result<(), &str> = retry_with_backoff(
+            &TEST_POLICY,
+            |_| false,
+            || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async { Err("not found") }
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_with_jitter() {
+        for _ in 0..100 {
+            let backoff = with_jitter(Duration::from_millis(500));
+            assert!(backoff >= Duration::from_millis(500));
+            assert!(backoff <= Duration::from_millis(750));
+        }
+        assert_eq!(
+            with_jitter(Duration::from_millis(1)),
+            Duration::from_millis(1)
+        );
+    }
+}
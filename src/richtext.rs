@@ -0,0 +1,401 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_properties::{GeneralCategoryGroup, UnicodeGeneralCategory};
+
+use crate::text::{grapheme_len, truncate_graphemes};
+
+// app.bsky.richtext.facetのレキシコンと同じJSONになるようにする
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Facet {
+    pub index: ByteSlice,
+    pub features: Vec<FacetFeature>,
+}
+
+// Blueskyのfacetの位置はUTF-8のバイト単位で指定する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ByteSlice {
+    pub byte_start: usize,
+    pub byte_end: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "$type")]
+pub enum FacetFeature {
+    #[serde(rename = "app.bsky.richtext.facet#link")]
+    Link { uri: String },
+    #[serde(rename = "app.bsky.richtext.facet#mention")]
+    Mention { did: String },
+    // tagには先頭の#を含めない
+    #[serde(rename = "app.bsky.richtext.facet#tag")]
+    Tag { tag: String },
+}
+
+// app.bsky.feed.postのtagsとハッシュタグの長さの上限(#を含めない)
+pub const MAX_TAG_GRAPHEMES: usize = 64;
+pub const MAX_TAG_BYTES: usize = 640;
+
+// 文末の句読点や閉じ括弧はURLに含めない
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', ')', ']'];
+
+pub fn detect_link_facets(text: &str, max_facets: usize) -> Vec<Facet> {
+    let url_regex = Regex::new(r#"https?://[^\s<>"'「」『』（）、。]+"#).unwrap();
+    url_regex
+        .find_iter(text)
+        .filter_map(|url_match| {
+            let url = url_match
+                .as_str()
+                .trim_end_matches(|c| TRAILING_PUNCTUATION.contains(&c));
+            if url.len() <= "https://".len() {
+                return None;
+            }
+            Some(Facet {
+                index: ByteSlice {
+                    byte_start: url_match.start(),
+                    byte_end: url_match.start() + url.len(),
+                },
+                features: vec![FacetFeature::Link {
+                    uri: url.to_string(),
+                }],
+            })
+        })
+        .take(max_facets)
+        .collect()
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.general_category_group() == GeneralCategoryGroup::Punctuation
+}
+
+// Blueskyの公式クライアントと同じく、行頭か空白の直後の#から始まるものをハッシュタグとする
+pub fn detect_tag_facets(text: &str) -> Vec<Facet> {
+    let tag_regex = Regex::new(
+        r"(?:^|\s)([#＃])([^\s\u{00AD}\u{2060}\u{200A}\u{200B}\u{200C}\u{200D}\u{20E2}]+)",
+    )
+    .unwrap();
+    tag_regex
+        .captures_iter(text)
+        .filter_map(|captures| {
+            let hash_match = captures.get(1)?;
+            let tag_match = captures.get(2)?;
+            let tag = tag_match.as_str().trim_end_matches(is_punctuation);
+            // 数字や記号だけのもの(#1など)はハッシュタグにしない
+            if tag.starts_with('\u{FE0F}')
+                || !tag.chars().any(|c| !c.is_numeric() && !is_punctuation(c))
+                || grapheme_len(tag) > MAX_TAG_GRAPHEMES
+                || tag.len() > MAX_TAG_BYTES
+            {
+                return None;
+            }
+            Some(Facet {
+                index: ByteSlice {
+                    byte_start: hash_match.start(),
+                    byte_end: tag_match.start() + tag.len(),
+                },
+                features: vec![FacetFeature::Tag {
+                    tag: tag.to_string(),
+                }],
+            })
+        })
+        .collect()
+}
+
+// リンクとハッシュタグのファセットを本文の順に返す
+pub fn detect_facets(text: &str, max_link_facets: usize) -> Vec<Facet> {
+    let mut facets = detect_link_facets(text, max_link_facets);
+    facets.extend(detect_tag_facets(text));
+    facets.sort_by_key(|facet| facet.index.byte_start);
+    facets
+}
+
+// 末尾のURLは途中で切るとリンクが壊れるため、手前の文章を切り詰めてURLを丸ごと残す
+// ファセットは切り詰めた後の本文から検出し直すことで位置が合う
+pub fn truncate_preserving_trailing_link(text: &str, max_graphemes: usize) -> String {
+    if grapheme_len(text) <= max_graphemes {
+        return text.to_string();
+    }
+    let trailing_facet = detect_link_facets(text, usize::MAX).pop().filter(|facet| {
+        text[facet.index.byte_end..]
+            .trim()
+            .chars()
+            .all(|c| TRAILING_PUNCTUATION.contains(&c))
+    });
+    let Some(trailing_facet) = trailing_facet else {
+        return truncate_graphemes(text, max_graphemes);
+    };
+    let (preceding, trailing) = text.split_at(trailing_facet.index.byte_start);
+    let trailing = trailing.trim_end();
+    let preceding_text = preceding.trim_end();
+    // URLの直前の空白や改行はそのまま残す
+    let separator = &preceding[preceding_text.len()..];
+    let reserved = grapheme_len(trailing) + grapheme_len(separator);
+    // URLだけで上限に達する場合は通常どおり切り詰める
+    if preceding_text.is_empty() || reserved >= max_graphemes {
+        return truncate_graphemes(text, max_graphemes);
+    }
+    format!(
+        "{}{}{}",
+        truncate_graphemes(preceding_text, max_graphemes - reserved),
+        separator,
+        trailing
+    )
+}
+
+// フッターは省略せず、本文を切り詰めてフッターの分の長さを確保する
+pub fn append_footer(text: &str, footer: &str, max_graphemes: usize) -> String {
+    if text.is_empty() {
+        return truncate_graphemes(footer, max_graphemes);
+    }
+    let separator = "\n";
+    let reserved = grapheme_len(footer) + grapheme_len(separator);
+    if reserved >= max_graphemes {
+        return truncate_graphemes(footer, max_graphemes);
+    }
+    let text = truncate_preserving_trailing_link(text, max_graphemes - reserved);
+    format!("{}{}{}", text, separator, footer)
+}
+
+// サイト名などの接尾辞は省略せず、前の本文を切り詰めて上限に収める
+pub fn append_suffix(text: &str, suffix: &str, max_graphemes: usize) -> String {
+    let reserved = grapheme_len(suffix);
+    // 接尾辞だけで上限に達する場合は全体を切り詰める
+    if reserved >= max_graphemes {
+        return truncate_graphemes(&format!("{}{}", text, suffix), max_graphemes);
+    }
+    format!(
+        "{}{}",
+        truncate_preserving_trailing_link(text, max_graphemes - reserved),
+        suffix
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::MAX_POST_GRAPHEMES;
+
+    fn facet_text<'a>(text: &'a str, facet: &Facet) -> &'a str {
+        &text[facet.index.byte_start..facet.index.byte_end]
+    }
+
+    #[test]
+    fn test_detect_link_facets() {
+        let text = "Rust 1.76.0をリリースしました。詳細は https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html を参照。変更点: https://github.com/rust-lang/rust/blob/master/RELEASES.md, (https://doc.rust-lang.org/)";
+        let facets = detect_link_facets(text, 10);
+        assert_eq!(facets.len(), 3);
+        assert_eq!(
+            facet_text(text, &facets[0]),
+            "https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html"
+        );
+        assert_eq!(
+            facet_text(text, &facets[1]),
+            "https://github.com/rust-lang/rust/blob/master/RELEASES.md"
+        );
+        assert_eq!(facet_text(text, &facets[2]), "https://doc.rust-lang.org/");
+        assert_eq!(
+            facets[1].features,
+            vec![FacetFeature::Link {
+                uri: "https://github.com/rust-lang/rust/blob/master/RELEASES.md".to_string()
+            }]
+        );
+        // 日本語(3バイト文字)の後ろでもバイト単位の位置になっている
+        assert_eq!(
+            facets[0].index.byte_start,
+            "Rust 1.76.0をリリースしました。詳細は ".len()
+        );
+    }
+
+    #[test]
+    fn test_facet_serialization_matches_lexicon() {
+        // atprotoのドキュメントにあるリッチテキストの例
+        let text = "✨ example mentioning @atproto.com to share the URL 👨‍❤️‍👨 https://en.wikipedia.org/wiki/CBOR.";
+        let json = r#"[{"index":{"byteStart":23,"byteEnd":35},"features":[{"$type":"app.bsky.richtext.facet#mention","did":"did:plc:ewvi7nxzyoun6zhxrhs64oiz"}]},{"index":{"byteStart":74,"byteEnd":108},"features":[{"$type":"app.bsky.richtext.facet#link","uri":"https://en.wikipedia.org/wiki/CBOR"}]}]"#;
+        let facets = vec![
+            Facet {
+                index: ByteSlice {
+                    byte_start: 23,
+                    byte_end: 35,
+                },
+                features: vec![FacetFeature::Mention {
+                    did: "did:plc:ewvi7nxzyoun6zhxrhs64oiz".to_string(),
+                }],
+            },
+            Facet {
+                index: ByteSlice {
+                    byte_start: 74,
+                    byte_end: 108,
+                },
+                features: vec![FacetFeature::Link {
+                    uri: "https://en.wikipedia.org/wiki/CBOR".to_string(),
+                }],
+            },
+        ];
+        assert_eq!(serde_json::to_string(&facets).unwrap(), json);
+        let parsed: Vec<Facet> = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed, facets);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        assert_eq!(facet_text(text, &facets[0]), "@atproto.com");
+        assert_eq!(
+            facet_text(text, &facets[1]),
+            "https://en.wikipedia.org/wiki/CBOR"
+        );
+        // 自動で検出したリンクも同じ形式になる
+        assert_eq!(detect_link_facets(text, 10)[0], facets[1]);
+
+        let json = r##"{"index":{"byteStart":0,"byteEnd":8},"features":[{"$type":"app.bsky.richtext.facet#tag","tag":"atproto"}]}"##;
+        let facet = Facet {
+            index: ByteSlice {
+                byte_start: 0,
+                byte_end: 8,
+            },
+            features: vec![FacetFeature::Tag {
+                tag: "atproto".to_string(),
+            }],
+        };
+        assert_eq!(serde_json::to_string(&facet).unwrap(), json);
+        assert_eq!(serde_json::from_str::<Facet>(json).unwrap(), facet);
+    }
+
+    #[test]
+    fn test_detect_link_facets_max() {
+        let text = "http://a.example.com http://b.example.com http://c.example.com";
+        let facets = detect_link_facets(text, 2);
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facet_text(text, &facets[1]), "http://b.example.com");
+        assert!(detect_link_facets(text, 0).is_empty());
+        assert!(detect_link_facets("no links here, just https://", 10).is_empty());
+    }
+
+    #[test]
+    fn test_detect_tag_facets() {
+        let text = "#Rust 1.76.0をリリース ＃日本語タグ。 詳細は#リンク先 https://example.com/#fragment (#1 #2024年) #";
+        let facets = detect_tag_facets(text);
+        let tags = facets
+            .iter()
+            .map(|facet| match &facet.features[..] {
+                [FacetFeature::Tag { tag }] => tag.as_str(),
+                _ => panic!("unexpected features: {:?}", facet.features),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["Rust", "日本語タグ", "2024年"]);
+        // 位置は#を含むUTF-8のバイト単位で、末尾の句読点は含めない
+        assert_eq!(facet_text(text, &facets[0]), "#Rust");
+        assert_eq!(facet_text(text, &facets[1]), "＃日本語タグ");
+        assert_eq!(facets[1].index.byte_start, "#Rust 1.76.0をリリース ".len());
+        assert_eq!(facet_text(text, &facets[2]), "#2024年");
+
+        // 絵文字や結合文字を含むタグも書記素単位で長さを数える
+        let text = "🦀 #e\u{301}te\u{301} #日本🇯🇵👍🏽";
+        let facets = detect_tag_facets(text);
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facet_text(text, &facets[0]), "#e\u{301}te\u{301}");
+        assert_eq!(facet_text(text, &facets[1]), "#日本🇯🇵👍🏽");
+        assert_eq!(facets[1].index.byte_end, text.len());
+
+        let tag = "🇯🇵".repeat(MAX_TAG_GRAPHEMES);
+        assert_eq!(detect_tag_facets(&format!("#{}", tag)).len(), 1);
+        assert!(detect_tag_facets(&format!("#{}あ", tag)).is_empty());
+    }
+
+    #[test]
+    fn test_detect_facets() {
+        let text = "新しいリリース https://example.com/a #Rust https://example.com/b";
+        let facets = detect_facets(text, 1);
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facet_text(text, &facets[0]), "https://example.com/a");
+        assert_eq!(facet_text(text, &facets[1]), "#Rust");
+        // リンクを付けない設定でもハッシュタグは付ける
+        let facets = detect_facets(text, 0);
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facet_text(text, &facets[0]), "#Rust");
+    }
+
+    #[test]
+    fn test_truncate_preserving_trailing_link() {
+        let url = "https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html";
+        let text = format!("{}{}", "Rust 1.76.0 release notes ".repeat(20), url);
+        let truncated = truncate_preserving_trailing_link(&text, 100);
+        assert_eq!(grapheme_len(&truncated), 100);
+        assert!(truncated.ends_with(&format!("… {}", url)));
+        // 切り詰めた後の本文のファセットがURL全体を指す
+        let facets = detect_link_facets(&truncated, 10);
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facet_text(&truncated, &facets[0]), url);
+        assert_eq!(facets[0].index.byte_end, truncated.len());
+        assert_eq!(
+            facets[0].features,
+            vec![FacetFeature::Link {
+                uri: url.to_string()
+            }]
+        );
+
+        // 改行や末尾の句読点はそのまま残す
+        let text = format!("{}\n{}.", "あ".repeat(120), url);
+        let truncated = truncate_preserving_trailing_link(&text, 100);
+        assert_eq!(grapheme_len(&truncated), 100);
+        assert!(truncated.ends_with(&format!("…\n{}.", url)));
+        // 上限以内、末尾がURLでない、URLだけで上限を超える場合は通常どおり
+        assert_eq!(truncate_preserving_trailing_link(&text, 300), text);
+        let text = format!("{} and more text", url);
+        assert_eq!(
+            truncate_preserving_trailing_link(&text, 20),
+            truncate_graphemes(&text, 20)
+        );
+        let text = format!("Release {}", url);
+        assert_eq!(
+            truncate_preserving_trailing_link(&text, 20),
+            truncate_graphemes(&text, 20)
+        );
+    }
+
+    #[test]
+    fn test_append_suffix() {
+        assert_eq!(
+            append_suffix("Entry", " | Blog", MAX_POST_GRAPHEMES),
+            "Entry | Blog"
+        );
+        // サイト名ではなくエントリーのタイトルを切り詰める
+        let text = append_suffix(
+            &"Long entry title ".repeat(30),
+            " | Blog",
+            MAX_POST_GRAPHEMES,
+        );
+        assert_eq!(grapheme_len(&text), MAX_POST_GRAPHEMES);
+        assert!(text.ends_with("… | Blog"));
+        // 絵文字や結合文字は1文字として数え、途中で分割しない
+        let title = "👨‍👩‍👧‍👦e\u{301}".repeat(10);
+        let text = append_suffix(&title, " | ブログ", 15);
+        assert_eq!(grapheme_len(&text), 15);
+        assert_eq!(text, format!("{}… | ブログ", "👨‍👩‍👧‍👦e\u{301}".repeat(4)));
+        // サイト名だけで上限に達する場合は全体を切り詰める
+        let text = append_suffix(
+            "Entry",
+            &format!(" | {}", "長いサイト名".repeat(60)),
+            MAX_POST_GRAPHEMES,
+        );
+        assert_eq!(grapheme_len(&text), MAX_POST_GRAPHEMES);
+        assert!(text.starts_with("Entry | "));
+        assert!(text.ends_with('…'));
+    }
+
+    #[test]
+    fn test_append_footer() {
+        assert_eq!(
+            append_footer("Entry | Blog", "🤖 auto-posted", MAX_POST_GRAPHEMES),
+            "Entry | Blog\n🤖 auto-posted"
+        );
+        assert_eq!(
+            append_footer("", "🤖 auto-posted", MAX_POST_GRAPHEMES),
+            "🤖 auto-posted"
+        );
+    }
+
+    #[test]
+    fn test_append_footer_truncates_text() {
+        let footer = "🤖 auto-posted";
+        let text = append_footer(&"a".repeat(300), footer, MAX_POST_GRAPHEMES);
+        assert_eq!(grapheme_len(&text), MAX_POST_GRAPHEMES);
+        assert!(text.ends_with(&format!("…\n{}", footer)));
+    }
+}
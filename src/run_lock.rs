@@ -0,0 +1,242 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, Utc};
+use tracing::warn;
+
+use crate::{dynamodb::CONFIG_TABLE_NAME, OpaqueError};
+
+static RUN_LOCK_KEY: &str = "run_lock";
+
+// 実行が重なった場合に同じエントリーを二重に投稿しないためのロック
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunLock {
+    pub owner: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub trait RunLockStore {
+    // ロックがないか期限切れの場合のみ書き込み、書き込めたかを返す
+    async fn put_lock_if_available(
+        &self,
+        run_lock: &RunLock,
+        now: DateTime<Utc>,
+    ) -> Result<bool, OpaqueError>;
+    // 自分が持っているロックのみ削除する
+    async fn delete_lock(&self, owner: &str) -> Result<(), OpaqueError>;
+}
+
+pub struct DynamoDbRunLockStore<'a> {
+    pub client: &'a aws_sdk_dynamodb::Client,
+}
+
+impl RunLockStore for DynamoDbRunLockStore<'_> {
+    async fn put_lock_if_available(
+        &self,
+        run_lock: &RunLock,
+        now: DateTime<Utc>,
+    ) -> Result<bool, OpaqueError> {
+        // TTLによる削除は遅れることがあるため、期限切れのロックは条件で上書きする
+        let result = self
+            .client
+            .put_item()
+            .table_name(CONFIG_TABLE_NAME)
+            .item("key", AttributeValue::S(RUN_LOCK_KEY.to_string()))
+            .item("owner", AttributeValue::S(run_lock.owner.clone()))
+            .item(
+                "expires_at",
+                AttributeValue::N(run_lock.expires_at.timestamp().to_string()),
+            )
+            .condition_expression("attribute_not_exists(#key) OR expires_at <= :now")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_values(":now", AttributeValue::N(now.timestamp().to_string()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete_lock(&self, owner: &str) -> Result<(), OpaqueError> {
+        let result = self
+            .client
+            .delete_item()
+            .table_name(CONFIG_TABLE_NAME)
+            .key("key", AttributeValue::S(RUN_LOCK_KEY.to_string()))
+            .condition_expression("#owner = :owner")
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_values(":owner", AttributeValue::S(owner.to_string()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            // 期限切れの後に他の実行がロックを取得している
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_conditional_check_failed_exception()) =>
+            {
+                warn!(owner, "Run lock was taken over by another run");
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// ロックを取得できなかった場合はNoneを返す
+pub async fn acquire_run_lock(
+    store: &impl RunLockStore,
+    owner: &str,
+    now: DateTime<Utc>,
+    ttl_seconds: i64,
+) -> Result<Option<RunLock>, OpaqueError> {
+    let run_lock = RunLock {
+        owner: owner.to_string(),
+        expires_at: now + Duration::seconds(ttl_seconds),
+    };
+    if store.put_lock_if_available(&run_lock, now).await? {
+        Ok(Some(run_lock))
+    } else {
+        Ok(None)
+    }
+}
+
+pub async fn release_run_lock(
+    store: &impl RunLockStore,
+    run_lock: &RunLock,
+) -> Result<(), OpaqueError> {
+    store.delete_lock(&run_lock.owner).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // put_lock_if_availableの条件式と同じ判定
+    fn is_run_lock_available(existing: Option<&RunLock>, now: DateTime<Utc>) -> bool {
+        match existing {
+            Some(existing) => existing.expires_at <= now,
+            None => true,
+        }
+    }
+
+    // DynamoDBの条件付き書き込みと同じ振る舞いをするストア
+    #[derive(Default)]
+    struct MockRunLockStore {
+        run_lock: Mutex<Option<RunLock>>,
+    }
+
+    impl RunLockStore for MockRunLockStore {
+        async fn put_lock_if_available(
+            &self,
+            run_lock: &RunLock,
+            now: DateTime<Utc>,
+        ) -> Result<bool, OpaqueError> {
+            let mut existing = self.run_lock.lock().unwrap();
+            if !is_run_lock_available(existing.as_ref(), now) {
+                return Ok(false);
+            }
+            *existing = Some(run_lock.clone());
+            Ok(true)
+        }
+
+        async fn delete_lock(&self, owner: &str) -> Result<(), OpaqueError> {
+            let mut existing = self.run_lock.lock().unwrap();
+            if existing
+                .as_ref()
+                .is_some_and(|run_lock| run_lock.owner == owner)
+            {
+                *existing = None;
+            }
+            Ok(())
+        }
+    }
+
+    fn datetime(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[tokio::test]
+    async fn test_acquire_run_lock() {
+        let store = MockRunLockStore::default();
+        let now = datetime("2024-02-10T00:00:00Z");
+        let run_lock = acquire_run_lock(&store, "run-1", now, 900)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(run_lock.owner, "run-1");
+        assert_eq!(run_lock.expires_at, datetime("2024-02-10T00:15:00Z"));
+        release_run_lock(&store, &run_lock).await.unwrap();
+        assert!(store.run_lock.lock().unwrap().is_none());
+        // 解放した後は次の実行が取得できる
+        assert!(acquire_run_lock(&store, "run-2", now, 900)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_run_lock_contention() {
+        let store = MockRunLockStore::default();
+        let now = datetime("2024-02-10T00:00:00Z");
+        let run_lock = acquire_run_lock(&store, "run-1", now, 900)
+            .await
+            .unwrap()
+            .unwrap();
+        // 実行中に始まった次の実行は取得できずに終了する
+        let later = now + Duration::minutes(5);
+        assert!(acquire_run_lock(&store, "run-2", later, 900)
+            .await
+            .unwrap()
+            .is_none());
+        // 取得できなかった実行の解放で他の実行のロックを消さない
+        store.delete_lock("run-2").await.unwrap();
+        assert_eq!(store.run_lock.lock().unwrap().as_ref(), Some(&run_lock));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_run_lock_recovers_stale_lock() {
+        let store = MockRunLockStore::default();
+        let now = datetime("2024-02-10T00:00:00Z");
+        // 解放せずに異常終了した実行のロック
+        let stale_lock = acquire_run_lock(&store, "run-1", now, 900)
+            .await
+            .unwrap()
+            .unwrap();
+        let later = now + Duration::minutes(20);
+        let run_lock = acquire_run_lock(&store, "run-2", later, 900)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(run_lock.owner, "run-2");
+        // 期限切れのロックの持ち主が後から解放しても新しいロックは残る
+        release_run_lock(&store, &stale_lock).await.unwrap();
+        assert_eq!(store.run_lock.lock().unwrap().as_ref(), Some(&run_lock));
+    }
+
+    #[test]
+    fn test_is_run_lock_available() {
+        let now = datetime("2024-02-10T00:00:00Z");
+        assert!(is_run_lock_available(None, now));
+        let run_lock = RunLock {
+            owner: "run-1".to_string(),
+            expires_at: now,
+        };
+        assert!(is_run_lock_available(Some(&run_lock), now));
+        assert!(!is_run_lock_available(
+            Some(&run_lock),
+            now - Duration::seconds(1)
+        ));
+    }
+}
@@ -0,0 +1,187 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+use crate::{
+    dynamodb::{get_cached_session, put_cached_session},
+    OpaqueError,
+};
+
+// 実行中に期限が切れないよう、Lambdaの最大実行時間より長く残っているトークンだけを使い回す
+const SESSION_EXPIRY_MARGIN_MINUTES: i64 = 15;
+
+// 起動のたびにcreateSessionを呼んで認証のレート制限に達しないよう、実行をまたいで保存するセッション
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedSession {
+    // 別のアカウントのセッションを使わないよう、ログインに使った識別子も保存する
+    pub identifier: String,
+    pub access_jwt: String,
+    pub refresh_jwt: String,
+    pub handle: String,
+    pub did: String,
+    pub service_url: String,
+}
+
+pub trait SessionStore {
+    async fn get_cached_session(&self) -> Result<Option<CachedSession>, OpaqueError>;
+    async fn put_cached_session(&self, cached_session: &CachedSession) -> Result<(), OpaqueError>;
+}
+
+pub struct DynamoDbSessionStore<'a> {
+    pub client: &'a aws_sdk_dynamodb::Client,
+}
+
+impl SessionStore for DynamoDbSessionStore<'_> {
+    async fn get_cached_session(&self) -> Result<Option<CachedSession>, OpaqueError> {
+        get_cached_session(self.client).await
+    }
+
+    async fn put_cached_session(&self, cached_session: &CachedSession) -> Result<(), OpaqueError> {
+        put_cached_session(self.client, cached_session).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionAction {
+    Reuse,
+    Refresh,
+    Create,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    exp: i64,
+}
+
+// 署名は検証せず、期限を判断するためにexpだけを読み取る
+pub fn jwt_expires_at(jwt: &str) -> Option<DateTime<Utc>> {
+    let payload = jwt.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: JwtClaims = serde_json::from_slice(&payload).ok()?;
+    DateTime::from_timestamp(claims.exp, 0)
+}
+
+// 期限を読み取れないトークンは切れているものとして扱う
+fn is_jwt_usable(jwt: &str, now: DateTime<Utc>) -> bool {
+    jwt_expires_at(jwt).is_some_and(|expires_at| {
+        expires_at > now + Duration::minutes(SESSION_EXPIRY_MARGIN_MINUTES)
+    })
+}
+
+pub fn decide_session_action(
+    cached_session: Option<&CachedSession>,
+    now: DateTime<Utc>,
+) -> SessionAction {
+    match cached_session {
+        Some(cached_session) if is_jwt_usable(&cached_session.access_jwt, now) => {
+            SessionAction::Reuse
+        }
+        Some(cached_session) if is_jwt_usable(&cached_session.refresh_jwt, now) => {
+            SessionAction::Refresh
+        }
+        _ => SessionAction::Create,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // 保存先を使わないテスト用のストア。bskyとmainのテストでも使う
+    #[derive(Default)]
+    pub struct MockSessionStore {
+        pub cached_session: std::sync::Mutex<Option<CachedSession>>,
+    }
+
+    impl SessionStore for MockSessionStore {
+        async fn get_cached_session(&self) -> Result<Option<CachedSession>, OpaqueError> {
+            Ok(self.cached_session.lock().unwrap().clone())
+        }
+
+        async fn put_cached_session(
+            &self,
+            cached_session: &CachedSession,
+        ) -> Result<(), OpaqueError> {
+            *self.cached_session.lock().unwrap() = Some(cached_session.clone());
+            Ok(())
+        }
+    }
+
+    fn datetime(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn jwt(expires_at: &str) -> String {
+        let payload = format!(
+            r#"{{"scope":"com.atproto.access","sub":"did:plc:test","exp":{}}}"#,
+            datetime(expires_at).timestamp()
+        );
+        format!(
+            "eyJhbGciOiJFUzI1NksifQ.{}.signature",
+            URL_SAFE_NO_PAD.encode(payload)
+        )
+    }
+
+    fn cached_session(access_expires_at: &str, refresh_expires_at: &str) -> CachedSession {
+        CachedSession {
+            identifier: "bot.example.com".to_string(),
+            access_jwt: jwt(access_expires_at),
+            refresh_jwt: jwt(refresh_expires_at),
+            handle: "bot.example.com".to_string(),
+            did: "did:plc:test".to_string(),
+            service_url: "https://bsky.social".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_jwt_expires_at() {
+        assert_eq!(
+            jwt_expires_at(&jwt("2024-02-10T02:00:00Z")),
+            Some(datetime("2024-02-10T02:00:00Z"))
+        );
+        assert_eq!(jwt_expires_at("not a jwt"), None);
+        assert_eq!(jwt_expires_at("header.not-base64!.signature"), None);
+        let without_exp = format!("header.{}.signature", URL_SAFE_NO_PAD.encode("{}"));
+        assert_eq!(jwt_expires_at(&without_exp), None);
+    }
+
+    #[test]
+    fn test_decide_session_action() {
+        let now = datetime("2024-02-10T00:00:00Z");
+        assert_eq!(decide_session_action(None, now), SessionAction::Create);
+        // アクセストークンが十分に残っていればそのまま使う
+        let fresh = cached_session("2024-02-10T02:00:00Z", "2024-05-10T00:00:00Z");
+        assert_eq!(
+            decide_session_action(Some(&fresh), now),
+            SessionAction::Reuse
+        );
+        // 実行中に切れそうなアクセストークンは先に更新する
+        let expiring = cached_session("2024-02-10T00:10:00Z", "2024-05-10T00:00:00Z");
+        assert_eq!(
+            decide_session_action(Some(&expiring), now),
+            SessionAction::Refresh
+        );
+        let access_expired = cached_session("2024-02-09T22:00:00Z", "2024-05-10T00:00:00Z");
+        assert_eq!(
+            decide_session_action(Some(&access_expired), now),
+            SessionAction::Refresh
+        );
+        // リフレッシュトークンも切れている場合はログインし直す
+        let refresh_expired = cached_session("2024-02-09T22:00:00Z", "2024-02-09T23:00:00Z");
+        assert_eq!(
+            decide_session_action(Some(&refresh_expired), now),
+            SessionAction::Create
+        );
+        let unreadable = CachedSession {
+            access_jwt: "access".to_string(),
+            refresh_jwt: "refresh".to_string(),
+            ..fresh
+        };
+        assert_eq!(
+            decide_session_action(Some(&unreadable), now),
+            SessionAction::Create
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use tracing::warn;
+use unicode_properties::{emoji::EmojiStatus, UnicodeEmoji};
+use unicode_segmentation::UnicodeSegmentation;
+
+// Blueskyの投稿本文の上限(grapheme数)
+pub const MAX_POST_GRAPHEMES: usize = 300;
+
+const ELLIPSIS: &str = "…";
+
+pub fn grapheme_len(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+// 上限を超える場合は末尾を省略記号に置き換えて、省略記号を含めてmax_graphemes以内に収める
+pub fn truncate_graphemes(text: &str, max_graphemes: usize) -> String {
+    if grapheme_len(text) <= max_graphemes {
+        return text.to_string();
+    }
+    if max_graphemes == 0 {
+        return "".to_string();
+    }
+    let truncated: String = text.graphemes(true).take(max_graphemes - 1).collect();
+    format!("{}{}", truncated.trim_end(), ELLIPSIS)
+}
+
+// フィードやOGPに残っている文字参照を1回だけ展開する
+// "&amp;lt;"は"&lt;"になり、"<"まで展開しない。不明な参照はそのまま残す
+pub fn decode_html_entities(text: &str) -> String {
+    html_escape::decode_html_entities(text).into_owned()
+}
+
+// 絵文字として表示される書記素クラスタを取り除き、残った空白を詰める
+// ©や™などテキストとして表示される記号は残す
+pub fn strip_emoji(text: &str) -> String {
+    let stripped = text
+        .graphemes(true)
+        .filter(|grapheme| !is_emoji_grapheme(grapheme))
+        .collect::<String>();
+    stripped.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// 投稿本文のエントリーのタイトルの大文字・小文字の変換
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TextCase {
+    #[default]
+    Unchanged,
+    Lower,
+    Title,
+    Sentence,
+}
+
+// 不明な値は変換しない
+pub fn parse_text_case(value: &str) -> TextCase {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "none" => TextCase::Unchanged,
+        "lower" => TextCase::Lower,
+        "title" => TextCase::Title,
+        "sentence" => TextCase::Sentence,
+        _ => {
+            warn!(value, "Ignored unknown text_case");
+            TextCase::Unchanged
+        }
+    }
+}
+
+// 英語のタイトルケースで先頭と末尾以外は小文字のままにする語
+const TITLE_CASE_SMALL_WORDS: [&str; 24] = [
+    "a", "an", "and", "as", "at", "but", "by", "en", "for", "if", "in", "nor", "of", "on", "or",
+    "per", "the", "to", "v", "vs", "via", "with", "from", "into",
+];
+
+// langはBCP47の言語タグ。言語によって大文字・小文字の対応や単語の扱いを変える
+pub fn apply_text_case(text: &str, text_case: TextCase, lang: Option<&str>) -> String {
+    let primary_lang = lang
+        .and_then(|lang| lang.split(['-', '_']).next())
+        .map(|lang| lang.trim().to_ascii_lowercase());
+    let lang = primary_lang.as_deref();
+    match text_case {
+        TextCase::Unchanged => text.to_string(),
+        TextCase::Lower => map_words(text, |word, _| lowercase(word, lang)),
+        TextCase::Sentence => to_sentence_case(text, lang),
+        // タイトルケースの慣習がない言語では文頭だけを大文字にする
+        TextCase::Title if lang.is_some_and(|lang| lang != "en") => to_sentence_case(text, lang),
+        TextCase::Title => to_title_case(text, lang),
+    }
+}
+
+fn to_sentence_case(text: &str, lang: Option<&str>) -> String {
+    let mut sentence_start = true;
+    map_words(text, |word, _| {
+        let word = lowercase(word, lang);
+        let word = if sentence_start {
+            capitalize(&word, lang)
+        } else {
+            word
+        };
+        // 記号だけの語では文頭の判定を変えない
+        if word.chars().any(char::is_alphanumeric) {
+            sentence_start = word.ends_with(['.', '!', '?']);
+        }
+        word
+    })
+}
+
+fn to_title_case(text: &str, lang: Option<&str>) -> String {
+    let word_count = text.split_whitespace().count();
+    let mut after_colon = false;
+    map_words(text, |word, index| {
+        let word = lowercase(word, lang);
+        let is_small_word =
+            TITLE_CASE_SMALL_WORDS.contains(&word.trim_matches(|c: char| !c.is_alphanumeric()));
+        let word = if is_small_word && index != 0 && index + 1 != word_count && !after_colon {
+            word
+        } else {
+            capitalize(&word, lang)
+        };
+        after_colon = word.ends_with(':');
+        word
+    })
+}
+
+// 空白で区切った語ごとに変換し、空白はそのまま残す
+// URLは大文字・小文字を変えるとリンク先が変わるため変換しない
+fn map_words(text: &str, mut f: impl FnMut(&str, usize) -> String) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut index = 0;
+    for token in text.split_inclusive(char::is_whitespace) {
+        let word = token.trim_end_matches(char::is_whitespace);
+        if word.is_empty() {
+            result.push_str(token);
+            continue;
+        }
+        if is_url_like(word) {
+            result.push_str(word);
+        } else {
+            result.push_str(&f(word, index));
+        }
+        result.push_str(&token[word.len()..]);
+        index += 1;
+    }
+    result
+}
+
+fn is_url_like(word: &str) -> bool {
+    word.contains("://") || word.starts_with("www.")
+}
+
+// トルコ語などではIとiが点の有無で別の文字に対応する
+fn is_dotted_i_lang(lang: Option<&str>) -> bool {
+    matches!(lang, Some("tr" | "az"))
+}
+
+fn lowercase(word: &str, lang: Option<&str>) -> String {
+    if is_dotted_i_lang(lang) {
+        word.replace('I', "ı").replace('İ', "i").to_lowercase()
+    } else {
+        word.to_lowercase()
+    }
+}
+
+// 最初の文字だけを大文字にする
+fn capitalize(word: &str, lang: Option<&str>) -> String {
+    let Some((index, first)) = word.char_indices().find(|(_, c)| c.is_alphabetic()) else {
+        return word.to_string();
+    };
+    let upper = if first == 'i' && is_dotted_i_lang(lang) {
+        "İ".to_string()
+    } else {
+        first.to_uppercase().collect()
+    };
+    format!(
+        "{}{}{}",
+        &word[..index],
+        upper,
+        &word[index + first.len_utf8()..]
+    )
+}
+
+fn is_emoji_grapheme(grapheme: &str) -> bool {
+    let Some(first) = grapheme.chars().next() else {
+        return false;
+    };
+    if first.is_emoji_char() && grapheme.contains('\u{FE0F}') {
+        return true;
+    }
+    matches!(
+        first.emoji_status(),
+        EmojiStatus::EmojiPresentation
+            | EmojiStatus::EmojiPresentationAndModifierBase
+            | EmojiStatus::EmojiPresentationAndEmojiComponent
+            | EmojiStatus::EmojiPresentationAndModifierAndEmojiComponent
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_text_case() {
+        let title = "BREAKING: THE RUST TEAM ANNOUNCES A NEW RELEASE OF CARGO";
+        assert_eq!(
+            apply_text_case(title, TextCase::Unchanged, Some("en")),
+            title
+        );
+        assert_eq!(
+            apply_text_case(title, TextCase::Lower, Some("en")),
+            "breaking: the rust team announces a new release of cargo"
+        );
+        // 小さい語は先頭と末尾、コロンの直後以外は小文字のまま
+        assert_eq!(
+            apply_text_case(title, TextCase::Title, Some("en-US")),
+            "Breaking: The Rust Team Announces a New Release of Cargo"
+        );
+        assert_eq!(
+            apply_text_case("WHAT IS IT FOR", TextCase::Title, None),
+            "What Is It For"
+        );
+        assert_eq!(
+            apply_text_case(title, TextCase::Sentence, Some("en")),
+            "Breaking: the rust team announces a new release of cargo"
+        );
+        assert_eq!(
+            apply_text_case("IT SHIPPED. NOW WHAT? 2 FIXES!", TextCase::Sentence, None),
+            "It shipped. Now what? 2 fixes!"
+        );
+    }
+
+    #[test]
+    fn test_apply_text_case_by_language() {
+        // タイトルケースの慣習がない言語では文頭だけを大文字にする
+        assert_eq!(
+            apply_text_case("NOUVELLE VERSION DE RUST", TextCase::Title, Some("fr")),
+            "Nouvelle version de rust"
+        );
+        // トルコ語の点のあるiとないı
+        assert_eq!(
+            apply_text_case("İSTANBUL IŞIKLARI", TextCase::Lower, Some("tr")),
+            "istanbul ışıkları"
+        );
+        assert_eq!(
+            apply_text_case("İSTANBUL IŞIKLARI", TextCase::Sentence, Some("tr")),
+            "İstanbul ışıkları"
+        );
+        // 大文字・小文字のない文字やURL、空白はそのまま残す
+        assert_eq!(
+            apply_text_case(
+                "RUST 1.76.0 リリース  https://Example.com/News",
+                TextCase::Lower,
+                Some("ja")
+            ),
+            "rust 1.76.0 リリース  https://Example.com/News"
+        );
+    }
+
+    #[test]
+    fn test_parse_text_case() {
+        assert_eq!(parse_text_case(""), TextCase::Unchanged);
+        assert_eq!(parse_text_case("none"), TextCase::Unchanged);
+        assert_eq!(parse_text_case("lower"), TextCase::Lower);
+        assert_eq!(parse_text_case(" Title "), TextCase::Title);
+        assert_eq!(parse_text_case("sentence"), TextCase::Sentence);
+        assert_eq!(parse_text_case("upper"), TextCase::Unchanged);
+    }
+
+    #[test]
+    fn test_decode_html_entities() {
+        assert_eq!(
+            decode_html_entities("Rust &amp; WebAssembly"),
+            "Rust & WebAssembly"
+        );
+        assert_eq!(
+            decode_html_entities("It&#39;s &quot;fast&quot; &mdash; really&hellip;"),
+            "It's \"fast\" — really…"
+        );
+        assert_eq!(
+            decode_html_entities("&#x1F980; &#X2014; &#8212; &#12354;"),
+            "🦀 — — あ"
+        );
+        // HTMLで定義されているすべての名前付き文字参照を展開する
+        assert_eq!(
+            decode_html_entities("Caf&eacute; &uuml;ber Fran&ccedil;ais &Aring; &szlig;"),
+            "Café über Français Å ß"
+        );
+        // 1回だけ展開し、二重に展開しない
+        assert_eq!(decode_html_entities("&amp;lt;tag&amp;gt;"), "&lt;tag&gt;");
+        // 文字参照を含まないテキストは変わらない
+        let decoded = decode_html_entities("Rust & WebAssembly <3");
+        assert_eq!(decoded, "Rust & WebAssembly <3");
+        assert_eq!(decode_html_entities(&decoded), decoded);
+        // 不明なものや範囲外のもの、セミコロンのないものはそのまま残す
+        assert_eq!(
+            decode_html_entities("&unknown; &#0; &#xD800; &#99999999; &amp"),
+            "&unknown; &#0; &#xD800; &#99999999; &amp"
+        );
+    }
+
+    #[test]
+    fn test_strip_emoji() {
+        assert_eq!(
+            strip_emoji("🎉 Rust 1.76.0 リリース 🦀🚀"),
+            "Rust 1.76.0 リリース"
+        );
+        // 異体字セレクタ、肌の色、ZWJ、国旗の絵文字も1つの絵文字として取り除く
+        assert_eq!(
+            strip_emoji("❤️ Thanks 👍🏽 from 👨‍👩‍👧 in 🇯🇵!"),
+            "Thanks from in !"
+        );
+        assert_eq!(strip_emoji("Rust© 2024™ #1 of 10"), "Rust© 2024™ #1 of 10");
+        assert_eq!(strip_emoji("🦀"), "");
+    }
+
+    #[test]
+    fn test_truncate_graphemes() {
+        assert_eq!(truncate_graphemes("hello", 5), "hello");
+        assert_eq!(truncate_graphemes("hello world", 6), "hello…");
+        assert_eq!(truncate_graphemes("👨‍👩‍👧‍👦👨‍👩‍👧‍👦👨‍👩‍👧‍👦", 2), "👨‍👩‍👧‍👦…");
+        assert_eq!(
+            grapheme_len(&truncate_graphemes(&"あ".repeat(400), 300)),
+            300
+        );
+    }
+}
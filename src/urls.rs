@@ -0,0 +1,259 @@
+use url::Url;
+
+// example.comを指定した場合はwww.example.comなどのサブドメインも対象にする
+pub fn host_matches_domains(url: &str, domains: &[String]) -> bool {
+    let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_lowercase()))
+    else {
+        return false;
+    };
+    domains.iter().any(|domain| {
+        let domain = domain.trim().trim_start_matches('.').to_lowercase();
+        !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+    })
+}
+
+// 記事と同じホスト(サブドメインを含む)か、許可リストのドメインのURLかどうか
+pub fn is_same_origin_or_allowed(url: &str, article_url: &str, allowed_domains: &[String]) -> bool {
+    let article_hosts = Url::parse(article_url)
+        .ok()
+        .and_then(|article_url| article_url.host_str().map(|host| host.to_string()))
+        .into_iter()
+        .collect::<Vec<_>>();
+    host_matches_domains(url, &article_hosts) || host_matches_domains(url, allowed_domains)
+}
+
+// AMPやモバイル版のURLは埋め込みカードが貧弱なため、通常版のURLに書き換える
+pub fn canonicalize_url(url: &str) -> String {
+    let Ok(mut parsed_url) = Url::parse(url) else {
+        return url.to_string();
+    };
+    if let Some(host) = parsed_url.host_str() {
+        if let Some(desktop_host) = host
+            .strip_prefix("m.")
+            .filter(|desktop_host| desktop_host.contains('.'))
+        {
+            let desktop_host = desktop_host.to_string();
+            if parsed_url.set_host(Some(&desktop_host)).is_err() {
+                return url.to_string();
+            }
+        }
+    }
+    let mut segments = parsed_url
+        .path_segments()
+        .map(|segments| {
+            segments
+                .map(|segment| segment.to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let has_trailing_slash = segments.len() > 1 && segments.last().is_some_and(String::is_empty);
+    if has_trailing_slash {
+        segments.pop();
+    }
+    let is_amp = |segment: &String| segment.eq_ignore_ascii_case("amp");
+    // 末尾の/ampか先頭の/amp/を取り除く
+    let is_rewritten = if segments.last().is_some_and(is_amp) {
+        segments.pop();
+        true
+    } else if segments.len() > 1 && segments.first().is_some_and(is_amp) {
+        segments.remove(0);
+        true
+    } else {
+        false
+    };
+    if is_rewritten {
+        let mut path = format!("/{}", segments.join("/"));
+        if has_trailing_slash && !segments.is_empty() {
+            path.push('/');
+        }
+        parsed_url.set_path(&path);
+    }
+    parsed_url.to_string()
+}
+
+// 末尾のスラッシュ、既定のポート、フラグメントの違いを無視して比較するためのキー
+pub fn normalize_dedup_url(url: &str) -> String {
+    let Ok(mut parsed_url) = Url::parse(url) else {
+        return url.to_string();
+    };
+    // 既定のポートはパースした時点で取り除かれる
+    parsed_url.set_fragment(None);
+    let path = parsed_url.path().trim_end_matches('/').to_string();
+    parsed_url.set_path(&path);
+    parsed_url.to_string()
+}
+
+// 広告やSNSからの流入を計測するためのパラメーター。記事の内容には影響しない
+const TRACKING_QUERY_PARAMS: [&str; 4] = ["fbclid", "gclid", "mc_cid", "mc_eid"];
+
+fn is_tracking_query_param(name: &str) -> bool {
+    name.to_lowercase().starts_with("utm_") || TRACKING_QUERY_PARAMS.contains(&name)
+}
+
+// 別のフィードに同じ記事が計測用のパラメーター付きで載っていても同じURLとして扱うためのキー
+pub fn entry_url_dedup_key(url: &str) -> String {
+    let Ok(mut parsed_url) = Url::parse(&canonicalize_url(url)) else {
+        return url.to_string();
+    };
+    let query_pairs = parsed_url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_query_param(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query_pairs.is_empty() {
+        parsed_url.set_query(None);
+    } else {
+        parsed_url
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(query_pairs);
+    }
+    normalize_dedup_url(parsed_url.as_str())
+}
+
+pub fn parse_domain_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|domain| domain.trim().to_string())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_matches_domains() {
+        let domains = parse_domain_list("slow.example.com, example.org ,");
+        assert_eq!(domains, vec!["slow.example.com", "example.org"]);
+        assert!(host_matches_domains("https://slow.example.com/a", &domains));
+        assert!(host_matches_domains("https://www.example.org/a", &domains));
+        assert!(host_matches_domains("https://EXAMPLE.org/a", &domains));
+        assert!(!host_matches_domains("https://example.com/a", &domains));
+        assert!(!host_matches_domains("https://notexample.org/a", &domains));
+        assert!(!host_matches_domains("not a url", &domains));
+    }
+
+    #[test]
+    fn test_canonicalize_url() {
+        assert_eq!(
+            canonicalize_url("https://example.com/posts/1/amp/"),
+            "https://example.com/posts/1/"
+        );
+        assert_eq!(
+            canonicalize_url("https://example.com/posts/1/amp?utm_source=rss"),
+            "https://example.com/posts/1?utm_source=rss"
+        );
+        assert_eq!(
+            canonicalize_url("https://example.com/amp/posts/1"),
+            "https://example.com/posts/1"
+        );
+        assert_eq!(
+            canonicalize_url("https://m.example.com/posts/1"),
+            "https://example.com/posts/1"
+        );
+        assert_eq!(
+            canonicalize_url("https://m.example.com/amp/s/posts/1"),
+            "https://example.com/s/posts/1"
+        );
+        // ampを含むだけのパスやドメインそのものは書き換えない
+        assert_eq!(
+            canonicalize_url("https://example.com/posts/amplifier"),
+            "https://example.com/posts/amplifier"
+        );
+        assert_eq!(canonicalize_url("https://m.com/amp"), "https://m.com/");
+        assert_eq!(canonicalize_url("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_normalize_dedup_url() {
+        let normalized = normalize_dedup_url("https://example.com/posts/1");
+        assert_eq!(normalized, "https://example.com/posts/1");
+        assert_eq!(
+            normalize_dedup_url("https://example.com/posts/1/"),
+            normalized
+        );
+        assert_eq!(
+            normalize_dedup_url("https://example.com:443/posts/1#comments"),
+            normalized
+        );
+        assert_eq!(
+            normalize_dedup_url("https://example.com/posts/1/?page=2"),
+            "https://example.com/posts/1?page=2"
+        );
+        assert_eq!(
+            normalize_dedup_url("https://example.com"),
+            normalize_dedup_url("https://example.com/")
+        );
+        assert_ne!(
+            normalize_dedup_url("https://example.com:8443/posts/1"),
+            normalized
+        );
+        assert_eq!(normalize_dedup_url("urn:uuid:1234"), "urn:uuid:1234");
+    }
+
+    #[test]
+    fn test_entry_url_dedup_key() {
+        let key = entry_url_dedup_key("https://example.com/posts/1");
+        assert_eq!(key, "https://example.com/posts/1");
+        assert_eq!(
+            entry_url_dedup_key(
+                "https://example.com/posts/1/?utm_source=rss&utm_medium=feed&UTM_Campaign=x"
+            ),
+            key
+        );
+        assert_eq!(
+            entry_url_dedup_key("https://example.com/posts/1?fbclid=abc#comments"),
+            key
+        );
+        assert_eq!(entry_url_dedup_key("https://example.com/posts/1#top"), key);
+        assert_eq!(
+            entry_url_dedup_key("https://m.example.com/posts/1/amp"),
+            key
+        );
+        // 記事を特定するパラメーターは残す
+        assert_eq!(
+            entry_url_dedup_key("https://example.com/?p=1&utm_source=rss"),
+            "https://example.com/?p=1"
+        );
+        assert_ne!(
+            entry_url_dedup_key("https://example.com/?p=2"),
+            "https://example.com/?p=1"
+        );
+        assert_eq!(entry_url_dedup_key("urn:uuid:1234"), "urn:uuid:1234");
+    }
+
+    #[test]
+    fn test_is_same_origin_or_allowed() {
+        let article_url = "https://example.com/posts/1";
+        let allowed_domains = parse_domain_list("cdn.example.net");
+        assert!(is_same_origin_or_allowed(
+            "https://example.com/images/cover.png",
+            article_url,
+            &allowed_domains
+        ));
+        assert!(is_same_origin_or_allowed(
+            "https://static.example.com/cover.png",
+            article_url,
+            &allowed_domains
+        ));
+        assert!(is_same_origin_or_allowed(
+            "https://cdn.example.net/cover.png",
+            article_url,
+            &allowed_domains
+        ));
+        assert!(!is_same_origin_or_allowed(
+            "https://ads.example.org/pixel.gif",
+            article_url,
+            &allowed_domains
+        ));
+        assert!(!is_same_origin_or_allowed(
+            "https://ads.example.org/pixel.gif",
+            "not a url",
+            &[]
+        ));
+    }
+}